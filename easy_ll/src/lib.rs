@@ -13,6 +13,7 @@ use std::sync::{Once, ONCE_INIT};
 use llvm::prelude::{LLVMContextRef, LLVMModuleRef};
 use llvm::execution_engine::{LLVMExecutionEngineRef, LLVMMCJITCompilerOptions};
 use llvm::analysis::LLVMVerifierFailureAction;
+use llvm::target_machine::{LLVMCodeGenFileType, LLVMCodeGenOptLevel, LLVMCodeModel, LLVMRelocMode};
 use llvm::transforms::pass_manager_builder as pmb;
 
 #[cfg(test)]
@@ -28,8 +29,6 @@ pub struct LlvmError(String);
 
 impl LlvmError {
     pub fn new(description: &str) -> LlvmError { LlvmError(description.to_string()) }
-
-    pub fn to_string(self) -> String { self.0 }
 }
 
 impl fmt::Display for LlvmError {
@@ -61,6 +60,9 @@ pub struct CompiledModule {
     function: Option<RunFunc>
 }
 
+// Each module has an LLVM context of its own, which can be used from any one thread at a time.
+unsafe impl Send for CompiledModule {}
+
 impl CompiledModule {
     /// Call the module's `run` function.
     pub fn run(&self, arg: i64) -> i64 {
@@ -84,6 +86,16 @@ impl Drop for CompiledModule {
 /// be executed. The LLVM IR should contain an entry point function called `run` that takes `i64`
 /// and returns `i64`, which will be called by `CompiledModule::run`.
 pub fn compile_module(code: &str) -> Result<CompiledModule, LlvmError> {
+    compile_module_with_opt_level(code, 2)
+}
+
+/// Same as `compile_module`, but optimizes the module at the given level (0 to 3, as in -O0
+/// through -O3) instead of the default of 2.
+pub fn compile_module_with_opt_level(code: &str, opt_level: u32)
+        -> Result<CompiledModule, LlvmError> {
+    if opt_level > 3 {
+        return Err(LlvmError(format!("Invalid optimization level: {}", opt_level)))
+    }
     unsafe {
         // Initialize LLVM
         ONCE.call_once(|| initialize());
@@ -106,10 +118,10 @@ pub fn compile_module(code: &str) -> Result<CompiledModule, LlvmError> {
         // Validate and optimize the module
         try!(verify_module(module));
         try!(check_run_function(module));
-        try!(optimize_module(module));
+        try!(optimize_module(module, opt_level));
 
         // Create an execution engine for the module and find its run function
-        let engine = try!(create_exec_engine(module));
+        let engine = try!(create_exec_engine(module, opt_level));
         result.engine = Some(engine);
         result.function = Some(try!(find_run_function(engine)));
 
@@ -117,6 +129,74 @@ pub fn compile_module(code: &str) -> Result<CompiledModule, LlvmError> {
     }
 }
 
+/// Compile a string of LLVM IR into a native object file at `path` for the host target,
+/// optimizing it at the given level (0 to 3). Like `compile_module`, the IR must define `run`.
+pub fn emit_object_file(code: &str, opt_level: u32, path: &str) -> Result<(), LlvmError> {
+    if opt_level > 3 {
+        return Err(LlvmError(format!("Invalid optimization level: {}", opt_level)))
+    }
+    unsafe {
+        ONCE.call_once(|| initialize());
+        if initialize_failed {
+            return Err(LlvmError::new("LLVM initialization failed"))
+        }
+
+        let context = llvm::core::LLVMContextCreate();
+        if context.is_null() {
+            return Err(LlvmError::new("LLVMContextCreate returned null"))
+        }
+        let result = parse_module(context, code).and_then(|module| {
+            try!(verify_module(module));
+            try!(check_run_function(module));
+            try!(optimize_module(module, opt_level));
+            write_object_file(module, opt_level, path)
+        });
+        llvm::core::LLVMContextDispose(context);
+        result
+    }
+}
+
+/// Generate native code for a module and write it to an object file.
+unsafe fn write_object_file(module: LLVMModuleRef, opt_level: u32, path: &str)
+        -> Result<(), LlvmError> {
+    let path = try!(CString::new(path));
+    let triple = llvm::target_machine::LLVMGetDefaultTargetTriple();
+    let mut target = 0 as llvm::target_machine::LLVMTargetRef;
+    let mut error_str = 0 as *mut c_char;
+    if llvm::target_machine::LLVMGetTargetFromTriple(triple, &mut target, &mut error_str) != 0 {
+        llvm::core::LLVMDisposeMessage(triple);
+        let msg = format!("Finding target failed: {}",
+            CStr::from_ptr(error_str).to_str().unwrap());
+        llvm::core::LLVMDisposeMessage(error_str);
+        return Err(LlvmError(msg));
+    }
+    let level = match opt_level {
+        0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+        1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+        2 => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+        _ => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+    };
+    let empty = CString::new("").unwrap();
+    let machine = llvm::target_machine::LLVMCreateTargetMachine(target, triple, empty.as_ptr(),
+        empty.as_ptr(), level, LLVMRelocMode::LLVMRelocPIC, LLVMCodeModel::LLVMCodeModelDefault);
+    if machine.is_null() {
+        llvm::core::LLVMDisposeMessage(triple);
+        return Err(LlvmError::new("LLVMCreateTargetMachine returned null"))
+    }
+    llvm::core::LLVMSetTarget(module, triple);
+    let result_code = llvm::target_machine::LLVMTargetMachineEmitToFile(machine, module,
+        path.as_ptr() as *mut c_char, LLVMCodeGenFileType::LLVMObjectFile, &mut error_str);
+    llvm::target_machine::LLVMDisposeTargetMachine(machine);
+    llvm::core::LLVMDisposeMessage(triple);
+    if result_code != 0 {
+        let msg = format!("Writing object file failed: {}",
+            CStr::from_ptr(error_str).to_str().unwrap());
+        llvm::core::LLVMDisposeMessage(error_str);
+        return Err(LlvmError(msg));
+    }
+    Ok(())
+}
+
 /// Initialize LLVM or save an error message in `initialize_failed` if this does not work.
 /// We call this function only once in cases some steps are expensive.
 fn initialize() {
@@ -191,8 +271,8 @@ unsafe fn check_run_function(module: LLVMModuleRef) -> Result<(), LlvmError> {
     Ok(())
 }
 
-/// Optimize an LLVM module using our chosen passes (currently uses standard passes for -O2).
-unsafe fn optimize_module(module: LLVMModuleRef) -> Result<(), LlvmError> {
+/// Optimize an LLVM module using the standard passes for the given optimization level.
+unsafe fn optimize_module(module: LLVMModuleRef, opt_level: u32) -> Result<(), LlvmError> {
     let manager = llvm::core::LLVMCreatePassManager();
     if manager.is_null() {
         return Err(LlvmError::new("LLVMCreatePassManager returned null"))
@@ -202,7 +282,7 @@ unsafe fn optimize_module(module: LLVMModuleRef) -> Result<(), LlvmError> {
         return Err(LlvmError::new("LLVMPassManagerBuilderCreate returned null"))
     }
     // TODO: not clear we need both Module and LTO calls here; just LTO might work
-    pmb::LLVMPassManagerBuilderSetOptLevel(builder, 2);
+    pmb::LLVMPassManagerBuilderSetOptLevel(builder, opt_level);
     pmb::LLVMPassManagerBuilderPopulateModulePassManager(builder, manager);
     pmb::LLVMPassManagerBuilderPopulateLTOPassManager(builder, manager, 1, 1);
     pmb::LLVMPassManagerBuilderDispose(builder);
@@ -212,13 +292,14 @@ unsafe fn optimize_module(module: LLVMModuleRef) -> Result<(), LlvmError> {
 }
 
 /// Create an MCJIT execution engine for a given module.
-unsafe fn create_exec_engine(module: LLVMModuleRef) -> Result<LLVMExecutionEngineRef, LlvmError> {
+unsafe fn create_exec_engine(module: LLVMModuleRef, opt_level: u32)
+        -> Result<LLVMExecutionEngineRef, LlvmError> {
     let mut engine = 0 as LLVMExecutionEngineRef;
     let mut error_str = 0 as *mut c_char;
-    let mut options: LLVMMCJITCompilerOptions = std::mem::uninitialized();
+    let mut options: LLVMMCJITCompilerOptions = std::mem::zeroed();
     let options_size = std::mem::size_of::<LLVMMCJITCompilerOptions>();
     llvm::execution_engine::LLVMInitializeMCJITCompilerOptions(&mut options, options_size);
-    options.OptLevel = 2;
+    options.OptLevel = opt_level;
     let result_code = llvm::execution_engine::LLVMCreateMCJITCompilerForModule(
         &mut engine, module, &mut options, options_size, &mut error_str);
     if result_code != 0 {
@@ -1,6 +1,8 @@
+use std::env;
 use std::error::Error;
+use std::fs;
 
-use super::compile_module;
+use super::{compile_module, compile_module_with_opt_level, emit_object_file};
 
 #[test]
 fn basic_use() {
@@ -52,3 +54,38 @@ fn wrong_function_type() {
     assert!(!module.is_ok());
     assert!(module.unwrap_err().description().contains("wrong type"));
 }
+
+#[test]
+fn opt_levels() {
+    let code = "
+       define i64 @run(i64 %arg) {
+           %1 = add i64 %arg, 1
+           ret i64 %1
+       }
+    ";
+    for level in 0..4 {
+        let module = compile_module_with_opt_level(code, level);
+        assert!(module.is_ok());
+        assert_eq!(module.unwrap().run(41), 42);
+    }
+    let module = compile_module_with_opt_level(code, 4);
+    assert!(module.unwrap_err().description().contains("optimization level"));
+}
+
+#[test]
+fn object_file() {
+    let code = "
+       define i64 @run(i64 %arg) {
+           %1 = add i64 %arg, 1
+           ret i64 %1
+       }
+    ";
+    let path = env::temp_dir().join("easy_ll_object_file_test.o");
+    let path = path.to_str().unwrap();
+    assert!(emit_object_file(code, 2, path).is_ok());
+    assert!(fs::metadata(path).unwrap().len() > 0);
+    fs::remove_file(path).unwrap();
+
+    let result = emit_object_file("define i64 @ZZZZZZZ() { ret i64 0 }", 2, path);
+    assert!(result.unwrap_err().description().contains("run function"));
+}
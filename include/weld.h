@@ -0,0 +1,74 @@
+/*
+ * C API for embedding Weld. See weld/weld_capi.rs for the implementation.
+ *
+ * All handles are opaque and owned by the caller; release each one with the matching
+ * *_free function. Functions that can fail take a weld_error_t, which is reset on entry and
+ * holds a nonzero code and a message if the call failed.
+ */
+
+#ifndef WELD_H
+#define WELD_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct WeldModule *weld_module_t;
+typedef struct WeldValue *weld_value_t;
+typedef struct WeldErrorInfo *weld_error_t;
+typedef struct WeldConf *weld_conf_t;
+
+/* Errors */
+
+/* Error codes, matching the ErrorKind enum in weld/error.rs. */
+#define WELD_SUCCESS 0
+#define WELD_PARSE_ERROR 1
+#define WELD_MACRO_ERROR 2
+#define WELD_TYPE_ERROR 3
+#define WELD_CODEGEN_ERROR 4
+#define WELD_RUNTIME_ERROR 5
+#define WELD_CONFIG_ERROR 6
+#define WELD_INTERNAL_ERROR 7
+
+weld_error_t weld_error_new(void);
+int32_t weld_error_code(weld_error_t err);
+const char *weld_error_message(weld_error_t err);
+/* The ID of the run that failed, or -1 if the error did not come from weld_module_run. */
+int64_t weld_error_run_id(weld_error_t err);
+void weld_error_free(weld_error_t err);
+
+/* Configuration (see weld/conf.rs for the supported keys; unknown keys are ignored) */
+
+weld_conf_t weld_conf_new(void);
+void weld_conf_set(weld_conf_t conf, const char *key, const char *value);
+/* Returns a copy of the value, or NULL if unset; release it with weld_string_free. */
+char *weld_conf_get(weld_conf_t conf, const char *key);
+void weld_conf_free(weld_conf_t conf);
+
+void weld_string_free(char *s);
+
+/* Values */
+
+/* Wraps caller-owned data; weld_value_free does not free it. */
+weld_value_t weld_value_new(const void *data);
+const void *weld_value_data(weld_value_t value);
+/* Frees the value, and its data if it was returned by weld_module_run. */
+void weld_value_free(weld_value_t value);
+
+/* Modules */
+
+/* Compiles a program whose body is a function; returns NULL on failure. In this function and
+ * weld_module_run, a NULL conf stands for the default configuration. */
+weld_module_t weld_module_compile(const char *code, weld_conf_t conf, weld_error_t err);
+/* Runs a module; arg must point to a struct holding the function's parameters in order. */
+weld_value_t weld_module_run(weld_module_t module, weld_conf_t conf, weld_value_t arg,
+                             weld_error_t err);
+void weld_module_free(weld_module_t module);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* WELD_H */
@@ -0,0 +1,157 @@
+//! Mapping between Rust types and the in-memory layout of Weld values.
+//!
+//! Generated code lays out Weld structs as LLVM structs, which use the same natural alignment
+//! rules as C. A Rust type can be passed to or returned from a module if it implements
+//! `WeldAbi`, which is provided here for scalars and can be derived for `#[repr(C)]` structs
+//! with `#[derive(WeldAbi)]` from the `weld_derive` crate.
+
+use std::cmp::max;
+use std::mem;
+
+use super::ast::*;
+use super::ast::ScalarKind::*;
+use super::ast::Type::*;
+use super::error::*;
+use super::pretty_print::*;
+
+/// A Rust type whose memory layout matches a Weld type.
+///
+/// This trait is unsafe to implement because modules read and write values through raw pointers
+/// based on `weld_type`; use `#[derive(WeldAbi)]`, which also generates `field_offsets`, rather
+/// than implementing it by hand.
+pub unsafe trait WeldAbi: Copy {
+    /// The Weld type that this Rust type is laid out as.
+    fn weld_type() -> Type;
+
+    /// Byte offsets of each field in the Rust type, for structs (empty for scalars).
+    fn field_offsets() -> Vec<usize> {
+        vec![]
+    }
+
+    /// The Weld type of this Rust type, as it would be written in a Weld program.
+    fn weld_type_string() -> String {
+        print_type(&Self::weld_type())
+    }
+
+    /// Check that the size, alignment and field offsets of the Rust type match the layout that
+    /// generated code uses for `weld_type`.
+    fn check_layout() -> WeldResult<()> {
+        let ty = Self::weld_type();
+        let layout = try!(TypeLayout::of(&ty));
+        if layout.size != mem::size_of::<Self>() || layout.align != mem::align_of::<Self>() {
+            return weld_err!("Layout mismatch for {}: Weld uses size {} and alignment {}, \
+                Rust uses size {} and alignment {}", print_type(&ty), layout.size, layout.align,
+                mem::size_of::<Self>(), mem::align_of::<Self>());
+        }
+        if let Struct(_) = ty {
+            let offsets = Self::field_offsets();
+            if offsets != layout.field_offsets {
+                return weld_err!("Field offset mismatch for {}: Weld uses {:?}, Rust uses {:?}",
+                    print_type(&ty), layout.field_offsets, offsets);
+            }
+        }
+        Ok(())
+    }
+}
+
+macro_rules! scalar_abi {
+    ( $rust_type:ty, $kind:expr ) => {
+        unsafe impl WeldAbi for $rust_type {
+            fn weld_type() -> Type { Scalar($kind) }
+        }
+    }
+}
+
+scalar_abi!(bool, Bool);
+scalar_abi!(i32, I32);
+scalar_abi!(i64, I64);
+scalar_abi!(f32, F32);
+scalar_abi!(f64, F64);
+
+/// The empty struct, for calling programs that take no parameters.
+unsafe impl WeldAbi for () {
+    fn weld_type() -> Type { Struct(vec![]) }
+}
+
+/// Size, alignment and field offsets of a Weld type in memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeLayout {
+    pub size: usize,
+    pub align: usize,
+    /// Offset of each field for structs; empty for other types.
+    pub field_offsets: Vec<usize>,
+}
+
+impl TypeLayout {
+    /// Compute the layout of a type, or return an error for types without a fixed layout yet.
+    pub fn of(ty: &Type) -> WeldResult<TypeLayout> {
+        match *ty {
+            Scalar(kind) => {
+                let size = match kind {
+                    Bool => 1,
+                    I32 | F32 => 4,
+                    I64 | F64 | Timestamp => 8,
+                    Decimal(precision, _) if precision <= MAX_DECIMAL64_PRECISION => 8,
+                    Decimal(_, _) => 16,
+                };
+                Ok(TypeLayout { size: size, align: size, field_offsets: vec![] })
+            }
+
+            Struct(ref fields) => {
+                let mut offset = 0;
+                let mut align = 1;
+                let mut field_offsets = Vec::with_capacity(fields.len());
+                for f in fields {
+                    let layout = try!(TypeLayout::of(f));
+                    offset = round_up(offset, layout.align);
+                    field_offsets.push(offset);
+                    offset += layout.size;
+                    align = max(align, layout.align);
+                }
+                Ok(TypeLayout {
+                    size: round_up(offset, align),
+                    align: align,
+                    field_offsets: field_offsets
+                })
+            }
+
+            _ => weld_err!("No memory layout defined for type {}", print_type(ty))
+        }
+    }
+}
+
+/// Round `offset` up to a multiple of `align`.
+fn round_up(offset: usize, align: usize) -> usize {
+    (offset + align - 1) / align * align
+}
+
+#[test]
+fn scalar_layouts() {
+    assert_eq!(i32::weld_type_string(), "i32");
+    assert_eq!(bool::weld_type_string(), "bool");
+    assert!(i32::check_layout().is_ok());
+    assert!(i64::check_layout().is_ok());
+    assert!(f32::check_layout().is_ok());
+    assert!(f64::check_layout().is_ok());
+    assert!(bool::check_layout().is_ok());
+    assert!(<()>::check_layout().is_ok());
+}
+
+#[test]
+fn struct_layouts() {
+    use super::parser::parse_type;
+
+    let ty = parse_type("{bool,i64,i32}").unwrap().to_type().unwrap();
+    let layout = TypeLayout::of(&ty).unwrap();
+    assert_eq!(layout, TypeLayout { size: 24, align: 8, field_offsets: vec![0, 8, 16] });
+
+    let ty = parse_type("{i32,{bool,f64}}").unwrap().to_type().unwrap();
+    let layout = TypeLayout::of(&ty).unwrap();
+    assert_eq!(layout, TypeLayout { size: 24, align: 8, field_offsets: vec![0, 8] });
+
+    let ty = parse_type("{}").unwrap().to_type().unwrap();
+    assert_eq!(TypeLayout::of(&ty).unwrap().size, 0);
+
+    let ty = parse_type("vec[i32]").unwrap().to_type().unwrap();
+    assert!(TypeLayout::of(&ty).is_err());
+}
@@ -1,15 +1,25 @@
 //! Abstract syntax tree for Weld.
 
-use std::vec;
+use std::cmp::max;
 use std::fmt;
+use std::mem;
+use std::vec;
 
-/// A symbol (identifier name); for now these are strings, but we may add some kind of scope ID.
+use super::intern::Name;
+
+/// A symbol (identifier name) with an ID that distinguishes different variables of the same name.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Symbol {
-    pub name: String,
+    pub name: Name,
     pub id: i32
 }
 
+impl Symbol {
+    pub fn new(name: &str, id: i32) -> Symbol {
+        Symbol { name: Name::new(name), id: id }
+    }
+}
+
 impl fmt::Display for Symbol {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.id == 0 {
@@ -37,12 +47,54 @@ pub enum ScalarKind {
     I64,
     F32,
     F64,
+    /// A point in time, stored as an i64 number of microseconds since 1970-01-01 00:00:00 UTC.
+    Timestamp,
+    /// A fixed-point number with the given precision (total number of digits, 1 to 38) and
+    /// scale (digits after the decimal point), stored as an integer count of units of its last
+    /// digit. Decimals with at most 18 digits are stored in an i64 and others in an i128.
+    Decimal(u8, u8),
+}
+
+/// The largest precision a decimal may have.
+pub const MAX_DECIMAL_PRECISION: u8 = 38;
+
+/// The largest precision of a decimal stored in an i64.
+pub const MAX_DECIMAL64_PRECISION: u8 = 18;
+
+impl ScalarKind {
+    pub fn is_decimal(&self) -> bool {
+        match *self {
+            ScalarKind::Decimal(_, _) => true,
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for ScalarKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ScalarKind::*;
+        match *self {
+            Bool => f.write_str("bool"),
+            I32 => f.write_str("i32"),
+            I64 => f.write_str("i64"),
+            F32 => f.write_str("f32"),
+            F64 => f.write_str("f64"),
+            Timestamp => f.write_str("timestamp"),
+            Decimal(precision, scale) => write!(f, "decimal[{},{}]", precision, scale),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BuilderKind {
     Appender(Box<Type>),
-    Merger(Box<Type>, BinOpKind)
+    Merger(Box<Type>, BinOpKind),
+    /// Takes ownership of an input vector and writes {index, value} merges into it in place.
+    MutVec(Box<Type>),
+    /// Accumulates numeric values into {count: i64, mean: f64, variance: f64, min, max}.
+    StatsMerger(Box<Type>),
+    /// Counts values into equal-width bins between two bounds, giving a vec[i64] of counts.
+    Histogram(Box<Type>),
 }
 
 /// An expression tree, having type annotations of type T. We make this parametrized because
@@ -64,7 +116,9 @@ pub enum ExprKind<T:Clone> {
     F64Literal(f64),
     BinOp(BinOpKind, Box<Expr<T>>, Box<Expr<T>>),
     Ident(Symbol),
-    NewBuilder,  // TODO: this may need to take a parameter
+    /// optional initial value (e.g. the vector a mutvec writes into); builders that take several
+    /// arguments get them as a MakeStruct
+    NewBuilder(Option<Box<Expr<T>>>),
     MakeStruct(Vec<Expr<T>>),
     MakeVector(Vec<Expr<T>>),
     GetField(Box<Expr<T>>, u32),
@@ -81,7 +135,109 @@ pub enum ExprKind<T:Clone> {
     /// builder, elem
     Merge(Box<Expr<T>>, Box<Expr<T>>),
     /// builder
-    Res(Box<Expr<T>>)
+    Res(Box<Expr<T>>),
+    /// function, arguments
+    Builtin(BuiltinKind, Vec<Expr<T>>),
+}
+
+/// A function built into Weld, called like a function but not a value that can be passed around.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BuiltinKind {
+    /// dot(a, b): the dot product of two vectors of f32s or f64s.
+    Dot,
+    /// gemm(a, b, m, n, k): the product of an m x k and a k x n matrix, each stored as a flat
+    /// vector in row-major order.
+    Gemm,
+    /// rand(): a random f64 in [0, 1), the same as rand_seeded(0L).
+    Rand,
+    /// rand_seeded(seed): a random f64 in [0, 1) from the stream with the given i64 seed. Each
+    /// call draws a new number; macro arguments that call it are evaluated once, like a
+    /// function's arguments.
+    RandSeeded,
+    /// to_timestamp(micros): the timestamp an i64 number of microseconds after the epoch.
+    ToTimestamp,
+    /// to_micros(t): the number of microseconds from the epoch to a timestamp, as an i64.
+    ToMicros,
+    /// extract_year(t) etc.: a field of a timestamp's UTC date and time, as an i32. Months and
+    /// days count from 1 and hours from 0.
+    Extract(TimeUnit),
+    /// trunc_year(t) etc.: a timestamp rounded down to the start of its year, month, day or hour.
+    Trunc(TimeUnit),
+    /// add_interval(t, micros): a timestamp moved by an i64 number of microseconds.
+    AddInterval,
+    /// interval_between(start, end): the number of microseconds from one timestamp to another.
+    IntervalBetween,
+    /// i64(x), decimal[10,2](x) etc.: a number converted to another numeric type. Floats are
+    /// rounded to the nearest decimal, with ties away from zero, but converted to integers by
+    /// truncating, as are decimals.
+    Cast(ScalarKind),
+}
+
+/// A unit of calendar time, for the timestamp builtins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TimeUnit {
+    Year,
+    Month,
+    Day,
+    Hour,
+}
+
+impl BuiltinKind {
+    /// The builtin called by the given name, if any.
+    pub fn from_name(name: &str) -> Option<BuiltinKind> {
+        use self::BuiltinKind::*;
+        match name {
+            "dot" => Some(Dot),
+            "gemm" => Some(Gemm),
+            "rand" => Some(Rand),
+            "rand_seeded" => Some(RandSeeded),
+            "to_timestamp" => Some(ToTimestamp),
+            "to_micros" => Some(ToMicros),
+            "extract_year" => Some(Extract(TimeUnit::Year)),
+            "extract_month" => Some(Extract(TimeUnit::Month)),
+            "extract_day" => Some(Extract(TimeUnit::Day)),
+            "extract_hour" => Some(Extract(TimeUnit::Hour)),
+            "trunc_year" => Some(Trunc(TimeUnit::Year)),
+            "trunc_month" => Some(Trunc(TimeUnit::Month)),
+            "trunc_day" => Some(Trunc(TimeUnit::Day)),
+            "trunc_hour" => Some(Trunc(TimeUnit::Hour)),
+            "add_interval" => Some(AddInterval),
+            "interval_between" => Some(IntervalBetween),
+            _ => None
+        }
+    }
+
+    /// Whether the builtin draws random numbers, so that calls to it cannot be duplicated,
+    /// dropped or reordered without changing the program's result.
+    pub fn is_random(&self) -> bool {
+        *self == BuiltinKind::Rand || *self == BuiltinKind::RandSeeded
+    }
+}
+
+impl fmt::Display for BuiltinKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::BuiltinKind::*;
+        let text = match *self {
+            Cast(kind) => return write!(f, "{}", kind),
+            Dot => "dot",
+            Gemm => "gemm",
+            Rand => "rand",
+            RandSeeded => "rand_seeded",
+            ToTimestamp => "to_timestamp",
+            ToMicros => "to_micros",
+            Extract(TimeUnit::Year) => "extract_year",
+            Extract(TimeUnit::Month) => "extract_month",
+            Extract(TimeUnit::Day) => "extract_day",
+            Extract(TimeUnit::Hour) => "extract_hour",
+            Trunc(TimeUnit::Year) => "trunc_year",
+            Trunc(TimeUnit::Month) => "trunc_month",
+            Trunc(TimeUnit::Day) => "trunc_day",
+            Trunc(TimeUnit::Hour) => "trunc_hour",
+            AddInterval => "add_interval",
+            IntervalBetween => "interval_between",
+        };
+        f.write_str(text)
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -108,7 +264,8 @@ impl BinOpKind {
     pub fn is_comparison(&self) -> bool {
         use ast::BinOpKind::*;
         match *self {
-            Equal | NotEqual | LessThan | GreaterThan => true,
+            Equal | NotEqual | LessThan | LessThanOrEqual | GreaterThan | GreaterThanOrEqual =>
+                true,
             _ => false
         }
     }
@@ -164,6 +321,7 @@ impl<T:Clone> Expr<T> {
             GetField(ref expr, _) => vec![expr.as_ref()],
             Merge(ref bldr, ref value) => vec![bldr.as_ref(), value.as_ref()],
             Res(ref bldr) => vec![bldr.as_ref()],
+            NewBuilder(Some(ref arg)) => vec![arg.as_ref()],
             For(ref data, ref bldr, ref func) =>
                 vec![data.as_ref(), bldr.as_ref(), func.as_ref()],
             If(ref cond, ref on_true, ref on_false) =>
@@ -173,35 +331,16 @@ impl<T:Clone> Expr<T> {
                 res.extend(params.iter());
                 res
             }
+            Builtin(_, ref args) => args.iter().collect(),
             // Explicitly list types instead of doing _ => ... to remember to add new types.
-            BoolLiteral(_) | I32Literal(_) | I64Literal(_) | F32Literal(_) | F64Literal(_) | Ident(_) | NewBuilder => vec![]
+            BoolLiteral(_) | I32Literal(_) | I64Literal(_) | F32Literal(_) | F64Literal(_) | Ident(_) |
+                NewBuilder(None) => vec![]
         }.into_iter()
     }
 
     /// Get an iterator of mutable references to the children of this expression.
     pub fn children_mut(&mut self) -> vec::IntoIter<&mut Expr<T>> {
-        use self::ExprKind::*;
-        match self.kind {
-            BinOp(_, ref mut left, ref mut right) => vec![left.as_mut(), right.as_mut()],
-            Let(_, ref mut value, ref mut body) => vec![value.as_mut(), body.as_mut()],
-            Lambda(_, ref mut body) => vec![body.as_mut()],
-            MakeStruct(ref mut exprs) => exprs.iter_mut().collect(),
-            MakeVector(ref mut exprs) => exprs.iter_mut().collect(),
-            GetField(ref mut expr, _) => vec![expr.as_mut()],
-            Merge(ref mut bldr, ref mut value) => vec![bldr.as_mut(), value.as_mut()],
-            Res(ref mut bldr) => vec![bldr.as_mut()],
-            For(ref mut data, ref mut bldr, ref mut func) =>
-                vec![data.as_mut(), bldr.as_mut(), func.as_mut()],
-            If(ref mut cond, ref mut on_true, ref mut on_false) =>
-                vec![cond.as_mut(), on_true.as_mut(), on_false.as_mut()],
-            Apply(ref mut func, ref mut params) => {
-                let mut res = vec![func.as_mut()];
-                res.extend(params.iter_mut());
-                res
-            }
-            // Explicitly list types instead of doing _ => ... to remember to add new types.
-            BoolLiteral(_) | I32Literal(_) | I64Literal(_) | F32Literal(_) | F64Literal(_) | Ident(_) | NewBuilder => vec![]
-        }.into_iter()
+        kind_children_mut(&mut self.kind).into_iter()
     }
 
     /// Substitute Ident nodes with the given symbol for another expression, stopping when an
@@ -244,9 +383,69 @@ impl<T:Clone> Expr<T> {
 
     /// Run a closure on this expression and every child, in pre-order.
     pub fn traverse<F>(&self, func: &mut F) where F: FnMut(&Expr<T>) -> () {
-        func(self);
-        for c in self.children() {
-            c.traverse(func);
+        // Use an explicit stack so that very deep expressions do not overflow the call stack
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            func(e);
+            stack.extend(e.children().rev());
+        }
+    }
+
+    /// The number of nodes on the longest path from this expression down to a leaf.
+    pub fn depth(&self) -> usize {
+        let mut max_depth = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((e, depth)) = stack.pop() {
+            max_depth = max(max_depth, depth);
+            stack.extend(e.children().map(|c| (c, depth + 1)));
+        }
+        max_depth
+    }
+}
+
+/// The children of an expression of the given kind.
+fn kind_children_mut<T:Clone>(kind: &mut ExprKind<T>) -> Vec<&mut Expr<T>> {
+    use self::ExprKind::*;
+    match *kind {
+        BinOp(_, ref mut left, ref mut right) => vec![left.as_mut(), right.as_mut()],
+        Let(_, ref mut value, ref mut body) => vec![value.as_mut(), body.as_mut()],
+        Lambda(_, ref mut body) => vec![body.as_mut()],
+        MakeStruct(ref mut exprs) => exprs.iter_mut().collect(),
+        MakeVector(ref mut exprs) => exprs.iter_mut().collect(),
+        GetField(ref mut expr, _) => vec![expr.as_mut()],
+        Merge(ref mut bldr, ref mut value) => vec![bldr.as_mut(), value.as_mut()],
+        Res(ref mut bldr) => vec![bldr.as_mut()],
+        NewBuilder(Some(ref mut arg)) => vec![arg.as_mut()],
+        For(ref mut data, ref mut bldr, ref mut func) =>
+            vec![data.as_mut(), bldr.as_mut(), func.as_mut()],
+        If(ref mut cond, ref mut on_true, ref mut on_false) =>
+            vec![cond.as_mut(), on_true.as_mut(), on_false.as_mut()],
+        Apply(ref mut func, ref mut params) => {
+            let mut res = vec![func.as_mut()];
+            res.extend(params.iter_mut());
+            res
+        }
+        Builtin(_, ref mut args) => args.iter_mut().collect(),
+        // Explicitly list types instead of doing _ => ... to remember to add new types.
+        BoolLiteral(_) | I32Literal(_) | I64Literal(_) | F32Literal(_) | F64Literal(_) | Ident(_) |
+            NewBuilder(None) => vec![]
+    }
+}
+
+/// Expressions are dropped iteratively, because the default recursive drop of their boxed
+/// children overflows the stack on expressions thousands of levels deep.
+impl<T:Clone> Drop for Expr<T> {
+    fn drop(&mut self) {
+        // Detach each child's kind, leaving a leaf behind, so that dropping a node never recurses
+        let mut pending = Vec::new();
+        let detach = |kind: &mut ExprKind<T>, pending: &mut Vec<ExprKind<T>>| {
+            for child in kind_children_mut(kind) {
+                pending.push(mem::replace(&mut child.kind, ExprKind::BoolLiteral(false)));
+            }
+        };
+        detach(&mut self.kind, &mut pending);
+        while let Some(mut kind) = pending.pop() {
+            detach(&mut kind, &mut pending);
         }
     }
 }
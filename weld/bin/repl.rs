@@ -52,7 +52,7 @@ fn main() {
 
         let program = parse_program(trimmed);
         if let Err(ref e) = program {
-            println!("Error during parsing: {:?}", e);
+            println!("Error during parsing: {}", e.format_with_source(trimmed));
             continue;
         }
         let program = program.unwrap();
@@ -0,0 +1,109 @@
+extern crate weld;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+use weld::error::*;
+use weld::parser::*;
+use weld::pretty_print::format_program;
+
+const USAGE: &'static str = "\
+Usage: weld-fmt [--check] [file.weld ...]
+
+Rewrites Weld source files in the canonical style. With no files, formats standard input to
+standard output.
+
+Options:
+  --check           Do not write any files; list those that are not formatted and exit with
+                    status 1 if there are any
+  -h, --help        Print this message";
+
+/// Format a program's source code, checking that the result parses back to the same program.
+fn format_source(code: &str) -> WeldResult<String> {
+    let program = try!(parse_program(code));
+    let formatted = format_program(&program);
+    match parse_program(&formatted) {
+        Ok(ref reparsed) if *reparsed == program => Ok(formatted),
+        _ => Err(WeldError::with_message(ErrorKind::Internal,
+            format!("Formatting changed the meaning of the program:\n{}", formatted)))
+    }
+}
+
+fn read_file(path: &str) -> io::Result<String> {
+    let mut code = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut code)));
+    Ok(code)
+}
+
+fn main() {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("-") => {
+                let _ = writeln!(io::stderr(), "Unknown option {}\n\n{}", arg, USAGE);
+                process::exit(2);
+            }
+            _ => paths.push(arg)
+        }
+    }
+
+    if paths.is_empty() {
+        let mut code = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut code) {
+            let _ = writeln!(io::stderr(), "weld-fmt: cannot read standard input: {}", e);
+            process::exit(1);
+        }
+        match format_source(&code) {
+            Ok(formatted) => {
+                if check && formatted != code {
+                    process::exit(1);
+                } else if !check {
+                    print!("{}", formatted);
+                }
+            }
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "weld-fmt: {}", e.format_with_source(&code));
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut failed = false;
+    for path in &paths {
+        let result = read_file(path).map_err(|e| format!("cannot read {}: {}", path, e))
+            .and_then(|code| {
+                format_source(&code)
+                    .map(|formatted| (code.clone(), formatted))
+                    .map_err(|e| format!("{}: {}", path, e.format_with_source(&code)))
+            });
+        match result {
+            Ok((ref code, ref formatted)) if formatted == code => (),
+            Ok((_, formatted)) => {
+                if check {
+                    println!("{}", path);
+                    failed = true;
+                } else if let Err(e) = File::create(path)
+                        .and_then(|mut f| f.write_all(formatted.as_bytes())) {
+                    let _ = writeln!(io::stderr(), "weld-fmt: cannot write {}: {}", path, e);
+                    failed = true;
+                }
+            }
+            Err(msg) => {
+                let _ = writeln!(io::stderr(), "weld-fmt: {}", msg);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
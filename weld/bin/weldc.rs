@@ -0,0 +1,195 @@
+extern crate easy_ll;
+extern crate weld;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+
+use weld::conf::*;
+use weld::error::*;
+use weld::explain::explain_program;
+use weld::interpreter::{trace_function, Value};
+use weld::llvm::generate_code;
+use weld::parser::*;
+use weld::pretty_print::*;
+use weld::tokenizer::*;
+use weld::type_inference::*;
+
+const USAGE: &'static str = "\
+Usage: weldc [options] <file.weld>
+
+Compiles a Weld program whose body is a function, printing the requested stages to stdout.
+
+Options:
+  --tokens          Print the token stream
+  --ast             Print the parsed program
+  --transformed     Print the expression after macro expansion and optimization passes
+  --explain         Print which optimization passes changed the program, and why
+  --typed           Print the expression with inferred types
+  --dot             Print the typed expression as a Graphviz graph
+  --llvm            Print the generated LLVM IR
+  --trace           Run the program in the tracing interpreter and print every step
+  --arg <expr>      An argument for --trace, written as a Weld expression such as [1,2,3];
+                    repeat once per parameter
+  -c                Write a native object file (see -o)
+  -o <file>         Object file to write (default: input name with a .o extension)
+  -O <level>        LLVM optimization level, 0 to 3 (default: 2)
+  --passes <list>   Comma-separated optimization passes (default: inline-apply)
+  -h, --help        Print this message";
+
+/// The stages that weldc was asked to print or emit.
+#[derive(Default)]
+struct Options {
+    tokens: bool,
+    ast: bool,
+    transformed: bool,
+    explain: bool,
+    typed: bool,
+    dot: bool,
+    llvm: bool,
+    trace: bool,
+    args: Vec<String>,
+    object: bool,
+    output: Option<String>,
+    input: Option<String>,
+    conf: WeldConf,
+    /// Print the usage message instead of compiling anything.
+    help: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut opts = Options::default();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        // Options that take a value consume the next argument
+        let mut value = || {
+            i += 1;
+            args.get(i).cloned().ok_or(format!("Missing value for {}", arg))
+        };
+        match arg {
+            "--tokens" => opts.tokens = true,
+            "--ast" => opts.ast = true,
+            "--transformed" => opts.transformed = true,
+            "--explain" => opts.explain = true,
+            "--typed" => opts.typed = true,
+            "--dot" => opts.dot = true,
+            "--llvm" => opts.llvm = true,
+            "--trace" => opts.trace = true,
+            "--arg" => opts.args.push(try!(value())),
+            "-c" => opts.object = true,
+            "-o" => opts.output = Some(try!(value())),
+            "-O" => opts.conf.set(LLVM_OPTIMIZATION_LEVEL_KEY, try!(value())),
+            "--passes" => opts.conf.set(OPTIMIZATION_PASSES_KEY, try!(value())),
+            "-h" | "--help" => {
+                opts.help = true;
+                return Ok(opts);
+            }
+            _ if arg.starts_with("-") => {
+                return Err(format!("Unknown option {}\n\n{}", arg, USAGE))
+            }
+            _ if opts.input.is_some() => return Err("Only one input file is allowed".to_string()),
+            _ => opts.input = Some(arg.to_string()),
+        }
+        i += 1;
+    }
+    if opts.input.is_none() {
+        return Err(USAGE.to_string());
+    }
+    Ok(opts)
+}
+
+/// Run the compiler over `code`, printing each stage that was requested.
+fn compile(code: &str, opts: &Options) -> WeldResult<()> {
+    let conf = try!(CompileConf::parse(&opts.conf));
+
+    if opts.tokens {
+        let tokens = try!(tokenize(code).map_err(|e| e.with_kind(ErrorKind::Parse)));
+        let tokens: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+        println!("{}", tokens.join(" "));
+    }
+
+    let program = try!(parse_program(code));
+    if opts.ast {
+        println!("{}", print_program(&program));
+    }
+
+    if opts.explain {
+        print!("{}", try!(explain_program(&program, &opts.conf)));
+    }
+
+    // Compile the program the same way the library does, and print the stages along the way
+    // from its result
+    let code = try!(generate_code(&program, &conf));
+    let expr = &code.expr;
+    if opts.transformed {
+        println!("{}", print_expr(expr));
+    }
+    if opts.typed {
+        println!("{}", print_typed_expr(expr));
+    }
+    if opts.dot {
+        print!("{}", to_dot_highlighted(expr));
+    }
+
+    if opts.trace {
+        let mut args = Vec::new();
+        for arg in &opts.args {
+            args.push(try!(eval_arg(arg)));
+        }
+        let (result, trace) = try!(trace_function(expr, &args));
+        print!("{}", trace);
+        println!("Result: {}", result);
+    }
+
+    if opts.llvm {
+        println!("{}", code.llvm_code);
+    }
+
+    if opts.object {
+        let input = opts.input.as_ref().unwrap();
+        let output = opts.output.clone().unwrap_or_else(|| {
+            Path::new(input).with_extension("o").to_string_lossy().into_owned()
+        });
+        try!(easy_ll::emit_object_file(&code.llvm_code, conf.llvm_optimization_level, &output));
+    }
+    Ok(())
+}
+
+/// Evaluate an argument given on the command line as a Weld expression.
+fn eval_arg(arg: &str) -> WeldResult<Value> {
+    let mut expr = try!(parse_expr(&format!("|| {}", arg)));
+    try!(infer_types(&mut expr));
+    let (value, _) = try!(trace_function(&try!(expr.to_typed()), &[]));
+    Ok(value)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let opts = match parse_args(&args) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            let _ = writeln!(io::stderr(), "{}", msg);
+            process::exit(2);
+        }
+    };
+    if opts.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let input = opts.input.as_ref().unwrap();
+    let mut code = String::new();
+    if let Err(e) = File::open(input).and_then(|mut f| f.read_to_string(&mut code)) {
+        let _ = writeln!(io::stderr(), "weldc: cannot read {}: {}", input, e);
+        process::exit(1);
+    }
+
+    if let Err(e) = compile(&code, &opts) {
+        let _ = writeln!(io::stderr(), "weldc: {:?} error: {}", e.kind(),
+            e.format_with_source(&code));
+        process::exit(1);
+    }
+}
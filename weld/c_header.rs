@@ -0,0 +1,213 @@
+//! Generates C headers describing the memory layout of a program's arguments and result.
+//!
+//! A module's `run` entry point takes a pointer to a struct holding all of the program's
+//! parameters and returns a pointer to its result (see `LlvmGenerator::add_function_on_pointers`).
+//! The header declares `<name>_args` with one field per parameter and `<name>_result` for the
+//! result, plus a numbered struct for every nested struct type. C structs cannot be empty, so
+//! `<name>_args` gets a placeholder field if the program takes no arguments, and empty struct
+//! types elsewhere, which take no space in generated code, are rejected.
+
+use std::collections::{HashMap, HashSet};
+
+use super::ast::*;
+use super::ast::ExprKind::*;
+use super::ast::ScalarKind::*;
+use super::ast::Type::*;
+use super::code_builder::CodeBuilder;
+use super::error::*;
+use super::pretty_print::*;
+use super::util::IdGenerator;
+
+#[cfg(test)] use super::parser::*;
+#[cfg(test)] use super::type_inference::*;
+
+/// C keywords up to C11, which cannot be used as field names.
+const C_KEYWORDS: &'static [&'static str] = &["auto", "break", "case", "char", "const",
+    "continue", "default", "do", "double", "else", "enum", "extern", "float", "for", "goto", "if",
+    "inline", "int", "long", "register", "restrict", "return", "short", "signed", "sizeof",
+    "static", "struct", "switch", "typedef", "union", "unsigned", "void", "volatile", "while",
+    "_Alignas", "_Alignof", "_Atomic", "_Bool", "_Complex", "_Generic", "_Imaginary",
+    "_Noreturn", "_Static_assert", "_Thread_local"];
+
+/// Generate a C header for a typed program whose body is a Lambda, using `name`, which must be a
+/// C identifier, as the prefix for all declared types.
+pub fn generate_c_header(name: &str, program: &TypedExpr) -> WeldResult<String> {
+    if !is_c_identifier(name) {
+        return weld_err!("Header name '{}' is not a C identifier", name);
+    }
+    match program.kind {
+        Lambda(ref params, ref body) => {
+            let mut gen = HeaderGenerator::new(name);
+            let mut args = Vec::with_capacity(params.len());
+            let mut fields = HashSet::new();
+            for p in params {
+                let field = c_field_name(&p.name);
+                if !fields.insert(field.clone()) {
+                    return weld_err!("Two parameters have the C field name '{}'", field);
+                }
+                args.push(format!("{} {};", try!(gen.c_type(&p.ty)), field));
+            }
+            let result = try!(gen.c_type(&body.ty));
+            gen.code.add(format!("struct {}_args {{", name));
+            for arg in args {
+                gen.code.add(arg);
+            }
+            if params.is_empty() {
+                gen.code.add("char unused;");
+            }
+            gen.code.add("};");
+            gen.code.add_line("");
+            gen.code.add(format!("typedef {} {}_result;", result, name));
+            Ok(gen.result())
+        }
+        _ => weld_err!("Expression passed to generate_c_header must be a Lambda")
+    }
+}
+
+/// Tracks the struct declarations needed for a header.
+struct HeaderGenerator {
+    name: String,
+    struct_names: HashMap<Vec<Type>, String>,
+    struct_ids: IdGenerator,
+    code: CodeBuilder,
+}
+
+impl HeaderGenerator {
+    fn new(name: &str) -> HeaderGenerator {
+        HeaderGenerator {
+            name: name.to_string(),
+            struct_names: HashMap::new(),
+            struct_ids: IdGenerator::new(&format!("{}_s", name)),
+            code: CodeBuilder::new(),
+        }
+    }
+
+    /// Return the full header, including include guards and standard includes.
+    fn result(&self) -> String {
+        let guard = format!("{}_WELD_H", self.name.to_uppercase());
+        format!("#ifndef {guard}\n#define {guard}\n\n#include <stdbool.h>\n#include <stdint.h>\n\n\
+            {body}\n#endif /* {guard} */\n", guard = guard, body = self.code.result())
+    }
+
+    /// Return the C type name for a Weld type, declaring any structs it needs.
+    fn c_type(&mut self, ty: &Type) -> WeldResult<String> {
+        match *ty {
+            Scalar(Bool) => Ok("bool".to_string()),
+            Scalar(I32) => Ok("int32_t".to_string()),
+            Scalar(I64) | Scalar(Timestamp) => Ok("int64_t".to_string()),
+            Scalar(Decimal(precision, _)) if precision <= MAX_DECIMAL64_PRECISION =>
+                Ok("int64_t".to_string()),
+            Scalar(Decimal(_, _)) => Ok("__int128".to_string()),
+            Scalar(F32) => Ok("float".to_string()),
+            Scalar(F64) => Ok("double".to_string()),
+
+            Struct(ref fields) if fields.is_empty() =>
+                weld_err!("Empty structs cannot be declared in a C header"),
+
+            Struct(ref fields) => {
+                if self.struct_names.get(fields) == None {
+                    let mut field_types = Vec::with_capacity(fields.len());
+                    for f in fields {
+                        field_types.push(try!(self.c_type(f)));
+                    }
+                    let name = self.struct_ids.next();
+                    self.code.add(format!("struct {} {{", name));
+                    for (i, f) in field_types.iter().enumerate() {
+                        self.code.add(format!("{} f{};", f, i));
+                    }
+                    self.code.add("};");
+                    self.code.add_line("");
+                    self.struct_names.insert(fields.clone(), name);
+                }
+                Ok(format!("struct {}", self.struct_names.get(fields).unwrap()))
+            }
+
+            _ => weld_err!("Unsupported type in C header: {}", print_type(ty))
+        }
+    }
+}
+
+/// Return a C field name for a Weld symbol. `$` is not allowed in C identifiers, so it becomes
+/// `_`, and C keywords get a trailing `_`.
+fn c_field_name(symbol: &Symbol) -> String {
+    let mut name = symbol.name.to_string().replace('$', "_");
+    if symbol.id != 0 {
+        name = format!("{}_{}", name, symbol.id);
+    }
+    if C_KEYWORDS.contains(&name.as_str()) {
+        name.push('_');
+    }
+    name
+}
+
+fn is_c_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' =>
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+fn typed_program(code: &str) -> TypedExpr {
+    let mut expr = parse_expr(code).unwrap();
+    infer_types(&mut expr).unwrap();
+    expr.to_typed().unwrap()
+}
+
+#[test]
+fn scalar_header() {
+    let header = generate_c_header("prog", &typed_program("|x:i32, y:f64| y")).unwrap();
+    assert_eq!(header, "\
+#ifndef PROG_WELD_H
+#define PROG_WELD_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+struct prog_args {
+  int32_t x;
+  double y;
+};
+
+typedef double prog_result;
+
+#endif /* PROG_WELD_H */
+");
+}
+
+#[test]
+fn struct_header() {
+    let program = typed_program("|a:{i64,{bool,f32}}, b:{bool,f32}| {a.$0, b.$1}");
+    let header = generate_c_header("q", &program).unwrap();
+    assert!(header.contains("struct q_s0 {\n  bool f0;\n  float f1;\n};\n"));
+    assert!(header.contains("struct q_s1 {\n  int64_t f0;\n  struct q_s0 f1;\n};\n"));
+    assert!(header.contains("struct q_args {\n  struct q_s1 a;\n  struct q_s0 b;\n};\n"));
+    assert!(header.contains("struct q_s2 {\n  int64_t f0;\n  float f1;\n};\n"));
+    assert!(header.contains("typedef struct q_s2 q_result;\n"));
+
+    let program = typed_program("|v:vec[i32]| v");
+    assert!(generate_c_header("v", &program).is_err());
+
+    let program = typed_program("let x = 1; x");
+    assert!(generate_c_header("e", &program).is_err());
+
+    let program = typed_program("|x:{}, y:i32| y");
+    assert!(generate_c_header("e", &program).is_err());
+}
+
+#[test]
+fn c_names() {
+    let header = generate_c_header("p", &typed_program("|| 1")).unwrap();
+    assert!(header.contains("struct p_args {\n  char unused;\n};\n"));
+
+    let program = typed_program("|$x:i32, int:i64, _Bool:bool, y$:i32| y$");
+    let header = generate_c_header("p", &program).unwrap();
+    assert!(header.contains("  int32_t _x;\n  int64_t int_;\n  bool _Bool_;\n  int32_t y_;\n"));
+
+    let program = typed_program("|$x:i32, _x:i32| _x");
+    assert!(generate_c_header("p", &program).is_err());
+    assert!(generate_c_header("my-prog", &typed_program("|x:i32| x")).is_err());
+    assert!(generate_c_header("2p", &typed_program("|x:i32| x")).is_err());
+}
@@ -0,0 +1,226 @@
+//! A cache of front-end results, so that compiling the same source again skips straight to LLVM.
+//!
+//! Query engines that template their Weld programs tend to compile identical source over and
+//! over. `compile_source` looks programs up by a hash of their source and the transforms they
+//! are compiled with, and reuses the typed expression and generated code from the last compile
+//! if it finds a match. The cache is shared by every caller in the process, so it holds as many
+//! programs as the largest `weld.compile.cacheSize` any of them has used, and evicts the least
+//! recently used program when it is full.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use super::conf::CompileConf;
+use super::error::*;
+use super::llvm::{generate_code, GeneratedCode};
+use super::parser::parse_program;
+use super::stage_log::logged_stages;
+
+#[cfg(test)] use std::env;
+#[cfg(test)] use std::fs;
+#[cfg(test)] use super::conf::*;
+
+lazy_static! {
+    /// The cache used by `compile_source`.
+    pub static ref FRONTEND_CACHE: Mutex<FrontendCache> = Mutex::new(FrontendCache::new(0));
+}
+
+struct CacheEntry {
+    /// Kept to rule out hash collisions.
+    source: String,
+    passes: Vec<String>,
+    code: GeneratedCode,
+}
+
+/// Generated code for recently compiled programs, keyed by their source and transforms.
+pub struct FrontendCache {
+    capacity: usize,
+    entries: HashMap<u64, CacheEntry>,
+    /// Keys from least to most recently used.
+    order: VecDeque<u64>,
+    hits: u64,
+    misses: u64,
+}
+
+impl FrontendCache {
+    /// Create a cache that holds up to `capacity` programs.
+    pub fn new(capacity: usize) -> FrontendCache {
+        FrontendCache {
+            capacity: capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Number of programs the cache can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the number of programs the cache holds, evicting the oldest ones if needed.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.order.len() > capacity {
+            let key = self.order.pop_front().unwrap();
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Look up the code generated for a program, counting a hit or a miss.
+    pub fn get(&mut self, source: &str, passes: &[String]) -> Option<GeneratedCode> {
+        let key = cache_key(source, passes);
+        let found = match self.entries.get(&key) {
+            Some(entry) if entry.source == source && entry.passes == passes =>
+                Some(entry.code.clone()),
+            _ => None
+        };
+        if found.is_some() {
+            self.hits += 1;
+            self.touch(key);
+        } else {
+            self.misses += 1;
+        }
+        found
+    }
+
+    /// Remember the code generated for a program.
+    pub fn insert(&mut self, source: &str, passes: &[String], code: GeneratedCode) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = cache_key(source, passes);
+        let entry = CacheEntry { source: source.to_string(), passes: passes.to_vec(), code: code };
+        if self.entries.insert(key, entry).is_some() {
+            self.touch(key);
+            return;
+        }
+        self.order.push_back(key);
+        let capacity = self.capacity;
+        self.set_capacity(capacity);
+    }
+
+    /// Number of programs in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Number of lookups that found a program.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Number of lookups that did not find a program.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Mark a key as the most recently used.
+    fn touch(&mut self, key: u64) {
+        if let Some(pos) = self.order.iter().position(|k| *k == key) {
+            self.order.remove(pos);
+        }
+        self.order.push_back(key);
+    }
+}
+
+fn cache_key(source: &str, passes: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    passes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Parse a program and generate code for it as `generate_code` does, unless `cache` already
+/// holds the result for the same source and transforms.
+///
+/// Programs compiled while stages are being dumped bypass the cache, so that every stage gets
+/// dumped, and so do programs for which the time budget caused transforms to be skipped, since
+/// their code is worse than a full compile would produce.
+pub fn generate_code_cached(source: &str, conf: &CompileConf, cache: &Mutex<FrontendCache>)
+        -> WeldResult<GeneratedCode> {
+    let use_cache = conf.cache_size > 0 && logged_stages(conf).is_empty();
+    if use_cache {
+        let start = Instant::now();
+        let mut cache = cache.lock().unwrap();
+        // Only ever grow the cache, so that a caller with a small cache size does not evict
+        // programs that other callers cached
+        if conf.cache_size > cache.capacity() {
+            cache.set_capacity(conf.cache_size);
+        }
+        if let Some(mut code) = cache.get(source, &conf.optimization_passes) {
+            code.elapsed = start.elapsed();
+            return Ok(code);
+        }
+    }
+    // Compile without holding the lock, so that other programs can use the cache meanwhile
+    let program = try!(parse_program(source));
+    let code = try!(generate_code(&program, conf));
+    if use_cache && code.skipped_passes.is_empty() {
+        cache.lock().unwrap().insert(source, &conf.optimization_passes, code.clone());
+    }
+    Ok(code)
+}
+
+#[test]
+fn cache_programs() {
+    let mut conf = WeldConf::new();
+    conf.set(CACHE_SIZE_KEY, "2");
+    let conf = CompileConf::parse(&conf).unwrap();
+    let cache = Mutex::new(FrontendCache::new(2));
+    let sources = ["|x:i32| x + 1", "|x:i64| x * 2L", "|x:f64| x - 1.0"];
+
+    let first = generate_code_cached(sources[0], &conf, &cache).unwrap();
+    let again = generate_code_cached(sources[0], &conf, &cache).unwrap();
+    assert_eq!(again.llvm_code, first.llvm_code);
+    assert_eq!(again.expr, first.expr);
+    {
+        let cache = cache.lock().unwrap();
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 1, 1));
+    }
+
+    // The least recently used program is evicted once the cache is full
+    generate_code_cached(sources[1], &conf, &cache).unwrap();
+    generate_code_cached(sources[0], &conf, &cache).unwrap();
+    generate_code_cached(sources[2], &conf, &cache).unwrap();
+    {
+        let mut cache = cache.lock().unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(sources[0], &conf.optimization_passes).is_some());
+        assert!(cache.get(sources[1], &conf.optimization_passes).is_none());
+        assert!(cache.get(sources[0], &[]).is_none());
+    }
+
+    // Errors are not cached, and programs compiled while dumping stages skip the cache
+    assert_eq!(generate_code_cached("|x:i32| x +", &conf, &cache).err().unwrap().kind(),
+        ErrorKind::Parse);
+    let dir = env::temp_dir().join(format!("weld-cache-test-{}", ::std::process::id()));
+    let mut dump_conf = conf.clone();
+    dump_conf.dump_stages = Some(vec!["llvm".to_string()]);
+    dump_conf.dump_dir = Some(dir.to_string_lossy().into_owned());
+    let misses = cache.lock().unwrap().misses();
+    generate_code_cached(sources[0], &dump_conf, &cache).unwrap();
+    assert_eq!(cache.lock().unwrap().misses(), misses);
+    assert!(dir.join("00-llvm.txt").exists());
+    fs::remove_dir_all(&dir).unwrap();
+
+    let mut no_cache = conf.clone();
+    no_cache.cache_size = 0;
+    generate_code_cached(sources[1], &no_cache, &cache).unwrap();
+    assert_eq!(cache.lock().unwrap().len(), 2);
+
+    // A smaller cache size does not shrink the shared cache, but a larger one grows it
+    let mut small = conf.clone();
+    small.cache_size = 1;
+    generate_code_cached(sources[0], &small, &cache).unwrap();
+    assert_eq!(cache.lock().unwrap().len(), 2);
+    let mut large = conf.clone();
+    large.cache_size = 3;
+    generate_code_cached(sources[1], &large, &cache).unwrap();
+    let cache = cache.lock().unwrap();
+    assert_eq!((cache.capacity(), cache.len()), (3, 3));
+}
@@ -0,0 +1,307 @@
+//! Key-value configuration for compiling and running Weld programs.
+//!
+//! A `WeldConf` is a string-to-string map so that embedders (including through the C API) can
+//! pass settings without depending on the exact set Weld understands. Known keys are parsed into
+//! `CompileConf` and `RunConf`; unknown keys are ignored with a warning on stderr so that newer
+//! callers keep working against older versions of the library.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use super::error::*;
+
+/// Number of worker threads to use for a run. Only 1 is supported so far.
+pub const THREADS_KEY: &'static str = "weld.threads";
+/// Maximum number of bytes a run may allocate. Runs cannot be limited yet, so this is rejected.
+pub const MEMORY_LIMIT_KEY: &'static str = "weld.memory.limit";
+/// Comma-separated list of AST transforms to apply, in order.
+pub const OPTIMIZATION_PASSES_KEY: &'static str = "weld.optimization.passes";
+/// LLVM optimization level, from 0 to 3.
+pub const LLVM_OPTIMIZATION_LEVEL_KEY: &'static str = "weld.llvm.optimization.level";
+/// Whether to print the generated LLVM code to stdout while compiling.
+pub const DUMP_CODE_KEY: &'static str = "weld.compile.dumpCode";
+/// Time budget for compiling a program, in milliseconds. Transforms not in `REQUIRED_TRANSFORMS`
+/// are skipped once it is used up (though so far every transform is required), LLVM optimizes
+/// at level 1 at most once the front end has used half of it, and if LLVM has not finished when
+/// it runs out, the program is compiled again at level 0 instead of waiting. Compilation can
+/// still take longer than the budget by the time that last step takes.
+pub const TIME_BUDGET_KEY: &'static str = "weld.compile.timeBudgetMs";
+/// Comma-separated list of compilation stages after which to dump the program, or `all`.
+pub const DUMP_STAGES_KEY: &'static str = "weld.compile.dumpStages";
+/// Directory to write stage dumps to; they go to stderr if this is not set.
+pub const DUMP_DIR_KEY: &'static str = "weld.compile.dumpDir";
+/// Number of programs whose front-end output `compile_source` keeps for reuse; 0 disables it.
+/// The cache is shared by the whole process and grows to the largest size requested.
+pub const CACHE_SIZE_KEY: &'static str = "weld.compile.cacheSize";
+
+/// Environment variable used as the value of `weld.compile.dumpStages` when that key is not set,
+/// so that stages can be dumped from programs that do not expose their configuration. It is
+/// read by `StageLog`, which only warns about an invalid value and dumps nothing.
+pub const LOG_ENV_VAR: &'static str = "WELD_LOG";
+
+const KNOWN_KEYS: &'static [&'static str] = &[
+    THREADS_KEY,
+    MEMORY_LIMIT_KEY,
+    OPTIMIZATION_PASSES_KEY,
+    LLVM_OPTIMIZATION_LEVEL_KEY,
+    DUMP_CODE_KEY,
+    TIME_BUDGET_KEY,
+    DUMP_STAGES_KEY,
+    DUMP_DIR_KEY,
+    CACHE_SIZE_KEY,
+];
+
+/// Names of the AST transforms that can be listed in `weld.optimization.passes`.
+pub const TRANSFORM_NAMES: &'static [&'static str] = &["inline-apply"];
+
+/// Transforms that code generation depends on, which are run even if the time budget is used up.
+/// `inline-apply` is one because we cannot generate code for Apply expressions.
+pub const REQUIRED_TRANSFORMS: &'static [&'static str] = &["inline-apply"];
+
+/// Names of the fixed stages that can be listed in `weld.compile.dumpStages`. Transforms can be
+/// listed too, by their name in `TRANSFORM_NAMES`, to dump the program after they run.
+pub const STAGE_NAMES: &'static [&'static str] = &["parse", "macros", "types", "llvm"];
+
+const DEFAULT_THREADS: i32 = 1;
+const DEFAULT_OPTIMIZATION_PASSES: &'static str = "inline-apply";
+const DEFAULT_LLVM_OPTIMIZATION_LEVEL: u32 = 2;
+const DEFAULT_CACHE_SIZE: usize = 64;
+
+/// A set of configuration options, passed to `compile_program` and when running a module.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WeldConf {
+    dict: HashMap<String, String>
+}
+
+impl WeldConf {
+    /// Create an empty configuration, in which every setting has its default value.
+    pub fn new() -> WeldConf {
+        WeldConf { dict: HashMap::new() }
+    }
+
+    /// Set a configuration key, replacing any previous value.
+    pub fn set<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.dict.insert(key.into(), value.into());
+    }
+
+    /// Get the value of a configuration key, if it was set.
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.dict.get(key)
+    }
+
+    /// Print a warning for each key that Weld does not recognize.
+    fn warn_unknown_keys(&self) {
+        let mut keys: Vec<&String> = self.dict.keys()
+            .filter(|k| !KNOWN_KEYS.contains(&k.as_str()))
+            .collect();
+        keys.sort();
+        for key in keys {
+            let _ = writeln!(io::stderr(), "Warning: ignoring unknown Weld configuration key {}",
+                key);
+        }
+    }
+}
+
+/// Settings used when compiling a program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompileConf {
+    pub optimization_passes: Vec<String>,
+    pub llvm_optimization_level: u32,
+    pub dump_code: bool,
+    pub time_budget_ms: Option<u64>,
+    /// Stages after which to dump the program, or None to use `WELD_LOG`; see `stage_log`.
+    pub dump_stages: Option<Vec<String>>,
+    pub dump_dir: Option<String>,
+    /// Number of entries to keep in the front-end cache; see `cache`.
+    pub cache_size: usize,
+}
+
+impl CompileConf {
+    /// Parse the compilation settings in a WeldConf, returning an error for invalid values.
+    pub fn parse(conf: &WeldConf) -> WeldResult<CompileConf> {
+        CompileConf::parse_impl(conf).map_err(|e| e.with_kind(ErrorKind::Config))
+    }
+
+    fn parse_impl(conf: &WeldConf) -> WeldResult<CompileConf> {
+        conf.warn_unknown_keys();
+        let passes = conf.get(OPTIMIZATION_PASSES_KEY).map(|s| s.as_str())
+            .unwrap_or(DEFAULT_OPTIMIZATION_PASSES);
+        let mut optimization_passes = Vec::new();
+        for pass in passes.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            if !TRANSFORM_NAMES.contains(&pass) {
+                return weld_err!("Unknown optimization pass in {}: {}",
+                    OPTIMIZATION_PASSES_KEY, pass);
+            }
+            optimization_passes.push(pass.to_string());
+        }
+        let llvm_optimization_level = try!(parse_value(conf, LLVM_OPTIMIZATION_LEVEL_KEY,
+            DEFAULT_LLVM_OPTIMIZATION_LEVEL));
+        if llvm_optimization_level > 3 {
+            return weld_err!("{} must be between 0 and 3", LLVM_OPTIMIZATION_LEVEL_KEY);
+        }
+        let dump_stages = match conf.get(DUMP_STAGES_KEY) {
+            Some(stages) => Some(try!(parse_stages(DUMP_STAGES_KEY, stages))),
+            None => None
+        };
+        Ok(CompileConf {
+            optimization_passes: optimization_passes,
+            llvm_optimization_level: llvm_optimization_level,
+            dump_code: try!(parse_value(conf, DUMP_CODE_KEY, false)),
+            time_budget_ms: match conf.get(TIME_BUDGET_KEY) {
+                Some(_) => Some(try!(parse_value(conf, TIME_BUDGET_KEY, 0))),
+                None => None
+            },
+            dump_stages: dump_stages,
+            dump_dir: conf.get(DUMP_DIR_KEY).cloned(),
+            cache_size: try!(parse_value(conf, CACHE_SIZE_KEY, DEFAULT_CACHE_SIZE)),
+        })
+    }
+}
+
+/// Settings used when running a compiled module.
+///
+/// Modules currently run on the calling thread without a memory tracker, so these cannot be
+/// acted on yet; `check_supported` rejects any that differ from what a run actually does.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunConf {
+    pub threads: i32,
+    /// None if no limit was set.
+    pub memory_limit: Option<i64>,
+}
+
+impl RunConf {
+    /// Parse the run settings in a WeldConf, returning an error for invalid values.
+    pub fn parse(conf: &WeldConf) -> WeldResult<RunConf> {
+        RunConf::parse_impl(conf).map_err(|e| e.with_kind(ErrorKind::Config))
+    }
+
+    fn parse_impl(conf: &WeldConf) -> WeldResult<RunConf> {
+        conf.warn_unknown_keys();
+        let threads = try!(parse_value(conf, THREADS_KEY, DEFAULT_THREADS));
+        if threads <= 0 {
+            return weld_err!("{} must be positive", THREADS_KEY);
+        }
+        let memory_limit = match conf.get(MEMORY_LIMIT_KEY) {
+            Some(_) => Some(try!(parse_value(conf, MEMORY_LIMIT_KEY, 0))),
+            None => None
+        };
+        if memory_limit.map_or(false, |limit| limit <= 0) {
+            return weld_err!("{} must be positive", MEMORY_LIMIT_KEY);
+        }
+        Ok(RunConf { threads: threads, memory_limit: memory_limit })
+    }
+
+    /// Return an error if a run cannot follow these settings.
+    pub fn check_supported(&self) -> WeldResult<()> {
+        if self.threads != 1 {
+            return weld_err!(Config, "{} is {}, but modules only run on the calling thread so \
+                far", THREADS_KEY, self.threads);
+        }
+        if self.memory_limit.is_some() {
+            return weld_err!(Config, "{} is set, but runs cannot be limited yet",
+                MEMORY_LIMIT_KEY);
+        }
+        Ok(())
+    }
+}
+
+/// Parse a comma-separated list of stages to dump, or `all`, taken from `source`.
+pub fn parse_stages(source: &str, stages: &str) -> WeldResult<Vec<String>> {
+    let mut result = Vec::new();
+    for stage in stages.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        if stage == "all" {
+            let all = STAGE_NAMES.iter().chain(TRANSFORM_NAMES);
+            result.extend(all.map(|s| s.to_string()));
+        } else if STAGE_NAMES.contains(&stage) || TRANSFORM_NAMES.contains(&stage) {
+            result.push(stage.to_string());
+        } else {
+            return weld_err!(Config, "Unknown stage in {}: {}", source, stage);
+        }
+    }
+    Ok(result)
+}
+
+/// Parse the value of a key with `FromStr`, or return `default` if it is not set.
+fn parse_value<T: FromStr>(conf: &WeldConf, key: &str, default: T) -> WeldResult<T> {
+    match conf.get(key) {
+        None => Ok(default),
+        Some(value) => match T::from_str(value.trim()) {
+            Ok(v) => Ok(v),
+            Err(_) => weld_err!("Invalid value for {}: {}", key, value)
+        }
+    }
+}
+
+#[test]
+fn default_conf() {
+    let conf = WeldConf::new();
+    let compile = CompileConf::parse(&conf).unwrap();
+    assert_eq!(compile.optimization_passes, vec!["inline-apply".to_string()]);
+    assert_eq!(compile.llvm_optimization_level, 2);
+    assert_eq!(compile.dump_code, false);
+    assert_eq!(compile.time_budget_ms, None);
+    assert_eq!(compile.cache_size, 64);
+    let run = RunConf::parse(&conf).unwrap();
+    assert_eq!(run.threads, 1);
+    assert_eq!(run.memory_limit, None);
+    assert!(run.check_supported().is_ok());
+}
+
+#[test]
+fn parse_conf() {
+    let mut conf = WeldConf::new();
+    conf.set(THREADS_KEY, "8");
+    conf.set(LLVM_OPTIMIZATION_LEVEL_KEY, "0");
+    conf.set(OPTIMIZATION_PASSES_KEY, "");
+    conf.set(DUMP_CODE_KEY, "true");
+    conf.set(TIME_BUDGET_KEY, "250");
+    conf.set(CACHE_SIZE_KEY, "0");
+    conf.set("weld.some.future.key", "1");
+    let compile = CompileConf::parse(&conf).unwrap();
+    assert_eq!(compile.optimization_passes.len(), 0);
+    assert_eq!(compile.llvm_optimization_level, 0);
+    assert_eq!(compile.dump_code, true);
+    assert_eq!(compile.time_budget_ms, Some(250));
+    assert_eq!(compile.cache_size, 0);
+    assert_eq!(RunConf::parse(&conf).unwrap().threads, 8);
+    assert_eq!(RunConf::parse(&conf).unwrap().check_supported().unwrap_err().kind(),
+        ErrorKind::Config);
+    conf.set(THREADS_KEY, "1");
+    conf.set(MEMORY_LIMIT_KEY, "1000000");
+    assert_eq!(RunConf::parse(&conf).unwrap().memory_limit, Some(1000000));
+    assert!(RunConf::parse(&conf).unwrap().check_supported().is_err());
+    conf.set(MEMORY_LIMIT_KEY, "0");
+    assert!(RunConf::parse(&conf).is_err());
+
+    conf.set(THREADS_KEY, "zero");
+    assert_eq!(RunConf::parse(&conf).unwrap_err().kind(), ErrorKind::Config);
+    conf.set(THREADS_KEY, "0");
+    assert!(RunConf::parse(&conf).is_err());
+    conf.set(LLVM_OPTIMIZATION_LEVEL_KEY, "4");
+    assert!(CompileConf::parse(&conf).is_err());
+    conf.set(LLVM_OPTIMIZATION_LEVEL_KEY, "2");
+    conf.set(TIME_BUDGET_KEY, "-1");
+    assert!(CompileConf::parse(&conf).is_err());
+    conf.set(TIME_BUDGET_KEY, "0");
+    conf.set(OPTIMIZATION_PASSES_KEY, "inline-apply, fuse-loops");
+    assert!(CompileConf::parse(&conf).is_err());
+}
+
+#[test]
+fn dump_stages() {
+    let mut conf = WeldConf::new();
+    assert_eq!(CompileConf::parse(&conf).unwrap().dump_stages, None);
+    conf.set(DUMP_STAGES_KEY, "");
+    assert_eq!(CompileConf::parse(&conf).unwrap().dump_stages, Some(vec![]));
+    conf.set(DUMP_STAGES_KEY, "macros, inline-apply,llvm");
+    conf.set(DUMP_DIR_KEY, "/tmp/weld");
+    let compile = CompileConf::parse(&conf).unwrap();
+    assert_eq!(compile.dump_stages.unwrap(), vec!["macros", "inline-apply", "llvm"]);
+    assert_eq!(compile.dump_dir, Some("/tmp/weld".to_string()));
+    conf.set(DUMP_STAGES_KEY, "all");
+    assert_eq!(CompileConf::parse(&conf).unwrap().dump_stages.unwrap(),
+        vec!["parse", "macros", "types", "llvm", "inline-apply"]);
+    conf.set(DUMP_STAGES_KEY, "parse,codegen");
+    assert_eq!(CompileConf::parse(&conf).unwrap_err().kind(), ErrorKind::Config);
+}
@@ -0,0 +1,111 @@
+//! Fixed-point decimal arithmetic for the interpreters.
+//!
+//! A `decimal[p,s]` value is stored as an integer count of units of 10^-s, in an i64 when p is
+//! at most 18 and in an i128 otherwise; the interpreters hold both in an i128 and wrap results
+//! to the storage width as generated code does. As with integers, results that need more than
+//! p digits are not checked. Multiplication, division and conversions that drop digits round to
+//! the nearest representable value, with ties away from zero. The `@decimal64.*` and `@i128.*`
+//! functions in the prelude do the same for generated code. Division by zero is a runtime error
+//! in the interpreters; generated code has no way to report errors yet, so it traps instead.
+
+use super::ast::MAX_DECIMAL64_PRECISION;
+
+/// 10^exp, for exp up to 38.
+pub fn pow10(exp: u8) -> i128 {
+    10i128.pow(exp as u32)
+}
+
+/// 10^exp as an f64. This is exact for exp up to 22 and the nearest f64 otherwise, which
+/// `powi` does not guarantee (it is off by one ulp for 10^33, for example).
+pub fn pow10_f64(exp: u8) -> f64 {
+    pow10(exp) as f64
+}
+
+/// Wrap a value around to the storage width of a decimal with the given precision.
+pub fn wrap(value: i128, precision: u8) -> i128 {
+    if precision <= MAX_DECIMAL64_PRECISION {
+        value as i64 as i128
+    } else {
+        value
+    }
+}
+
+/// Divide, rounding to the nearest integer with ties away from zero.
+pub fn div_round(n: i128, d: i128) -> i128 {
+    let (q, r) = (n.wrapping_div(d), n.wrapping_rem(d));
+    // 2|r| >= |d|, without overflowing
+    if r.abs() >= d.abs() - r.abs() {
+        if (n < 0) != (d < 0) { q - 1 } else { q + 1 }
+    } else {
+        q
+    }
+}
+
+/// Multiply two decimals of a type with at most 18 digits and the given scale.
+pub fn mul(a: i128, b: i128, scale: u8) -> i128 {
+    wrap(div_round(a * b, pow10(scale)), MAX_DECIMAL64_PRECISION)
+}
+
+/// Divide two decimals of a type with at most 18 digits and the given scale; `b` must not be 0.
+pub fn div(a: i128, b: i128, scale: u8) -> i128 {
+    wrap(div_round(a * pow10(scale), b), MAX_DECIMAL64_PRECISION)
+}
+
+/// Change the scale of a decimal, rounding if digits are dropped, and wrap it to the storage
+/// width of the new precision.
+pub fn rescale(value: i128, scale: u8, new_precision: u8, new_scale: u8) -> i128 {
+    let value = if new_scale >= scale {
+        value.wrapping_mul(pow10(new_scale - scale))
+    } else {
+        div_round(value, pow10(scale - new_scale))
+    };
+    wrap(value, new_precision)
+}
+
+/// Print a decimal with all of its digits after the decimal point, e.g. `-0.50`.
+pub fn format(value: i128, scale: u8) -> String {
+    let digits = value.unsigned_abs().to_string();
+    let scale = scale as usize;
+    let digits = if digits.len() <= scale {
+        format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits)
+    } else {
+        digits
+    };
+    let (int_part, frac_part) = digits.split_at(digits.len() - scale);
+    let sign = if value < 0 { "-" } else { "" };
+    if scale == 0 {
+        format!("{}{}", sign, int_part)
+    } else {
+        format!("{}{}.{}", sign, int_part, frac_part)
+    }
+}
+
+#[test]
+fn decimal_arithmetic() {
+    assert_eq!(div_round(5, 2), 3);
+    assert_eq!(div_round(-5, 2), -3);
+    assert_eq!(div_round(5, -2), -3);
+    assert_eq!(div_round(7, 3), 2);
+    assert_eq!(div_round(-7, 3), -2);
+    assert_eq!(div_round(-1, 3), 0);
+    assert_eq!(div_round(pow10(38) - 1, pow10(38)), 1);
+
+    // 1.05 * 1.05 = 1.1025 and 1.00 / 3.00 = 0.333...
+    assert_eq!(mul(105, 105, 2), 110);
+    assert_eq!(mul(-105, 105, 2), -110);
+    assert_eq!(div(100, 300, 2), 33);
+    assert_eq!(div(200, 300, 2), 67);
+
+    assert_eq!(rescale(12345, 3, 10, 1), 123);
+    assert_eq!(rescale(-12350, 3, 10, 1), -124);
+    assert_eq!(rescale(5, 0, 20, 30), 5 * pow10(30));
+    assert_eq!(wrap(1 << 63, 18), -(1 << 63));
+    assert_eq!(wrap(1 << 63, 19), 1 << 63);
+    assert_eq!(pow10_f64(33), 1e33);
+    assert_eq!(pow10_f64(37), 1e37);
+
+    assert_eq!(format(-50, 2), "-0.50");
+    assert_eq!(format(12345, 2), "123.45");
+    assert_eq!(format(7, 0), "7");
+    assert_eq!(format(-pow10(37), 37), "-1.0000000000000000000000000000000000000");
+}
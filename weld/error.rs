@@ -1,36 +1,212 @@
+use std::cmp::{max, min};
 use std::convert::From;
 use std::error;
 use std::fmt;
 
 use easy_ll::LlvmError;
 
+/// The stage of compilation or execution that an error came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The program could not be tokenized or parsed.
+    Parse,
+    /// Macro expansion failed (e.g. wrong number of arguments or runaway recursion).
+    Macro,
+    /// Type inference failed or found inconsistent types.
+    Type,
+    /// LLVM code generation or compilation failed.
+    Codegen,
+    /// A compiled program could not be run (e.g. its arguments had the wrong type).
+    Runtime,
+    /// A configuration value was invalid.
+    Config,
+    /// An invariant inside Weld was violated; this indicates a bug in Weld, not the program.
+    Internal,
+}
+
+impl ErrorKind {
+    /// Is this kind of error caused by the user's program (as opposed to Weld or its caller)?
+    pub fn is_user_error(&self) -> bool {
+        match *self {
+            ErrorKind::Parse | ErrorKind::Macro | ErrorKind::Type => true,
+            _ => false
+        }
+    }
+}
+
+/// A range of byte offsets in a program's source text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Return the 1-based line and column (in characters) where this span starts in `source`.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let prefix = &source[..min(self.start, source.len())];
+        let line = prefix.matches('\n').count() + 1;
+        let line_start = prefix.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        (line, prefix[line_start..].chars().count() + 1)
+    }
+}
+
+/// Maximum number of characters of a source line to show in error snippets.
+const MAX_SNIPPET_WIDTH: usize = 80;
+
 /// Error type returned by Weld.
-#[derive(Debug)]
-pub struct WeldError(String);
+///
+/// Errors are usually created without a kind by `weld_err!` deep inside a stage, and tagged
+/// with the stage's kind by `with_kind` at its entry point; errors that escape untagged are
+/// reported as `Internal`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeldError {
+    message: String,
+    kind: Option<ErrorKind>,
+    span: Option<Span>,
+    run_id: Option<i64>,
+}
 
 impl WeldError {
     pub fn new(description: String) -> WeldError {
-        WeldError(description)
+        WeldError { message: description, kind: None, span: None, run_id: None }
+    }
+
+    /// Create an error of a specific kind.
+    pub fn with_message(kind: ErrorKind, description: String) -> WeldError {
+        WeldError::new(description).with_kind(kind)
+    }
+
+    /// Set the kind of this error, unless it already has one.
+    pub fn with_kind(mut self, kind: ErrorKind) -> WeldError {
+        if self.kind.is_none() {
+            self.kind = Some(kind);
+        }
+        self
+    }
+
+    /// Set the source span this error refers to, unless it already has one.
+    pub fn with_span(mut self, span: Span) -> WeldError {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    /// Set the ID of the run this error occurred in.
+    pub fn with_run_id(mut self, run_id: i64) -> WeldError {
+        self.run_id = Some(run_id);
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind.unwrap_or(ErrorKind::Internal)
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// The ID of the run this error occurred in, from `llvm::new_run_id`, if it came from one.
+    pub fn run_id(&self) -> Option<i64> {
+        self.run_id
+    }
+
+    /// Append the line and column of this error's span in `source` to its message, as in
+    /// "Expected ')' at 4:12". Does nothing if the error has no span.
+    pub fn with_source_location(mut self, source: &str) -> WeldError {
+        if let Some(span) = self.span {
+            let (line, col) = span.line_col(source);
+            self.message = format!("{} at {}:{}", self.message, line, col);
+        }
+        self
+    }
+
+    /// Format the error message followed by the source line it refers to, with carets under
+    /// the span. Long lines are clipped around the span. Returns just the message if the error
+    /// has no span.
+    pub fn format_with_source(&self, source: &str) -> String {
+        let span = match self.span {
+            Some(span) => span,
+            None => return self.message.clone()
+        };
+        let (line, col) = span.line_col(source);
+        let text: Vec<char> = source.lines().nth(line - 1).unwrap_or("").chars().collect();
+        let first = if col > MAX_SNIPPET_WIDTH / 2 { col - 1 - MAX_SNIPPET_WIDTH / 2 } else { 0 };
+        let last = min(text.len(), first + MAX_SNIPPET_WIDTH);
+        let snippet: String = text[min(first, last)..last].iter().collect();
+        let span_len = source.get(span.start..span.end).map(|s| s.chars().count()).unwrap_or(0);
+        let carets = max(1, min(span_len, (last + 1).saturating_sub(col)));
+        format!("{}\n  {}\n  {}{}", self.message, snippet,
+            " ".repeat(col - 1 - first), "^".repeat(carets))
     }
 }
 
 impl fmt::Display for WeldError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.message)
     }
 }
 
 impl error::Error for WeldError {
-    fn description(&self) -> &str { &self.0 }
+    fn description(&self) -> &str { &self.message }
 
     fn cause(&self) -> Option<&error::Error> { None }
 }
 
 impl From<LlvmError> for WeldError {
     fn from(err: LlvmError) -> WeldError {
-        WeldError(err.to_string())
+        WeldError::with_message(ErrorKind::Codegen, err.to_string())
     }
 }
 
 /// Result type returned by Weld.
 pub type WeldResult<T> = Result<T, WeldError>;
+
+#[test]
+fn error_kinds() {
+    let err = WeldError::new("oops".to_string());
+    assert_eq!(err.kind(), ErrorKind::Internal);
+    let err = err.with_kind(ErrorKind::Type).with_kind(ErrorKind::Codegen);
+    assert_eq!(err.kind(), ErrorKind::Type);
+    assert!(err.kind().is_user_error());
+    assert_eq!(err.to_string(), "oops");
+
+    let err: WeldError = LlvmError::new("bad IR").into();
+    assert_eq!(err.kind(), ErrorKind::Codegen);
+    assert!(!err.kind().is_user_error());
+
+    let err = err.with_span(Span { start: 1, end: 2 }).with_run_id(7);
+    assert_eq!(err.span(), Some(Span { start: 1, end: 2 }));
+    assert_eq!(err.run_id(), Some(7));
+}
+
+#[test]
+fn source_locations() {
+    let source = "let a = 1;\nlet bb = (a b);\nbb";
+    assert_eq!(Span { start: 0, end: 3 }.line_col(source), (1, 1));
+    assert_eq!(Span { start: 11, end: 14 }.line_col(source), (2, 1));
+    assert_eq!(Span { start: 23, end: 24 }.line_col(source), (2, 13));
+    assert_eq!(Span { start: 29, end: 29 }.line_col(source), (3, 3));
+
+    let err = WeldError::new("Expected ')'".to_string()).with_span(Span { start: 23, end: 24 });
+    assert_eq!(err.format_with_source(source), "Expected ')'\n  let bb = (a b);\n              ^");
+    assert_eq!(err.with_source_location(source).to_string(), "Expected ')' at 2:13");
+
+    let err = WeldError::new("Unexpected token: x".to_string());
+    assert_eq!(err.clone().with_source_location(source), err);
+    assert_eq!(err.format_with_source(source), "Unexpected token: x");
+
+    // Long lines are clipped to a window around the error
+    let source = format!("{}bad{}", "a + ".repeat(50), " + a".repeat(50));
+    let err = WeldError::new("Bad".to_string()).with_span(Span { start: 200, end: 203 });
+    let formatted = err.format_with_source(&source);
+    let lines: Vec<&str> = formatted.lines().collect();
+    assert_eq!(lines[1].len(), 2 + MAX_SNIPPET_WIDTH);
+    assert_eq!(lines[2], format!("  {}^^^", " ".repeat(MAX_SNIPPET_WIDTH / 2)));
+    assert_eq!(&lines[1][2 + MAX_SNIPPET_WIDTH / 2..][..3], "bad");
+}
@@ -0,0 +1,873 @@
+//! A reference evaluator for typed expressions, used to check generated code.
+//!
+//! Unlike the tracing interpreter, this evaluator keeps no record of how it got its result; it
+//! is kept as simple as possible so that it can serve as the definition of what a program
+//! should compute. It shares only the interpreter's value types: operators, builtins, builders,
+//! decimal arithmetic, the calendar and the random number generator are implemented again here,
+//! so that a mistake in one of them shows up as a disagreement between the two instead of being
+//! copied into both. Where the interpreter is written for speed, this module picks the most
+//! direct formulation instead, e.g. walking through years and months to split up a date.
+//!
+//! `check_compiled` runs a program both here and through LLVM and reports any difference, which
+//! lets tests and fuzzers catch miscompilations without hand-written expected values.
+
+use std::cmp::Ordering;
+use std::ops::{Add, Div, Mul, Rem, Sub};
+use std::ptr;
+
+use super::ast::*;
+use super::ast::BinOpKind::*;
+use super::ast::ExprKind::*;
+use super::ast::ScalarKind::*;
+use super::conf::WeldConf;
+use super::error::*;
+use super::interpreter::{BuilderValue, Env, Stats, Value};
+use super::llvm::{compile_program, WeldModule};
+use super::parser::parse_program;
+use super::testgen::TestCase;
+
+#[cfg(test)] use super::parser::parse_expr;
+#[cfg(test)] use super::interpreter::trace_function;
+#[cfg(test)] use super::llvm::jit_available;
+#[cfg(test)] use super::random::RandomState;
+#[cfg(test)] use super::timestamp;
+#[cfg(test)] use super::testgen::{GenConf, ProgramGenerator};
+#[cfg(test)] use super::type_inference::infer_types;
+
+/// Evaluate a function on the given arguments.
+pub fn evaluate(func: &TypedExpr, args: &[Value]) -> WeldResult<Value> {
+    let mut random = Random::new();
+    let func = try!(eval(func, &Env::new(), &mut random));
+    call(&func, args.to_vec(), &mut random)
+}
+
+/// Evaluate an expression that does not refer to any free symbols.
+pub fn evaluate_closed(expr: &TypedExpr) -> WeldResult<Value> {
+    eval(expr, &Env::new(), &mut Random::new())
+}
+
+fn eval(expr: &TypedExpr, env: &Env, random: &mut Random) -> WeldResult<Value> {
+    let eval_all = |exprs: &Vec<TypedExpr>, random: &mut Random| -> WeldResult<Vec<Value>> {
+        exprs.iter().map(|e| eval(e, env, random)).collect()
+    };
+    match expr.kind {
+        BoolLiteral(v) => Ok(Value::Bool(v)),
+        I32Literal(v) => Ok(Value::I32(v)),
+        I64Literal(v) => Ok(Value::I64(v)),
+        F32Literal(v) => Ok(Value::F32(v)),
+        F64Literal(v) => Ok(Value::F64(v)),
+
+        Ident(ref symbol) => match env.get(symbol) {
+            Some(value) => Ok(value.clone()),
+            None => weld_err!(Runtime, "Undefined identifier: {}", symbol)
+        },
+
+        BinOp(op, ref left, ref right) =>
+            binop(op, &try!(eval(left, env, random)), &try!(eval(right, env, random))),
+
+        Let(ref symbol, ref value, ref body) => {
+            let mut env = env.clone();
+            env.insert(symbol.clone(), try!(eval(value, &env, random)));
+            eval(body, &env, random)
+        }
+
+        MakeStruct(ref exprs) => Ok(Value::Struct(try!(eval_all(exprs, random)))),
+
+        MakeVector(ref exprs) => Ok(Value::Vector(try!(eval_all(exprs, random)))),
+
+        GetField(ref param, index) => match try!(eval(param, env, random)) {
+            Value::Struct(mut values) if (index as usize) < values.len() =>
+                Ok(values.swap_remove(index as usize)),
+            other => weld_err!(Runtime, "Cannot get field {} of {}", index, other)
+        },
+
+        Lambda(ref params, ref body) => {
+            let params = params.iter().map(|p| p.name.clone()).collect();
+            Ok(Value::Function(params, body.clone(), env.clone()))
+        }
+
+        Apply(ref func, ref args) => {
+            let func = try!(eval(func, env, random));
+            call(&func, try!(eval_all(args, random)), random)
+        }
+
+        Builtin(kind, ref args) => {
+            let args = try!(eval_all(args, random));
+            builtin(kind, &expr.ty, args, random)
+        }
+
+        If(ref cond, ref on_true, ref on_false) => match try!(eval(cond, env, random)) {
+            Value::Bool(true) => eval(on_true, env, random),
+            Value::Bool(false) => eval(on_false, env, random),
+            other => weld_err!(Runtime, "If condition is not a bool: {}", other)
+        },
+
+        NewBuilder(ref arg) => {
+            let builder = match (&expr.ty, arg) {
+                (&Type::Builder(BuilderKind::Appender(_)), &None) =>
+                    BuilderValue::Appender(Vec::new()),
+                (&Type::Builder(BuilderKind::Merger(ref elem, op)), &None) =>
+                    BuilderValue::Merger(Box::new(try!(identity(elem, op))), op),
+                (&Type::Builder(BuilderKind::StatsMerger(ref elem)), &None) => {
+                    let zero = try!(zero(elem));
+                    BuilderValue::StatsMerger(Stats {
+                        count: 0,
+                        mean: 0.0,
+                        m2: 0.0,
+                        min: Box::new(zero.clone()),
+                        max: Box::new(zero),
+                    })
+                }
+                (&Type::Builder(BuilderKind::Histogram(_)), &Some(ref arg)) =>
+                    try!(histogram(try!(eval(arg, env, random)))),
+                (&Type::Builder(BuilderKind::MutVec(_)), &Some(ref arg)) =>
+                    match try!(eval(arg, env, random)) {
+                        Value::Vector(values) => BuilderValue::MutVec(values),
+                        other => return weld_err!(Runtime, "mutvec needs a vector, got {}", other)
+                    },
+                _ => return weld_err!(Runtime, "Invalid builder of type {:?}", expr.ty)
+            };
+            Ok(Value::Builder(builder))
+        }
+
+        Merge(ref builder, ref value) => {
+            let builder = try!(eval(builder, env, random));
+            merge(builder, try!(eval(value, env, random)))
+        }
+
+        Res(ref builder) => match try!(eval(builder, env, random)) {
+            Value::Builder(BuilderValue::Appender(values)) => Ok(Value::Vector(values)),
+            Value::Builder(BuilderValue::Merger(value, _)) => Ok(*value),
+            Value::Builder(BuilderValue::MutVec(values)) => Ok(Value::Vector(values)),
+            Value::Builder(BuilderValue::StatsMerger(stats)) => {
+                let variance = if stats.count == 0 { 0.0 } else { stats.m2 / stats.count as f64 };
+                Ok(Value::Struct(vec![Value::I64(stats.count), Value::F64(stats.mean),
+                    Value::F64(variance), *stats.min, *stats.max]))
+            }
+            Value::Builder(BuilderValue::Histogram(_, _, counts)) =>
+                Ok(Value::Vector(counts.into_iter().map(Value::I64).collect())),
+            other => weld_err!(Runtime, "Result called on non-builder {}", other)
+        },
+
+        For(ref data, ref builder, ref func) => {
+            let data = match try!(eval(data, env, random)) {
+                Value::Vector(values) => values,
+                other => return weld_err!(Runtime, "For loop over non-vector {}", other)
+            };
+            let mut builder = try!(eval(builder, env, random));
+            let func = try!(eval(func, env, random));
+            for (index, element) in data.into_iter().enumerate() {
+                random.enter_iteration(index);
+                builder = try!(call(&func, vec![builder, element], random));
+                random.exit_iteration();
+            }
+            Ok(builder)
+        }
+    }
+}
+
+fn call(func: &Value, args: Vec<Value>, random: &mut Random) -> WeldResult<Value> {
+    match *func {
+        Value::Function(ref params, ref body, ref captured) if params.len() == args.len() => {
+            let mut env = captured.clone();
+            env.extend(params.iter().cloned().zip(args));
+            eval(body, &env, random)
+        }
+        ref other => weld_err!(Runtime, "Cannot call {} on {} arguments", other, args.len())
+    }
+}
+
+/// Apply a binary operator to two scalars of the same type. Integer results are computed
+/// exactly and then truncated to the operands' width, which wraps them around on overflow.
+fn binop(op: BinOpKind, left: &Value, right: &Value) -> WeldResult<Value> {
+    match (left, right) {
+        (&Value::I32(l), &Value::I32(r)) =>
+            int_binop(op, l as i128, r as i128, |v| Value::I32(v as i32)),
+        (&Value::I64(l), &Value::I64(r)) =>
+            int_binop(op, l as i128, r as i128, |v| Value::I64(v as i64)),
+        (&Value::F32(l), &Value::F32(r)) => float_binop(op, l, r, Value::F32),
+        (&Value::F64(l), &Value::F64(r)) => float_binop(op, l, r, Value::F64),
+        (&Value::Bool(l), &Value::Bool(r)) => match op {
+            LogicalAnd | BitwiseAnd => Ok(Value::Bool(l & r)),
+            LogicalOr | BitwiseOr => Ok(Value::Bool(l | r)),
+            Xor => Ok(Value::Bool(l ^ r)),
+            _ => comparison(op, l.partial_cmp(&r))
+        },
+        (&Value::Timestamp(l), &Value::Timestamp(r)) => comparison(op, l.partial_cmp(&r)),
+        (&Value::Decimal(l, p, s), &Value::Decimal(r, rp, rs)) if (p, s) == (rp, rs) => {
+            let value = match op {
+                Add => store_decimal(l.wrapping_add(r), p),
+                Subtract => store_decimal(l.wrapping_sub(r), p),
+                Multiply | Divide if p > MAX_DECIMAL64_PRECISION =>
+                    return weld_err!(Runtime, "Cannot apply {} to decimals with more than {} \
+                        digits", op, MAX_DECIMAL64_PRECISION),
+                Multiply => store_decimal(round_div(l * r, power_of_ten(s)), p),
+                Divide if r == 0 => return weld_err!(Runtime, "Division by zero"),
+                Divide => store_decimal(round_div(l * power_of_ten(s), r), p),
+                _ => return comparison(op, l.partial_cmp(&r))
+            };
+            Ok(Value::Decimal(value, p, s))
+        }
+        _ => weld_err!(Runtime, "Cannot apply {} to {} and {}", op, left, right)
+    }
+}
+
+/// Apply an operator to two integers, which are at most 64 bits wide so that the exact result
+/// of any operator fits in an i128, and pass arithmetic results to `wrap`.
+fn int_binop<F>(op: BinOpKind, l: i128, r: i128, wrap: F) -> WeldResult<Value>
+        where F: Fn(i128) -> Value {
+    let value = match op {
+        Add => l + r,
+        Subtract => l - r,
+        Multiply => l * r,
+        Divide | Modulo if r == 0 => return weld_err!(Runtime, "Division by zero"),
+        Divide => l / r,
+        Modulo => l % r,
+        BitwiseAnd => l & r,
+        BitwiseOr => l | r,
+        Xor => l ^ r,
+        _ => return comparison(op, l.partial_cmp(&r))
+    };
+    Ok(wrap(value))
+}
+
+/// Apply an operator to two floats of the same width.
+fn float_binop<T, F>(op: BinOpKind, l: T, r: T, wrap: F) -> WeldResult<Value>
+        where T: Copy + PartialOrd + Add<Output=T> + Sub<Output=T> + Mul<Output=T> +
+            Div<Output=T> + Rem<Output=T>,
+              F: Fn(T) -> Value {
+    let value = match op {
+        Add => l + r,
+        Subtract => l - r,
+        Multiply => l * r,
+        Divide => l / r,
+        Modulo => l % r,
+        _ => return comparison(op, l.partial_cmp(&r))
+    };
+    Ok(wrap(value))
+}
+
+/// Apply a comparison operator given how its operands are ordered, or None if they are not
+/// ordered at all (because one is a NaN), in which case only `!=` holds.
+fn comparison(op: BinOpKind, order: Option<Ordering>) -> WeldResult<Value> {
+    let result = match op {
+        Equal => order == Some(Ordering::Equal),
+        NotEqual => order != Some(Ordering::Equal),
+        LessThan => order == Some(Ordering::Less),
+        LessThanOrEqual => order == Some(Ordering::Less) || order == Some(Ordering::Equal),
+        GreaterThan => order == Some(Ordering::Greater),
+        GreaterThanOrEqual => order == Some(Ordering::Greater) || order == Some(Ordering::Equal),
+        _ => return weld_err!(Runtime, "Operator {} is not supported on this type", op)
+    };
+    Ok(Value::Bool(result))
+}
+
+/// The zero of a numeric type.
+fn zero(ty: &Type) -> WeldResult<Value> {
+    match *ty {
+        Type::Scalar(I32) => Ok(Value::I32(0)),
+        Type::Scalar(I64) => Ok(Value::I64(0)),
+        Type::Scalar(F32) => Ok(Value::F32(0.0)),
+        Type::Scalar(F64) => Ok(Value::F64(0.0)),
+        Type::Scalar(Decimal(p, s)) => Ok(Value::Decimal(0, p, s)),
+        _ => weld_err!(Runtime, "Unsupported builder element type {:?}", ty)
+    }
+}
+
+/// The identity of a merger's operator, which is its initial value.
+fn identity(ty: &Type, op: BinOpKind) -> WeldResult<Value> {
+    match (op, ty) {
+        (Add, _) => zero(ty),
+        (Multiply, &Type::Scalar(I32)) => Ok(Value::I32(1)),
+        (Multiply, &Type::Scalar(I64)) => Ok(Value::I64(1)),
+        (Multiply, &Type::Scalar(F32)) => Ok(Value::F32(1.0)),
+        (Multiply, &Type::Scalar(F64)) => Ok(Value::F64(1.0)),
+        (Multiply, &Type::Scalar(Decimal(p, s))) => Ok(Value::Decimal(power_of_ten(s), p, s)),
+        (LogicalAnd, &Type::Scalar(Bool)) => Ok(Value::Bool(true)),
+        (LogicalOr, &Type::Scalar(Bool)) => Ok(Value::Bool(false)),
+        _ => weld_err!(Runtime, "Unsupported merger of {:?} with {}", ty, op)
+    }
+}
+
+/// The value of a number as an f64.
+fn to_f64(value: &Value) -> WeldResult<f64> {
+    match *value {
+        Value::I32(v) => Ok(v as f64),
+        Value::I64(v) => Ok(v as f64),
+        Value::F32(v) => Ok(v as f64),
+        Value::F64(v) => Ok(v),
+        Value::Decimal(v, _, scale) => Ok(v as f64 / power_of_ten(scale) as f64),
+        ref other => weld_err!(Runtime, "Expected a number, got {}", other)
+    }
+}
+
+/// Convert a number to another numeric type. Integers and decimals are converted exactly where
+/// they fit: dropped fractional digits are truncated and integer results wrap around. Floats are
+/// rounded to the nearest representable value, saturating when converted to integers.
+fn cast(kind: ScalarKind, value: &Value) -> WeldResult<Value> {
+    // Integers and decimals as an unscaled value and scale
+    let (v, scale) = match *value {
+        Value::I32(v) => (v as i128, 0),
+        Value::I64(v) => (v as i128, 0),
+        Value::Decimal(v, _, scale) => (v, scale),
+        Value::F32(x) => return cast_float(kind, x as f64),
+        Value::F64(x) => return cast_float(kind, x),
+        ref other => return weld_err!(Runtime, "Cannot cast {} to {}", other, kind)
+    };
+    match kind {
+        I32 => Ok(Value::I32((v / power_of_ten(scale)) as i32)),
+        I64 => Ok(Value::I64((v / power_of_ten(scale)) as i64)),
+        F32 if scale == 0 => Ok(Value::F32(v as f32)),
+        F64 if scale == 0 => Ok(Value::F64(v as f64)),
+        F32 => Ok(Value::F32((v as f64 / power_of_ten(scale) as f64) as f32)),
+        F64 => Ok(Value::F64(v as f64 / power_of_ten(scale) as f64)),
+        Decimal(p, s) if s >= scale =>
+            Ok(Value::Decimal(store_decimal(v.wrapping_mul(power_of_ten(s - scale)), p), p, s)),
+        Decimal(p, s) =>
+            Ok(Value::Decimal(store_decimal(round_div(v, power_of_ten(scale - s)), p), p, s)),
+        _ => weld_err!(Runtime, "Cannot cast {} to {}", value, kind)
+    }
+}
+
+/// Convert a float to another numeric type, as in `cast`.
+fn cast_float(kind: ScalarKind, x: f64) -> WeldResult<Value> {
+    match kind {
+        I32 => Ok(Value::I32(x as i32)),
+        I64 => Ok(Value::I64(x as i64)),
+        F32 => Ok(Value::F32(x as f32)),
+        F64 => Ok(Value::F64(x)),
+        Decimal(p, s) => {
+            let v = (x * power_of_ten(s) as f64).round() as i128;
+            Ok(Value::Decimal(store_decimal(v, p), p, s))
+        }
+        _ => weld_err!(Runtime, "Cannot cast {} to {}", x, kind)
+    }
+}
+
+/// 10^exp, for exp up to 38.
+fn power_of_ten(exp: u8) -> i128 {
+    let mut result = 1;
+    for _ in 0..exp {
+        result *= 10;
+    }
+    result
+}
+
+/// Truncate the unscaled value of a decimal with the given precision to the integer it is
+/// stored in: an i64 for at most 18 digits and an i128 otherwise.
+fn store_decimal(value: i128, precision: u8) -> i128 {
+    if precision > MAX_DECIMAL64_PRECISION { value } else { value as i64 as i128 }
+}
+
+/// n / d rounded to the nearest integer, with ties away from zero. The quotient of the
+/// magnitudes is rounded up when the remainder is at least half the divisor and then given the
+/// sign of the exact result.
+fn round_div(n: i128, d: i128) -> i128 {
+    let (n_abs, d_abs) = (n.unsigned_abs(), d.unsigned_abs());
+    let mut quotient = n_abs / d_abs;
+    if 2 * (n_abs % d_abs) >= d_abs {
+        quotient += 1;
+    }
+    if (n < 0) != (d < 0) { (quotient as i128).wrapping_neg() } else { quotient as i128 }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_year(year: i64) -> i64 {
+    if is_leap_year(year) { 366 } else { 365 }
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+/// The (year, month, day of the month, day of the year) of a number of days since 1970-01-01,
+/// all but the year counted from 1. Whole 400-year cycles of 146097 days are counted from
+/// 2000-01-01, where one starts, and the rest is walked through a year and a month at a time.
+fn civil_date(days: i64) -> (i64, i64, i64, i64) {
+    let days = days - 10957;
+    let mut year = 2000 + days.div_euclid(146097) * 400;
+    let mut rest = days.rem_euclid(146097);
+    while rest >= days_in_year(year) {
+        rest -= days_in_year(year);
+        year += 1;
+    }
+    let day_of_year = rest + 1;
+    let mut month = 1;
+    while rest >= days_in_month(year, month) {
+        rest -= days_in_month(year, month);
+        month += 1;
+    }
+    (year, month, rest + 1, day_of_year)
+}
+
+const MICROS_PER_HOUR: i64 = 3_600_000_000;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+/// The year, month, day of the month or hour of a timestamp.
+fn extract(unit: TimeUnit, micros: i64) -> i32 {
+    let (year, month, day, _) = civil_date(micros.div_euclid(MICROS_PER_DAY));
+    let value = match unit {
+        TimeUnit::Year => year,
+        TimeUnit::Month => month,
+        TimeUnit::Day => day,
+        TimeUnit::Hour => micros.rem_euclid(MICROS_PER_DAY) / MICROS_PER_HOUR,
+    };
+    value as i32
+}
+
+/// The first microsecond of a timestamp's year, month, day or hour, which is an error if it is
+/// before the earliest timestamp.
+fn trunc(unit: TimeUnit, micros: i64) -> WeldResult<i64> {
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let (_, _, day, day_of_year) = civil_date(days);
+    let start = match unit {
+        TimeUnit::Year => (days - (day_of_year - 1)).checked_mul(MICROS_PER_DAY),
+        TimeUnit::Month => (days - (day - 1)).checked_mul(MICROS_PER_DAY),
+        TimeUnit::Day => days.checked_mul(MICROS_PER_DAY),
+        TimeUnit::Hour => micros.div_euclid(MICROS_PER_HOUR).checked_mul(MICROS_PER_HOUR),
+    };
+    match start {
+        Some(start) => Ok(start),
+        None => weld_err!(Runtime, "Truncating {} to its {:?} is out of range", micros, unit)
+    }
+}
+
+/// The Philox4x32-10 block function: ten rounds that multiply the first and third words of the
+/// counter by fixed constants and mix the halves of the products with the other two words and
+/// the key, which is bumped after every round.
+fn philox(counter: [u32; 4], key: [u32; 2]) -> [u32; 4] {
+    let (mut x, mut k) = (counter, key);
+    for _ in 0..10 {
+        let a = 0xD2511F53u64 * x[0] as u64;
+        let b = 0xCD9E8D57u64 * x[2] as u64;
+        x = [(b >> 32) as u32 ^ x[1] ^ k[0], b as u32, (a >> 32) as u32 ^ x[3] ^ k[1], a as u32];
+        k = [k[0].wrapping_add(0x9E3779B9), k[1].wrapping_add(0xBB67AE85)];
+    }
+    x
+}
+
+/// Where the next random number comes from.
+struct Random {
+    /// The index of each enclosing loop iteration, outermost first, and the draws made in it.
+    loops: Vec<(usize, u64)>,
+    /// The draws made outside of any loop.
+    outside: u64,
+}
+
+impl Random {
+    fn new() -> Random {
+        Random { loops: Vec::new(), outside: 0 }
+    }
+
+    fn enter_iteration(&mut self, index: usize) {
+        self.loops.push((index, 0));
+    }
+
+    fn exit_iteration(&mut self) {
+        self.loops.pop();
+    }
+
+    /// Draw a number in [0, 1) for a seed. The Philox counter holds the draws made so far in the
+    /// innermost iteration and a 64-bit hash of the enclosing iterations' indices, the key is the
+    /// seed, and the top 53 bits of the first two output words become the number.
+    fn next(&mut self, seed: i64) -> f64 {
+        let path = self.loops.iter().fold(0u64, |path, &(index, _)| {
+            path.wrapping_mul(0x100000001B3) ^ (index as u64 + 1)
+        });
+        let draws = match self.loops.last_mut() {
+            Some(last) => &mut last.1,
+            None => &mut self.outside
+        };
+        let counter = [*draws as u32, (*draws >> 32) as u32, path as u32, (path >> 32) as u32];
+        *draws += 1;
+        let out = philox(counter, [seed as u32, (seed as u64 >> 32) as u32]);
+        let bits = (out[0] as u64) << 21 | (out[1] as u64) >> 11;
+        bits as f64 / 9007199254740992.0
+    }
+}
+
+/// The elements of a vector of floats, in double precision.
+fn floats(value: &Value) -> WeldResult<Vec<f64>> {
+    match *value {
+        Value::Vector(ref values) => values.iter().map(|v| match *v {
+            Value::F32(x) => Ok(x as f64),
+            Value::F64(x) => Ok(x),
+            ref other => weld_err!(Runtime, "Expected a float, got {}", other)
+        }).collect(),
+        ref other => weld_err!(Runtime, "Expected a vector of floats, got {}", other)
+    }
+}
+
+/// Call a builtin with result type `ty`. dot and gemm sum their products in double precision and
+/// round f32 results at the end.
+fn builtin(kind: BuiltinKind, ty: &Type, args: Vec<Value>, random: &mut Random)
+        -> WeldResult<Value> {
+    let float = |x: f64| match *ty {
+        Type::Scalar(F32) => Value::F32(x as f32),
+        Type::Vector(ref elem) if **elem == Type::Scalar(F32) => Value::F32(x as f32),
+        _ => Value::F64(x)
+    };
+    match (kind, &args[..]) {
+        (BuiltinKind::Dot, &[ref a, ref b]) => {
+            let (a, b) = (try!(floats(a)), try!(floats(b)));
+            if a.len() != b.len() {
+                return weld_err!(Runtime, "dot of vectors of lengths {} and {}", a.len(), b.len());
+            }
+            let mut sum = 0.0;
+            for i in 0..a.len() {
+                sum += a[i] * b[i];
+            }
+            Ok(float(sum))
+        }
+        (BuiltinKind::Gemm, &[ref a, ref b, Value::I64(m), Value::I64(n), Value::I64(k)]) => {
+            let (a, b) = (try!(floats(a)), try!(floats(b)));
+            let valid = m >= 0 && n >= 0 && k >= 0 && m.checked_mul(k) == Some(a.len() as i64) &&
+                k.checked_mul(n) == Some(b.len() as i64);
+            if !valid {
+                return weld_err!(Runtime, "gemm of {} x {} and {} x {} matrices got vectors of \
+                    lengths {} and {}", m, k, k, n, a.len(), b.len());
+            }
+            let mut result = Vec::new();
+            let len = m.checked_mul(n);
+            if len.map_or(true, |len| result.try_reserve_exact(len as usize).is_err()) {
+                return weld_err!(Runtime, "gemm result of {} x {} is too large", m, n);
+            }
+            let (m, n, k) = (m as usize, n as usize, k as usize);
+            for row in 0..m {
+                for col in 0..n {
+                    let mut sum = 0.0;
+                    for i in 0..k {
+                        sum += a[row * k + i] * b[i * n + col];
+                    }
+                    result.push(float(sum));
+                }
+            }
+            Ok(Value::Vector(result))
+        }
+        (BuiltinKind::Rand, &[]) => Ok(Value::F64(random.next(0))),
+        (BuiltinKind::RandSeeded, &[Value::I64(seed)]) => Ok(Value::F64(random.next(seed))),
+        (BuiltinKind::Cast(kind), &[ref value]) => cast(kind, value),
+        (BuiltinKind::ToTimestamp, &[Value::I64(micros)]) => Ok(Value::Timestamp(micros)),
+        (BuiltinKind::ToMicros, &[Value::Timestamp(t)]) => Ok(Value::I64(t)),
+        (BuiltinKind::Extract(unit), &[Value::Timestamp(t)]) => Ok(Value::I32(extract(unit, t))),
+        (BuiltinKind::Trunc(unit), &[Value::Timestamp(t)]) => trunc(unit, t).map(Value::Timestamp),
+        (BuiltinKind::AddInterval, &[Value::Timestamp(t), Value::I64(micros)]) =>
+            Ok(Value::Timestamp(t.wrapping_add(micros))),
+        (BuiltinKind::IntervalBetween, &[Value::Timestamp(start), Value::Timestamp(end)]) =>
+            Ok(Value::I64(end.wrapping_sub(start))),
+        _ => weld_err!(Runtime, "Invalid arguments to {}", kind)
+    }
+}
+
+/// Create a histogram builder from its {lo, hi, nbins} argument.
+fn histogram(args: Value) -> WeldResult<BuilderValue> {
+    let (lo, hi, nbins) = match args {
+        Value::Struct(ref fields) if fields.len() == 3 => match fields[2] {
+            Value::I64(nbins) => (try!(to_f64(&fields[0])), try!(to_f64(&fields[1])), nbins),
+            _ => return weld_err!(Runtime, "Invalid histogram arguments {}", args)
+        },
+        _ => return weld_err!(Runtime, "Invalid histogram arguments {}", args)
+    };
+    if nbins <= 0 || !(lo < hi) {
+        return weld_err!(Runtime, "histogram needs lo < hi and at least one bin, got {}", args);
+    }
+    let mut counts = Vec::new();
+    if counts.try_reserve_exact(nbins as usize).is_err() {
+        return weld_err!(Runtime, "histogram of {} bins is too large", nbins);
+    }
+    counts.resize(nbins as usize, 0);
+    Ok(BuilderValue::Histogram(lo, hi, counts))
+}
+
+/// Merge a value into a builder, returning the new builder.
+fn merge(builder: Value, value: Value) -> WeldResult<Value> {
+    let merged = match builder {
+        Value::Builder(BuilderValue::Appender(mut values)) => {
+            values.push(value);
+            BuilderValue::Appender(values)
+        }
+        Value::Builder(BuilderValue::Merger(current, op)) =>
+            BuilderValue::Merger(Box::new(try!(binop(op, &current, &value))), op),
+        Value::Builder(BuilderValue::MutVec(mut values)) => {
+            let (index, element) = match value {
+                Value::Struct(ref fields) if fields.len() == 2 => match fields[0] {
+                    Value::I64(index) => (index, fields[1].clone()),
+                    _ => return weld_err!(Runtime, "mutvec merges must be {{i64, value}}")
+                },
+                _ => return weld_err!(Runtime, "mutvec merges must be {{i64, value}}")
+            };
+            if index < 0 || index as usize >= values.len() {
+                return weld_err!(Runtime, "mutvec index {} out of bounds for length {}",
+                    index, values.len());
+            }
+            values[index as usize] = element;
+            BuilderValue::MutVec(values)
+        }
+        Value::Builder(BuilderValue::StatsMerger(stats)) => {
+            // Welford's algorithm, with the first value taken as is
+            let x = try!(to_f64(&value));
+            let count = stats.count + 1;
+            let merged = if stats.count == 0 {
+                Stats {
+                    count: count,
+                    mean: x,
+                    m2: 0.0,
+                    min: Box::new(value.clone()),
+                    max: Box::new(value),
+                }
+            } else {
+                let delta = x - stats.mean;
+                let is_min = try!(binop(LessThan, &value, &stats.min)) == Value::Bool(true);
+                let is_max = try!(binop(GreaterThan, &value, &stats.max)) == Value::Bool(true);
+                Stats {
+                    count: count,
+                    mean: stats.mean + delta / count as f64,
+                    m2: stats.m2 + delta * delta * stats.count as f64 / count as f64,
+                    min: if is_min { Box::new(value.clone()) } else { stats.min },
+                    max: if is_max { Box::new(value) } else { stats.max },
+                }
+            };
+            BuilderValue::StatsMerger(merged)
+        }
+        Value::Builder(BuilderValue::Histogram(lo, hi, mut counts)) => {
+            // Each bin includes its lower bound, and the last one includes hi as well
+            let x = try!(to_f64(&value));
+            if lo <= x && x <= hi {
+                let last = counts.len() - 1;
+                let bin = ((x - lo) / (hi - lo) * counts.len() as f64).floor() as usize;
+                counts[if bin > last { last } else { bin }] += 1;
+            }
+            BuilderValue::Histogram(lo, hi, counts)
+        }
+        other => return weld_err!(Runtime, "Merge called on non-builder {}", other)
+    };
+    Ok(Value::Builder(merged))
+}
+
+/// Compile a generated test case with LLVM, run it, and check that it gives the same result as
+/// the reference evaluator. Only programs whose parameters and result are scalars can be run
+/// this way for now.
+pub fn check_compiled(case: &TestCase, conf: &WeldConf) -> WeldResult<()> {
+    let mut args = Vec::new();
+    for arg in &case.args {
+        args.push(try!(evaluate_closed(arg)));
+    }
+    let expected = try!(evaluate(&case.program, &args));
+    let program = try!(parse_program(&case.program_source()));
+    let module = try!(compile_program(&program, conf));
+    let actual = try!(run_scalar(&module, &args));
+    if !same_result(&actual, &expected) {
+        return weld_err!(Internal, "Compiled program returned {} but the reference evaluator \
+            returned {}\nProgram: {}", actual, expected, case.apply_source());
+    }
+    Ok(())
+}
+
+/// Whether a compiled program's result matches the reference. Floats must be equal, except that
+/// any NaN matches any other, since LLVM does not preserve the sign or payload of NaNs.
+fn same_result(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (&Value::F32(a), &Value::F32(b)) => a == b || a.is_nan() && b.is_nan(),
+        (&Value::F64(a), &Value::F64(b)) => a == b || a.is_nan() && b.is_nan(),
+        _ => actual == expected
+    }
+}
+
+/// The size and alignment of a scalar in generated code, which match the C ABI.
+fn scalar_layout(value: &Value) -> WeldResult<(usize, usize)> {
+    match *value {
+        Value::Bool(_) => Ok((1, 1)),
+        Value::I32(_) | Value::F32(_) => Ok((4, 4)),
+        Value::I64(_) | Value::F64(_) | Value::Timestamp(_) => Ok((8, 8)),
+        Value::Decimal(_, precision, _) if precision <= MAX_DECIMAL64_PRECISION => Ok((8, 8)),
+        Value::Decimal(_, _, _) => Ok((16, 16)),
+        ref other => weld_err!(Runtime, "Only scalar arguments are supported, got {}", other)
+    }
+}
+
+/// Run a compiled module on scalar arguments, laying them out as the struct of parameters it
+/// expects, and read back its scalar result.
+fn run_scalar(module: &WeldModule, args: &[Value]) -> WeldResult<Value> {
+    // Use u128s to get 16-byte alignment for the argument struct
+    let mut buffer = vec![0u128; args.len() + 1];
+    let base = buffer.as_mut_ptr() as *mut u8;
+    let mut offset = 0;
+    for arg in args {
+        let (size, align) = try!(scalar_layout(arg));
+        offset = (offset + align - 1) / align * align;
+        unsafe {
+            let dest = base.offset(offset as isize);
+            match *arg {
+                Value::Bool(v) => *dest = v as u8,
+                Value::I32(v) => ptr::copy_nonoverlapping(&v as *const i32 as *const u8, dest, 4),
+                Value::I64(v) | Value::Timestamp(v) =>
+                    ptr::copy_nonoverlapping(&v as *const i64 as *const u8, dest, 8),
+                Value::Decimal(v, _, _) if size == 8 => ptr::copy_nonoverlapping(
+                    &(v as i64) as *const i64 as *const u8, dest, 8),
+                Value::Decimal(v, _, _) =>
+                    ptr::copy_nonoverlapping(&v as *const i128 as *const u8, dest, 16),
+                Value::F32(v) => ptr::copy_nonoverlapping(&v as *const f32 as *const u8, dest, 4),
+                Value::F64(v) => ptr::copy_nonoverlapping(&v as *const f64 as *const u8, dest, 8),
+                _ => unreachable!()
+            }
+        }
+        offset += size;
+    }
+    let result = module.run(base as i64) as *mut u8;
+    let value = unsafe {
+        match *module.return_type() {
+            Type::Scalar(Bool) => Value::Bool(*result & 1 != 0),
+            Type::Scalar(I32) => Value::I32(ptr::read_unaligned(result as *const i32)),
+            Type::Scalar(I64) => Value::I64(ptr::read_unaligned(result as *const i64)),
+            Type::Scalar(F32) => Value::F32(ptr::read_unaligned(result as *const f32)),
+            Type::Scalar(F64) => Value::F64(ptr::read_unaligned(result as *const f64)),
+            Type::Scalar(Timestamp) =>
+                Value::Timestamp(ptr::read_unaligned(result as *const i64)),
+            Type::Scalar(Decimal(p, s)) if p <= MAX_DECIMAL64_PRECISION =>
+                Value::Decimal(ptr::read_unaligned(result as *const i64) as i128, p, s),
+            Type::Scalar(Decimal(p, s)) =>
+                Value::Decimal(ptr::read_unaligned(result as *const i128), p, s),
+            ref other => {
+                return weld_err!(Runtime, "Only scalar results are supported, got {:?}", other)
+            }
+        }
+    };
+    unsafe { free(result as *mut ::std::os::raw::c_void) };
+    Ok(value)
+}
+
+extern "C" {
+    fn free(ptr: *mut ::std::os::raw::c_void);
+}
+
+#[cfg(test)]
+fn typed_expr(code: &str) -> TypedExpr {
+    let mut e = parse_expr(code).unwrap();
+    infer_types(&mut e).unwrap();
+    e.to_typed().unwrap()
+}
+
+#[test]
+fn evaluate_programs() {
+    let func = typed_expr("|x:i32, y:i64| let z = y * 2L; if(x > 1, z + 1L, z)");
+    assert_eq!(evaluate(&func, &[Value::I32(2), Value::I64(20)]).unwrap(), Value::I64(41));
+    assert_eq!(evaluate(&func, &[Value::I32(0), Value::I64(20)]).unwrap(), Value::I64(40));
+
+    let func = typed_expr("|v:vec[f32]| let f = (|a:f32| a * 2.0f); \
+        result(for(v, appender, |b,x| merge(b, {(f)(x), x > 1.0f})))");
+    let args = [Value::Vector(vec![Value::F32(0.5), Value::F32(1.5)])];
+    assert_eq!(evaluate(&func, &args).unwrap().to_string(), "[{1.0F,false},{3.0F,true}]");
+
+    let func = typed_expr("|v:vec[f32]| result(for(v, statsmerger, |b,x| merge(b, x)))");
+    let args = [Value::Vector(vec![Value::F32(3.0), Value::F32(1.0)])];
+    assert_eq!(evaluate(&func, &args).unwrap().to_string(), "{2L,2.0,1.0,1.0F,3.0F}");
+
+    let func = typed_expr("|v:vec[i32]| result(for(v, histogram(0, 10, 2L), |b,x| merge(b, x)))");
+    let args = [Value::Vector(vec![Value::I32(3), Value::I32(5), Value::I32(10)])];
+    assert_eq!(evaluate(&func, &args).unwrap().to_string(), "[1L,2L]");
+
+    let func = typed_expr("|t:timestamp| if(trunc_hour(t) == t, extract_hour(t), 0)");
+    let args = [Value::Timestamp(1489503600000000)];
+    assert_eq!(evaluate(&func, &args).unwrap(), Value::I32(15));
+
+    let func = typed_expr("|a:decimal[12,4], b:decimal[12,4]| i64(a * b * decimal[12,4](10000))");
+    let args = [Value::Decimal(15000, 12, 4), Value::Decimal(333, 12, 4)];
+    assert_eq!(evaluate(&func, &args).unwrap(), Value::I64(500));
+
+    let expr = typed_expr("let a = [1L, 2L]; let b = {a, 3}; b.$1");
+    assert_eq!(evaluate_closed(&expr).unwrap(), Value::I32(3));
+
+    let func = typed_expr("|x:i32| x / 0");
+    assert_eq!(evaluate(&func, &[Value::I32(1)]).unwrap_err().kind(), ErrorKind::Runtime);
+}
+
+#[test]
+fn evaluate_like_interpreter() {
+    // Programs that generated test cases do not cover, checked against the tracing interpreter
+    let v = Value::Vector([1.5, -2.0, 4.0, 0.5].iter().map(|x| Value::F64(*x)).collect());
+    let cases = [
+        "|v:vec[f64]| {dot(v, v), gemm(v, v, 2L, 2L, 2L), gemm(v, v, 4L, 4L, 1L)}",
+        "|v:vec[f64]| let x = 0.0 - 2.75; {i32(x), i64(x), f32(x), decimal[10,1](x), f64(7L), \
+            decimal[4,1](decimal[5,2](x)), i64(decimal[5,2](x))}",
+        "|v:vec[f64]| result(for(v, statsmerger, |b,x| merge(b, x)))",
+        "|v:vec[f64]| result(for(v, histogram(0.0 - 2.0, 4.0, 3L), |b,x| merge(b, x)))",
+        "|v:vec[f64]| result(for(v, mutvec(v), |b,x| merge(b, {1L, x})))",
+        "|v:vec[f64]| let d = decimal[12,3](1.5); \
+            {d * d, d / decimal[12,3](0.25), d - d > d, f64(d), i32(d)}",
+        "|v:vec[f64]| {(0 - 2147483647 - 1) / (0 - 1), (0 - 7) % 2, 7L ^ 3L, 2147483647 + 1, \
+            (0L - 9223372036854775807L) * 3L}",
+        "|v:vec[f64]| {0.0 / 0.0 == 0.0 / 0.0, 0.0 / 0.0 != 0.0 / 0.0, 1.0f / 3.0f, 5.5 % 2.0}",
+        "|v:vec[f64]| {rand(), rand_seeded(3L), result(for(v, appender, |b,x| merge(b, rand())))}",
+        "|v:vec[f64]| let t = to_timestamp(1489503600000000L); \
+            {extract_year(t), trunc_day(t), to_micros(add_interval(t, 5L)), \
+            interval_between(t, t)}",
+    ];
+    for code in cases.iter() {
+        let func = typed_expr(code);
+        let args = [v.clone()];
+        let expected = trace_function(&func, &args).unwrap().0;
+        assert_eq!(evaluate(&func, &args).unwrap(), expected, "{}", code);
+    }
+}
+
+#[test]
+fn evaluate_generated_programs() {
+    // The reference evaluator must agree with the tracing interpreter
+    for seed in 0..200 {
+        let case = ProgramGenerator::new(seed, GenConf::new()).test_case();
+        let args: Vec<Value> = case.args.iter().map(|a| evaluate_closed(a).unwrap()).collect();
+        let expected = trace_function(&case.program, &args).unwrap().0;
+        assert_eq!(evaluate(&case.program, &args).unwrap(), expected, "{}", case.apply_source());
+    }
+}
+
+#[test]
+fn calendar_and_random_match_interpreter() {
+    let units = [TimeUnit::Year, TimeUnit::Month, TimeUnit::Day, TimeUnit::Hour];
+    let mut times = vec![i64::min_value(), i64::min_value() + MICROS_PER_DAY, i64::max_value()];
+    times.extend((-2000..2000).map(|i| i * 7919 * MICROS_PER_HOUR + i * 104729));
+    for &t in &times {
+        for &unit in &units {
+            assert_eq!(extract(unit, t), timestamp::extract(unit, t), "{} {:?}", t, unit);
+            assert_eq!(trunc(unit, t).ok(), timestamp::trunc(unit, t).ok(), "{} {:?}", t, unit);
+        }
+    }
+
+    let (mut random, mut state) = (Random::new(), RandomState::new());
+    assert_eq!(random.next(7), state.next(7));
+    for i in 0..3 {
+        random.enter_iteration(i);
+        state.enter_iteration(i);
+        for j in 0..3 {
+            random.enter_iteration(j);
+            state.enter_iteration(j);
+            assert_eq!(random.next(-1), state.next(-1));
+            assert_eq!(random.next(i as i64), state.next(i as i64));
+            random.exit_iteration();
+            state.exit_iteration();
+        }
+        assert_eq!(random.next(0), state.next(0));
+        random.exit_iteration();
+        state.exit_iteration();
+    }
+}
+
+#[test]
+fn compiled_programs_match_reference() {
+    if !jit_available() {
+        eprintln!("Skipping: LLVM rejects the generated code");
+        return;
+    }
+    for seed in 0..50 {
+        let case = ProgramGenerator::new(seed, GenConf::scalar()).test_case();
+        check_compiled(&case, &WeldConf::new()).unwrap();
+    }
+}
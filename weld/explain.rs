@@ -0,0 +1,187 @@
+//! Reports on which optimizations were applied to a program and why others were not.
+//!
+//! `explain_program` runs the same transforms as `compile_program` and records a `Decision` for
+//! every transform Weld knows about, so that users can see how their code was rewritten and what
+//! kept a transform from firing.
+
+use std::fmt;
+
+use super::ast::Expr;
+use super::ast::ExprKind::*;
+use super::conf::*;
+use super::error::*;
+use super::macro_processor;
+use super::program::Program;
+use super::transforms;
+
+#[cfg(test)] use super::parser::parse_program;
+
+/// What happened when a transform was considered for a program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    /// The transform changed the program; the string describes what it did.
+    Applied(String),
+    /// The transform ran but found nothing to change; the string says why.
+    NotApplied(String),
+    /// The transform was not run, for the reason given.
+    Skipped(String),
+}
+
+/// The decision made for one transform.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformReport {
+    pub name: String,
+    pub decision: Decision,
+}
+
+/// A report for every known transform, with the transforms that ran listed first, in the order
+/// they ran.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExplainReport {
+    pub transforms: Vec<TransformReport>,
+}
+
+impl ExplainReport {
+    /// Get the report for the transform with the given name.
+    pub fn get(&self, name: &str) -> Option<&TransformReport> {
+        self.transforms.iter().find(|t| t.name == name)
+    }
+}
+
+impl fmt::Display for ExplainReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for t in &self.transforms {
+            let (status, reason) = match t.decision {
+                Decision::Applied(ref r) => ("applied", r),
+                Decision::NotApplied(ref r) => ("not applied", r),
+                Decision::Skipped(ref r) => ("skipped", r),
+            };
+            try!(writeln!(f, "{}: {} ({})", t.name, status, reason));
+        }
+        Ok(())
+    }
+}
+
+/// Apply the configured transforms to a program, as `compile_program` would, and report what
+/// each of them did.
+pub fn explain_program(program: &Program, conf: &WeldConf) -> WeldResult<ExplainReport> {
+    let conf = try!(CompileConf::parse(conf));
+    let mut expr = try!(macro_processor::process_program(program));
+    let mut reports = Vec::new();
+    for pass in &conf.optimization_passes {
+        let decision = try!(explain_pass(pass, &mut expr));
+        reports.push(TransformReport { name: pass.clone(), decision: decision });
+    }
+    for name in TRANSFORM_NAMES {
+        if !conf.optimization_passes.iter().any(|p| p == name) {
+            reports.push(TransformReport {
+                name: name.to_string(),
+                decision: Decision::Skipped(format!("not listed in {}", OPTIMIZATION_PASSES_KEY)),
+            });
+        }
+    }
+    Ok(ExplainReport { transforms: reports })
+}
+
+/// Run one transform on `expr` and describe its effect.
+fn explain_pass<T: Clone>(name: &str, expr: &mut Expr<T>) -> WeldResult<Decision> {
+    match name {
+        "inline-apply" => {
+            let inlined = try!(transforms::inline_apply_counted(expr));
+            let (remaining, remaining_lambdas, random) = count_applies(expr);
+            let not_inlined = remaining - remaining_lambdas - random;
+            let random_reason = format!("{} call(s) of lambdas were kept because their \
+                arguments draw random numbers", random);
+            Ok(if inlined > 0 {
+                let mut reason = format!("inlined {} call(s) of lambda expressions", inlined);
+                if not_inlined > 0 {
+                    reason.push_str(&format!("; {} call(s) of other functions were kept",
+                        not_inlined));
+                }
+                if random > 0 {
+                    reason.push_str(&format!("; {}", random_reason));
+                }
+                if remaining_lambdas > 0 {
+                    reason.push_str(&format!("; {} call(s) of lambdas introduced by inlining \
+                        remain", remaining_lambdas));
+                }
+                Decision::Applied(reason)
+            } else if random > 0 {
+                Decision::NotApplied(random_reason)
+            } else if not_inlined > 0 {
+                Decision::NotApplied(format!("{} call(s) found, but none of them call a lambda \
+                    expression directly", not_inlined))
+            } else {
+                Decision::NotApplied("the program has no function calls".to_string())
+            })
+        }
+        _ => {
+            try!(transforms::apply_pass(name, expr));
+            Ok(Decision::Applied("no details available".to_string()))
+        }
+    }
+}
+
+/// Count the Apply nodes in an expression, how many of them apply a Lambda, and how many apply
+/// a Lambda to arguments that draw random numbers (which inlining leaves alone).
+fn count_applies<T: Clone>(expr: &Expr<T>) -> (usize, usize, usize) {
+    let mut counts = (0, 0, 0);
+    add_applies(expr, &mut counts);
+    counts
+}
+
+/// Add the Apply nodes in `expr` to `counts` as in `count_applies`, returning whether `expr`
+/// draws random numbers.
+fn add_applies<T: Clone>(expr: &Expr<T>, counts: &mut (usize, usize, usize)) -> bool {
+    let random: Vec<bool> = expr.children().map(|c| add_applies(c, counts)).collect();
+    if let Apply(ref func, _) = expr.kind {
+        counts.0 += 1;
+        if let Lambda(_, _) = func.kind {
+            if random[1..].contains(&true) {
+                counts.2 += 1;
+            } else {
+                counts.1 += 1;
+            }
+        }
+    }
+    random.contains(&true) || transforms::builtin_is_random(expr)
+}
+
+#[test]
+fn explain_inlining() {
+    let conf = WeldConf::new();
+    let program = parse_program("|v:vec[i32]| map(v, |x| (|y| y + 1)(x))").unwrap();
+    let report = explain_program(&program, &conf).unwrap();
+    assert_eq!(report.get("inline-apply").unwrap().decision,
+        Decision::Applied("inlined 2 call(s) of lambda expressions".to_string()));
+    assert_eq!(report.to_string(),
+        "inline-apply: applied (inlined 2 call(s) of lambda expressions)\n");
+
+    let program = parse_program("|f:i32, a:i32| (f)((|y| y)(a))").unwrap();
+    let report = explain_program(&program, &conf).unwrap();
+    assert_eq!(report.get("inline-apply").unwrap().decision, Decision::Applied(
+        "inlined 1 call(s) of lambda expressions; 1 call(s) of other functions were kept"
+        .to_string()));
+
+    let program = parse_program("|f:i32, a:i32| (f)(a)").unwrap();
+    let report = explain_program(&program, &conf).unwrap();
+    assert_eq!(report.get("inline-apply").unwrap().decision, Decision::NotApplied(
+        "1 call(s) found, but none of them call a lambda expression directly".to_string()));
+
+    let program = parse_program("|a:i32| (|y| y + y)(rand())").unwrap();
+    let report = explain_program(&program, &conf).unwrap();
+    assert_eq!(report.get("inline-apply").unwrap().decision, Decision::NotApplied(
+        "1 call(s) of lambdas were kept because their arguments draw random numbers"
+        .to_string()));
+
+    let program = parse_program("|a:i32| a + 1").unwrap();
+    let report = explain_program(&program, &conf).unwrap();
+    assert_eq!(report.get("inline-apply").unwrap().decision,
+        Decision::NotApplied("the program has no function calls".to_string()));
+
+    let mut conf = WeldConf::new();
+    conf.set(OPTIMIZATION_PASSES_KEY, "");
+    let report = explain_program(&program, &conf).unwrap();
+    assert_eq!(report.get("inline-apply").unwrap().decision,
+        Decision::Skipped("not listed in weld.optimization.passes".to_string()));
+}
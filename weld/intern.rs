@@ -0,0 +1,153 @@
+//! Interned identifier names.
+//!
+//! Every distinct identifier is stored once in a global table and referred to by a `Name`, which
+//! holds a small integer ID for comparing and hashing along with the interned string itself, so
+//! comparing, hashing, cloning and printing names (and hence symbols and expressions) never
+//! copies string data or takes a lock. Only interning a new string locks the table.
+//!
+//! The table is global rather than scoped to a compilation because names end up in values that
+//! outlive one: cached modules, expressions held by the REPL and errors returned to callers.
+//! Interned strings are therefore never freed. A process that compiles many programs with
+//! distinct identifiers could grow the table without limit, so the tokenizer interns through
+//! `Name::intern`, which refuses to add names once their text takes up `MAX_INTERNED_BYTES`.
+//! Programs that only use names interned before then still compile.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::RwLock;
+
+use super::error::*;
+
+/// The most bytes of identifier text that `Name::intern` will add to the table (64 MiB). This is
+/// a limit for the whole process, since the table is never emptied.
+pub const MAX_INTERNED_BYTES: usize = 64 << 20;
+
+struct Interner {
+    ids: HashMap<&'static str, u32>,
+    /// Total length of the interned strings.
+    bytes: usize,
+}
+
+lazy_static! {
+    static ref INTERNER: RwLock<Interner> = RwLock::new(Interner {
+        ids: HashMap::new(),
+        bytes: 0,
+    });
+}
+
+/// An interned identifier name. Names are equal exactly when their IDs are.
+#[derive(Clone, Copy)]
+pub struct Name {
+    id: u32,
+    text: &'static str,
+}
+
+impl Name {
+    /// Intern a string, returning the existing `Name` if it was interned before. This is for
+    /// names made up by the compiler itself; names from program text go through `intern`.
+    pub fn new(name: &str) -> Name {
+        Name::lookup_or_insert(name, usize::max_value()).unwrap()
+    }
+
+    /// Intern a string from a program. Once the table holds `MAX_INTERNED_BYTES` of text, this
+    /// fails for any string that was not interned before, and keeps failing for the rest of the
+    /// process; names that are already in the table can still be looked up.
+    pub fn intern(name: &str) -> WeldResult<Name> {
+        Name::lookup_or_insert(name, MAX_INTERNED_BYTES)
+    }
+
+    fn lookup_or_insert(name: &str, max_bytes: usize) -> WeldResult<Name> {
+        if let Some((&text, &id)) = INTERNER.read().unwrap().ids.get_key_value(name) {
+            return Ok(Name { id: id, text: text });
+        }
+        let mut interner = INTERNER.write().unwrap();
+        if let Some((&text, &id)) = interner.ids.get_key_value(name) {
+            return Ok(Name { id: id, text: text });
+        }
+        if interner.bytes + name.len() > max_bytes {
+            return weld_err!("Cannot intern identifier {}: the intern table is full ({} bytes of \
+                identifiers)", name, interner.bytes);
+        }
+        let text: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let id = interner.ids.len() as u32;
+        interner.ids.insert(text, id);
+        interner.bytes += text.len();
+        Ok(Name { id: id, text: text })
+    }
+
+    /// The string this name was interned from.
+    pub fn as_str(&self) -> &'static str {
+        self.text
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Name) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl<'a> From<&'a str> for Name {
+    fn from(name: &'a str) -> Name {
+        Name::new(name)
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Name {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[test]
+fn intern_names() {
+    let a = Name::new("interned_a");
+    let b = Name::new("interned_b");
+    assert_eq!(a, Name::new("interned_a"));
+    assert_eq!(a, Name::intern("interned_a").unwrap());
+    assert!(a != b);
+    assert_eq!(a.as_str(), "interned_a");
+    assert!(b == "interned_b");
+    assert_eq!(format!("{} {:?}", a, b), "interned_a \"interned_b\"");
+
+    // A full table still finds existing names but does not take new ones
+    assert_eq!(Name::lookup_or_insert("interned_a", 0).unwrap(), a);
+    let err = Name::lookup_or_insert("interned_never", 0).unwrap_err();
+    assert!(err.to_string().contains("the intern table is full"));
+}
@@ -0,0 +1,975 @@
+//! A tracing interpreter for typed expressions, for stepping through how a program computed its
+//! result.
+//!
+//! The interpreter walks the expression tree directly and records an event for every value
+//! bound, loop iteration, merge into a builder and intermediate result, along with the loop
+//! iterations it happened in. It is meant to be run on small inputs to find out why a builder
+//! produced the wrong answer, so it favors keeping everything over being fast.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::ast::*;
+use super::ast::BinOpKind::*;
+use super::ast::BuilderKind::*;
+use super::ast::ExprKind::*;
+use super::ast::ScalarKind::*;
+use super::error::*;
+use super::pretty_print::*;
+use super::decimal;
+use super::random::RandomState;
+use super::timestamp;
+
+#[cfg(test)] use super::parser::parse_expr;
+#[cfg(test)] use super::type_inference::infer_types;
+
+/// A runtime value of a Weld expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    /// Microseconds since the epoch.
+    Timestamp(i64),
+    /// A decimal's unscaled value, precision and scale.
+    Decimal(i128, u8, u8),
+    Vector(Vec<Value>),
+    Struct(Vec<Value>),
+    /// A function along with the values of the symbols it captured.
+    Function(Vec<Symbol>, Box<TypedExpr>, Env),
+    Builder(BuilderValue),
+}
+
+/// The contents of a builder that is still being merged into.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuilderValue {
+    Appender(Vec<Value>),
+    Merger(Box<Value>, BinOpKind),
+    MutVec(Vec<Value>),
+    StatsMerger(Stats),
+    /// lo, hi, and the count in each bin
+    Histogram(f64, f64, Vec<i64>),
+}
+
+/// The running statistics of a statsmerger.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    pub count: i64,
+    pub mean: f64,
+    /// Sum of squared differences from the mean, from which the variance is computed.
+    pub m2: f64,
+    /// The smallest and largest values merged, or zeros of the element type while `count` is 0.
+    pub min: Box<Value>,
+    pub max: Box<Value>,
+}
+
+impl Stats {
+    /// Statistics of no values at all, for elements of the given type.
+    pub fn empty(ty: &Type) -> WeldResult<Stats> {
+        let zero = match *ty {
+            Type::Scalar(I32) => Value::I32(0),
+            Type::Scalar(I64) => Value::I64(0),
+            Type::Scalar(F32) => Value::F32(0.0),
+            Type::Scalar(F64) => Value::F64(0.0),
+            Type::Scalar(Decimal(p, s)) => Value::Decimal(0, p, s),
+            _ => return weld_err!(Runtime, "Unsupported statsmerger: statsmerger[{}]",
+                print_type(ty))
+        };
+        Ok(Stats { count: 0, mean: 0.0, m2: 0.0, min: Box::new(zero.clone()), max: Box::new(zero) })
+    }
+
+    /// Statistics of a single value.
+    pub fn of(value: Value) -> WeldResult<Stats> {
+        let x = match to_f64(&value) {
+            Some(x) => x,
+            None => return weld_err!(Runtime, "Cannot merge {} into a statsmerger", value)
+        };
+        Ok(Stats { count: 1, mean: x, m2: 0.0, min: Box::new(value.clone()), max: Box::new(value) })
+    }
+
+    /// Combine the statistics of two disjoint sets of values, as in Chan et al.'s parallel
+    /// variance algorithm. Merging one value at a time this way reduces to Welford's algorithm.
+    pub fn combine(&self, other: &Stats) -> WeldResult<Stats> {
+        if self.count == 0 {
+            return Ok(other.clone());
+        } else if other.count == 0 {
+            return Ok(self.clone());
+        }
+        let (n1, n2) = (self.count as f64, other.count as f64);
+        let n = n1 + n2;
+        let delta = other.mean - self.mean;
+        let pick = |op, a: &Value, b: &Value| -> WeldResult<Box<Value>> {
+            match try!(eval_binop(op, a, b)) {
+                Value::Bool(true) => Ok(Box::new(a.clone())),
+                _ => Ok(Box::new(b.clone()))
+            }
+        };
+        Ok(Stats {
+            count: self.count + other.count,
+            mean: self.mean + delta * n2 / n,
+            m2: self.m2 + other.m2 + delta * delta * n1 * n2 / n,
+            min: try!(pick(LessThanOrEqual, &self.min, &other.min)),
+            max: try!(pick(GreaterThanOrEqual, &self.max, &other.max)),
+        })
+    }
+
+    /// The result of a statsmerger: {count, mean, population variance, min, max}. The mean and
+    /// variance of no values are 0.
+    pub fn result(&self) -> Value {
+        let variance = if self.count == 0 { 0.0 } else { self.m2 / self.count as f64 };
+        Value::Struct(vec![Value::I64(self.count), Value::F64(self.mean), Value::F64(variance),
+            *self.min.clone(), *self.max.clone()])
+    }
+}
+
+/// Values bound to symbols.
+pub type Env = HashMap<Symbol, Value>;
+
+impl fmt::Display for Value {
+    /// Print a value in the syntax of a Weld literal, where there is one.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter, start: &str, values: &[Value], end: &str)
+                -> fmt::Result {
+            try!(f.write_str(start));
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    try!(f.write_str(","));
+                }
+                try!(write!(f, "{}", v));
+            }
+            f.write_str(end)
+        }
+        match *self {
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::I32(v) => write!(f, "{}", v),
+            Value::I64(v) => write!(f, "{}L", v),
+            Value::F32(v) => write!(f, "{:?}F", v),
+            Value::F64(v) => write!(f, "{:?}", v),
+            Value::Timestamp(v) => write!(f, "to_timestamp({}L)", v),
+            Value::Decimal(v, precision, scale) =>
+                write!(f, "decimal[{},{}]({})", precision, scale, decimal::format(v, scale)),
+            Value::Vector(ref values) => list(f, "[", values, "]"),
+            Value::Struct(ref values) => list(f, "{", values, "}"),
+            Value::Function(ref params, ref body, _) => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "|{}|{}", params.join(","), print_expr(body))
+            }
+            Value::Builder(BuilderValue::Appender(ref values)) => list(f, "appender[", values, "]"),
+            Value::Builder(BuilderValue::Merger(ref value, op)) =>
+                write!(f, "merger[{},{}]", value, op),
+            Value::Builder(BuilderValue::MutVec(ref values)) => list(f, "mutvec[", values, "]"),
+            Value::Builder(BuilderValue::StatsMerger(ref stats)) =>
+                write!(f, "statsmerger{}", stats.result()),
+            Value::Builder(BuilderValue::Histogram(_, _, ref counts)) => {
+                let counts: Vec<Value> = counts.iter().map(|c| Value::I64(*c)).collect();
+                list(f, "histogram[", &counts, "]")
+            }
+        }
+    }
+}
+
+/// Apply a binary operator to two scalar values. Integer `+`, `-`, `*` and `/` wrap around on
+/// overflow, as they do in generated code, and division by zero is an error. The LLVM backend
+/// has no `%` yet; here it is Rust's remainder, which takes the sign of the dividend.
+pub fn eval_binop(op: BinOpKind, left: &Value, right: &Value) -> WeldResult<Value> {
+    use self::Value::*;
+    macro_rules! int_op {
+        ($l:expr, $r:expr, $variant:ident) => ({
+            let (l, r) = ($l, $r);
+            match op {
+                Add => Ok($variant(l.wrapping_add(r))),
+                Subtract => Ok($variant(l.wrapping_sub(r))),
+                Multiply => Ok($variant(l.wrapping_mul(r))),
+                Divide | Modulo if r == 0 => weld_err!(Runtime, "Division by zero"),
+                Divide => Ok($variant(l.wrapping_div(r))),
+                Modulo => Ok($variant(l.wrapping_rem(r))),
+                BitwiseAnd => Ok($variant(l & r)),
+                BitwiseOr => Ok($variant(l | r)),
+                Xor => Ok($variant(l ^ r)),
+                _ => compare(op, &l, &r)
+            }
+        })
+    }
+    macro_rules! float_op {
+        ($l:expr, $r:expr, $variant:ident) => ({
+            let (l, r) = ($l, $r);
+            match op {
+                Add => Ok($variant(l + r)),
+                Subtract => Ok($variant(l - r)),
+                Multiply => Ok($variant(l * r)),
+                Divide => Ok($variant(l / r)),
+                Modulo => Ok($variant(l % r)),
+                _ => compare(op, &l, &r)
+            }
+        })
+    }
+    match (left, right) {
+        (&I32(l), &I32(r)) => int_op!(l, r, I32),
+        (&I64(l), &I64(r)) => int_op!(l, r, I64),
+        (&F32(l), &F32(r)) => float_op!(l, r, F32),
+        (&F64(l), &F64(r)) => float_op!(l, r, F64),
+        (&Bool(l), &Bool(r)) => match op {
+            LogicalAnd | BitwiseAnd => Ok(Bool(l && r)),
+            LogicalOr | BitwiseOr => Ok(Bool(l || r)),
+            Xor => Ok(Bool(l != r)),
+            _ => compare(op, &l, &r)
+        },
+        (&Timestamp(l), &Timestamp(r)) => compare(op, &l, &r),
+        (&Decimal(l, p, s), &Decimal(r, rp, rs)) if (p, s) == (rp, rs) => match op {
+            Add => Ok(Decimal(decimal::wrap(l.wrapping_add(r), p), p, s)),
+            Subtract => Ok(Decimal(decimal::wrap(l.wrapping_sub(r), p), p, s)),
+            Multiply | Divide if p > MAX_DECIMAL64_PRECISION =>
+                weld_err!(Runtime, "Cannot apply {} to decimals with more than {} digits", op,
+                    MAX_DECIMAL64_PRECISION),
+            Multiply => Ok(Decimal(decimal::mul(l, r, s), p, s)),
+            Divide if r == 0 => weld_err!(Runtime, "Division by zero"),
+            Divide => Ok(Decimal(decimal::div(l, r, s), p, s)),
+            _ => compare(op, &l, &r)
+        },
+        _ => weld_err!(Runtime, "Cannot apply {} to {} and {}", op, left, right)
+    }
+}
+
+/// Apply a comparison operator to two values of the same scalar type.
+fn compare<T: PartialOrd>(op: BinOpKind, left: &T, right: &T) -> WeldResult<Value> {
+    let result = match op {
+        Equal => left == right,
+        NotEqual => left != right,
+        LessThan => left < right,
+        LessThanOrEqual => left <= right,
+        GreaterThan => left > right,
+        GreaterThanOrEqual => left >= right,
+        _ => return weld_err!(Runtime, "Operator {} is not supported on this type", op)
+    };
+    Ok(Value::Bool(result))
+}
+
+/// The initial value of a merger, which is the identity of its operator.
+pub fn merger_identity(ty: &Type, op: BinOpKind) -> WeldResult<Value> {
+    let value = match (ty, op) {
+        (&Type::Scalar(I32), Add) => Value::I32(0),
+        (&Type::Scalar(I64), Add) => Value::I64(0),
+        (&Type::Scalar(F32), Add) => Value::F32(0.0),
+        (&Type::Scalar(F64), Add) => Value::F64(0.0),
+        (&Type::Scalar(I32), Multiply) => Value::I32(1),
+        (&Type::Scalar(I64), Multiply) => Value::I64(1),
+        (&Type::Scalar(F32), Multiply) => Value::F32(1.0),
+        (&Type::Scalar(F64), Multiply) => Value::F64(1.0),
+        (&Type::Scalar(Decimal(p, s)), Add) => Value::Decimal(0, p, s),
+        (&Type::Scalar(Decimal(p, s)), Multiply) => Value::Decimal(decimal::pow10(s), p, s),
+        (&Type::Scalar(Bool), LogicalAnd) => Value::Bool(true),
+        (&Type::Scalar(Bool), LogicalOr) => Value::Bool(false),
+        _ => return weld_err!(Runtime, "Unsupported merger: merger[{},{}]", print_type(ty), op)
+    };
+    Ok(value)
+}
+
+/// The value of a numeric scalar as an f64.
+fn to_f64(value: &Value) -> Option<f64> {
+    match *value {
+        Value::I32(v) => Some(v as f64),
+        Value::I64(v) => Some(v as f64),
+        Value::F32(v) => Some(v as f64),
+        Value::F64(v) => Some(v),
+        Value::Decimal(v, _, scale) => Some(v as f64 / decimal::pow10_f64(scale)),
+        _ => None
+    }
+}
+
+/// Convert a numeric value to another numeric type, as described in `BuiltinKind::Cast`.
+/// Integers wrap around and floats saturate if the result does not fit.
+pub fn eval_cast(kind: ScalarKind, value: &Value) -> WeldResult<Value> {
+    // Integers and decimals as an unscaled i128 and their scale
+    let fixed = match *value {
+        Value::I32(v) => Some((v as i128, 0)),
+        Value::I64(v) => Some((v as i128, 0)),
+        Value::Decimal(v, _, scale) => Some((v, scale)),
+        _ => None
+    };
+    let float = match *value {
+        Value::F32(v) => Some(v as f64),
+        Value::F64(v) => Some(v),
+        _ => None
+    };
+    let res = match (kind, fixed, float) {
+        (I32, Some((v, scale)), _) => Value::I32((v / decimal::pow10(scale)) as i32),
+        (I64, Some((v, scale)), _) => Value::I64((v / decimal::pow10(scale)) as i64),
+        (I32, _, Some(x)) => Value::I32(x as i32),
+        (I64, _, Some(x)) => Value::I64(x as i64),
+        (F32, Some((v, 0)), _) => Value::F32(v as f32),
+        (F64, Some((v, 0)), _) => Value::F64(v as f64),
+        (F32, Some((v, scale)), _) => Value::F32((v as f64 / decimal::pow10_f64(scale)) as f32),
+        (F64, Some((v, scale)), _) => Value::F64(v as f64 / decimal::pow10_f64(scale)),
+        (F32, _, Some(x)) => Value::F32(x as f32),
+        (F64, _, Some(x)) => Value::F64(x),
+        (Decimal(p, s), Some((v, scale)), _) =>
+            Value::Decimal(decimal::rescale(v, scale, p, s), p, s),
+        (Decimal(p, s), _, Some(x)) =>
+            Value::Decimal(decimal::wrap((x * decimal::pow10_f64(s)).round() as i128, p), p, s),
+        _ => return weld_err!(Runtime, "Cannot cast {} to {}", value, kind)
+    };
+    Ok(res)
+}
+
+/// Call a builtin with result type `ty` on the values of its arguments, drawing any random
+/// numbers from `random`. Float vectors are multiplied out in double precision, with f32 results
+/// rounded at the end.
+pub fn eval_builtin(kind: BuiltinKind, ty: &Type, args: Vec<Value>, random: &mut RandomState)
+        -> WeldResult<Value> {
+    let is_f32 = match *ty {
+        Type::Scalar(F32) => true,
+        Type::Vector(ref elem) => **elem == Type::Scalar(F32),
+        _ => false
+    };
+    let floats = |value: &Value| -> WeldResult<Vec<f64>> {
+        let mut res = Vec::new();
+        if let Value::Vector(ref values) = *value {
+            for v in values {
+                match *v {
+                    Value::F32(x) if is_f32 => res.push(x as f64),
+                    Value::F64(x) if !is_f32 => res.push(x),
+                    _ => return weld_err!(Runtime, "Expected a vector of floats, got {}", value)
+                }
+            }
+            return Ok(res);
+        }
+        weld_err!(Runtime, "Expected a vector of floats, got {}", value)
+    };
+    let float = |x: f64| if is_f32 { Value::F32(x as f32) } else { Value::F64(x) };
+
+    match (kind, &args[..]) {
+        (BuiltinKind::Dot, &[ref a, ref b]) => {
+            let (a, b) = (try!(floats(a)), try!(floats(b)));
+            if a.len() != b.len() {
+                return weld_err!(Runtime, "dot of vectors of lengths {} and {}", a.len(), b.len());
+            }
+            Ok(float(a.iter().zip(&b).map(|(x, y)| x * y).sum()))
+        }
+        (BuiltinKind::Gemm, &[ref a, ref b, Value::I64(m), Value::I64(n), Value::I64(k)]) => {
+            let (a, b) = (try!(floats(a)), try!(floats(b)));
+            if m < 0 || n < 0 || k < 0 || m.checked_mul(k) != Some(a.len() as i64) ||
+                    k.checked_mul(n) != Some(b.len() as i64) {
+                return weld_err!(Runtime, "gemm of {} x {} and {} x {} matrices got vectors of \
+                    lengths {} and {}", m, k, k, n, a.len(), b.len());
+            }
+            // With k = 0 the inputs are empty however large the result is
+            let mut res = Vec::new();
+            if m.checked_mul(n).map_or(true, |len| res.try_reserve_exact(len as usize).is_err()) {
+                return weld_err!(Runtime, "gemm result of {} x {} is too large", m, n);
+            }
+            let (m, n, k) = (m as usize, n as usize, k as usize);
+            for i in 0..m {
+                for j in 0..n {
+                    let sum = (0..k).map(|p| a[i * k + p] * b[p * n + j]).sum();
+                    res.push(float(sum));
+                }
+            }
+            Ok(Value::Vector(res))
+        }
+        (BuiltinKind::Rand, &[]) => Ok(Value::F64(random.next(0))),
+        (BuiltinKind::RandSeeded, &[Value::I64(seed)]) => Ok(Value::F64(random.next(seed))),
+        (BuiltinKind::Cast(kind), &[ref value]) => eval_cast(kind, value),
+        (BuiltinKind::ToTimestamp, &[Value::I64(v)]) => Ok(Value::Timestamp(v)),
+        (BuiltinKind::ToMicros, &[Value::Timestamp(t)]) => Ok(Value::I64(t)),
+        (BuiltinKind::Extract(unit), &[Value::Timestamp(t)]) =>
+            Ok(Value::I32(timestamp::extract(unit, t))),
+        (BuiltinKind::Trunc(unit), &[Value::Timestamp(t)]) =>
+            Ok(Value::Timestamp(try!(timestamp::trunc(unit, t)))),
+        (BuiltinKind::AddInterval, &[Value::Timestamp(t), Value::I64(v)]) =>
+            Ok(Value::Timestamp(t.wrapping_add(v))),
+        (BuiltinKind::IntervalBetween, &[Value::Timestamp(start), Value::Timestamp(end)]) =>
+            Ok(Value::I64(end.wrapping_sub(start))),
+        _ => weld_err!(Runtime, "Invalid arguments to {}", kind)
+    }
+}
+
+/// Create a histogram from its {lo, hi, nbins} arguments.
+pub fn new_histogram(args: Value) -> WeldResult<BuilderValue> {
+    let (lo, hi, nbins) = match args {
+        Value::Struct(ref fields) if fields.len() == 3 => match (to_f64(&fields[0]),
+                to_f64(&fields[1]), &fields[2]) {
+            (Some(lo), Some(hi), &Value::I64(nbins)) => (lo, hi, nbins),
+            _ => return weld_err!(Runtime, "Invalid histogram arguments {}", args)
+        },
+        _ => return weld_err!(Runtime, "Invalid histogram arguments {}", args)
+    };
+    if nbins <= 0 || !(lo < hi) {
+        return weld_err!(Runtime, "histogram needs lo < hi and at least one bin, got {}", args);
+    }
+    let mut counts = Vec::new();
+    if counts.try_reserve_exact(nbins as usize).is_err() {
+        return weld_err!(Runtime, "histogram of {} bins is too large", nbins);
+    }
+    counts.resize(nbins as usize, 0);
+    Ok(BuilderValue::Histogram(lo, hi, counts))
+}
+
+/// Something that happened while interpreting a program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceEvent {
+    /// A symbol was bound by a let or as a function parameter.
+    Bind(Symbol, Value),
+    /// A loop started running over a vector with the given number of elements.
+    LoopStart { loop_id: usize, expr: String, length: usize },
+    /// A loop iteration started with the given element.
+    Iteration { loop_id: usize, index: usize, element: Value },
+    /// A value was merged into a builder, giving the new builder.
+    Merge { value: Value, builder: Value },
+    /// An expression other than a literal, identifier or function was evaluated.
+    Eval { expr: String, value: Value },
+}
+
+/// A trace event along with the loop iterations it happened in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry {
+    /// The enclosing loops, outermost first, as (loop ID, iteration index) pairs.
+    pub loops: Vec<(usize, usize)>,
+    pub event: TraceEvent,
+}
+
+/// The events recorded while interpreting a program, in the order they happened.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    /// All the values merged into builders, in order, with the builders they produced.
+    pub fn merges(&self) -> Vec<&TraceEntry> {
+        self.entries.iter().filter(|e| match e.event {
+            TraceEvent::Merge { .. } => true,
+            _ => false
+        }).collect()
+    }
+
+    /// The iterations of the loop with the given ID.
+    pub fn iterations(&self, loop_id: usize) -> Vec<&TraceEntry> {
+        self.entries.iter().filter(|e| match e.event {
+            TraceEvent::Iteration { loop_id: id, .. } => id == loop_id,
+            _ => false
+        }).collect()
+    }
+
+    /// The values bound to symbols with the given name, in order.
+    pub fn bindings(&self, name: &str) -> Vec<&Value> {
+        self.entries.iter().filter_map(|e| match e.event {
+            TraceEvent::Bind(ref symbol, ref value) if symbol.name == name => Some(value),
+            _ => None
+        }).collect()
+    }
+
+    /// The values that an expression, printed as by `print_expr`, evaluated to.
+    pub fn values_of(&self, expr: &str) -> Vec<&Value> {
+        self.entries.iter().filter_map(|e| match e.event {
+            TraceEvent::Eval { expr: ref text, ref value } if text == expr => Some(value),
+            _ => None
+        }).collect()
+    }
+
+    /// Everything that happened during the given iteration of a loop, including inside nested
+    /// loops.
+    pub fn during_iteration(&self, loop_id: usize, index: usize) -> Vec<&TraceEntry> {
+        self.entries.iter().filter(|e| e.loops.contains(&(loop_id, index))).collect()
+    }
+}
+
+impl fmt::Display for Trace {
+    /// Print one event per line, indented by loop nesting depth.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            for _ in 0..entry.loops.len() {
+                try!(f.write_str("  "));
+            }
+            try!(match entry.event {
+                TraceEvent::Bind(ref symbol, ref value) => writeln!(f, "{} = {}", symbol, value),
+                TraceEvent::LoopStart { loop_id, ref expr, length } =>
+                    writeln!(f, "loop {} over {} elements: {}", loop_id, length, expr),
+                TraceEvent::Iteration { loop_id, index, ref element } =>
+                    writeln!(f, "loop {} iteration {}: {}", loop_id, index, element),
+                TraceEvent::Merge { ref value, ref builder } =>
+                    writeln!(f, "merge {} -> {}", value, builder),
+                TraceEvent::Eval { ref expr, ref value } => writeln!(f, "{} => {}", expr, value),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Evaluate a function on the given arguments, recording a trace of the evaluation. Returns the
+/// result together with the trace.
+pub fn trace_function(func: &TypedExpr, args: &[Value]) -> WeldResult<(Value, Trace)> {
+    let mut interpreter = Interpreter {
+        trace: Trace::default(),
+        loops: Vec::new(),
+        next_loop: 0,
+        random: RandomState::new(),
+    };
+    let mut env = Env::new();
+    let func = try!(interpreter.eval(func, &mut env));
+    let result = try!(interpreter.call(&func, args.to_vec()));
+    Ok((result, interpreter.trace))
+}
+
+struct Interpreter {
+    trace: Trace,
+    /// The loops currently running, as (loop ID, iteration index) pairs.
+    loops: Vec<(usize, usize)>,
+    next_loop: usize,
+    random: RandomState,
+}
+
+impl Interpreter {
+    fn record(&mut self, event: TraceEvent) {
+        self.trace.entries.push(TraceEntry { loops: self.loops.clone(), event: event });
+    }
+
+    fn eval(&mut self, expr: &TypedExpr, env: &mut Env) -> WeldResult<Value> {
+        let value = try!(self.eval_kind(expr, env));
+        match expr.kind {
+            BoolLiteral(_) | I32Literal(_) | I64Literal(_) | F32Literal(_) | F64Literal(_) |
+            Ident(_) | Lambda(_, _) => (),
+            _ => self.record(TraceEvent::Eval { expr: print_expr(expr), value: value.clone() })
+        }
+        Ok(value)
+    }
+
+    fn eval_kind(&mut self, expr: &TypedExpr, env: &mut Env) -> WeldResult<Value> {
+        match expr.kind {
+            BoolLiteral(v) => Ok(Value::Bool(v)),
+            I32Literal(v) => Ok(Value::I32(v)),
+            I64Literal(v) => Ok(Value::I64(v)),
+            F32Literal(v) => Ok(Value::F32(v)),
+            F64Literal(v) => Ok(Value::F64(v)),
+
+            Ident(ref symbol) => match env.get(symbol) {
+                Some(value) => Ok(value.clone()),
+                None => weld_err!(Runtime, "Undefined identifier: {}", symbol)
+            },
+
+            BinOp(op, ref left, ref right) => {
+                let left = try!(self.eval(left, env));
+                let right = try!(self.eval(right, env));
+                eval_binop(op, &left, &right)
+            }
+
+            Let(ref symbol, ref value, ref body) => {
+                let value = try!(self.eval(value, env));
+                self.record(TraceEvent::Bind(symbol.clone(), value.clone()));
+                let old = env.insert(symbol.clone(), value);
+                let result = self.eval(body, env);
+                match old {
+                    Some(old) => env.insert(symbol.clone(), old),
+                    None => env.remove(symbol)
+                };
+                result
+            }
+
+            MakeStruct(ref exprs) => {
+                let mut values = Vec::new();
+                for e in exprs {
+                    values.push(try!(self.eval(e, env)));
+                }
+                Ok(Value::Struct(values))
+            }
+
+            MakeVector(ref exprs) => {
+                let mut values = Vec::new();
+                for e in exprs {
+                    values.push(try!(self.eval(e, env)));
+                }
+                Ok(Value::Vector(values))
+            }
+
+            GetField(ref param, index) => match try!(self.eval(param, env)) {
+                Value::Struct(ref values) if (index as usize) < values.len() =>
+                    Ok(values[index as usize].clone()),
+                other => weld_err!(Runtime, "Cannot get field {} of {}", index, other)
+            },
+
+            Lambda(ref params, ref body) => {
+                let params = params.iter().map(|p| p.name.clone()).collect();
+                Ok(Value::Function(params, body.clone(), env.clone()))
+            }
+
+            Apply(ref func, ref args) => {
+                let func = try!(self.eval(func, env));
+                let mut values = Vec::new();
+                for a in args {
+                    values.push(try!(self.eval(a, env)));
+                }
+                self.call(&func, values)
+            }
+
+            Builtin(kind, ref args) => {
+                let mut values = Vec::new();
+                for a in args {
+                    values.push(try!(self.eval(a, env)));
+                }
+                eval_builtin(kind, &expr.ty, values, &mut self.random)
+            }
+
+            If(ref cond, ref on_true, ref on_false) => match try!(self.eval(cond, env)) {
+                Value::Bool(true) => self.eval(on_true, env),
+                Value::Bool(false) => self.eval(on_false, env),
+                other => weld_err!(Runtime, "If condition is not a bool: {}", other)
+            },
+
+            NewBuilder(ref arg) => {
+                let builder = match (&expr.ty, arg) {
+                    (&Type::Builder(Appender(_)), &None) => BuilderValue::Appender(Vec::new()),
+                    (&Type::Builder(Merger(ref elem, op)), &None) =>
+                        BuilderValue::Merger(Box::new(try!(merger_identity(elem, op))), op),
+                    (&Type::Builder(StatsMerger(ref elem)), &None) =>
+                        BuilderValue::StatsMerger(try!(Stats::empty(elem))),
+                    (&Type::Builder(Histogram(_)), &Some(ref arg)) =>
+                        try!(new_histogram(try!(self.eval(arg, env)))),
+                    (&Type::Builder(MutVec(_)), &Some(ref arg)) => match try!(self.eval(arg, env)) {
+                        Value::Vector(values) => BuilderValue::MutVec(values),
+                        other => return weld_err!(Runtime, "mutvec needs a vector, got {}", other)
+                    },
+                    _ => return weld_err!(Runtime, "Invalid builder: {}", print_expr(expr))
+                };
+                Ok(Value::Builder(builder))
+            }
+
+            Merge(ref builder, ref value) => {
+                let builder = try!(self.eval(builder, env));
+                let value = try!(self.eval(value, env));
+                let merged = try!(merge(builder, value.clone()));
+                self.record(TraceEvent::Merge { value: value, builder: merged.clone() });
+                Ok(merged)
+            }
+
+            Res(ref builder) => match try!(self.eval(builder, env)) {
+                Value::Builder(BuilderValue::Appender(values)) => Ok(Value::Vector(values)),
+                Value::Builder(BuilderValue::Merger(value, _)) => Ok(*value),
+                Value::Builder(BuilderValue::MutVec(values)) => Ok(Value::Vector(values)),
+                Value::Builder(BuilderValue::StatsMerger(stats)) => Ok(stats.result()),
+                Value::Builder(BuilderValue::Histogram(_, _, counts)) =>
+                    Ok(Value::Vector(counts.into_iter().map(Value::I64).collect())),
+                other => weld_err!(Runtime, "Result called on non-builder {}", other)
+            },
+
+            For(ref data, ref builder, ref func) => {
+                let data = match try!(self.eval(data, env)) {
+                    Value::Vector(values) => values,
+                    other => return weld_err!(Runtime, "For loop over non-vector {}", other)
+                };
+                let mut builder = try!(self.eval(builder, env));
+                let func = try!(self.eval(func, env));
+                let loop_id = self.next_loop;
+                self.next_loop += 1;
+                self.record(TraceEvent::LoopStart {
+                    loop_id: loop_id,
+                    expr: print_expr(expr),
+                    length: data.len(),
+                });
+                for (index, element) in data.into_iter().enumerate() {
+                    self.loops.push((loop_id, index));
+                    self.random.enter_iteration(index);
+                    self.record(TraceEvent::Iteration {
+                        loop_id: loop_id,
+                        index: index,
+                        element: element.clone(),
+                    });
+                    let result = self.call(&func, vec![builder, element]);
+                    self.random.exit_iteration();
+                    self.loops.pop();
+                    builder = try!(result);
+                }
+                Ok(builder)
+            }
+        }
+    }
+
+    /// Call a function value on some arguments.
+    fn call(&mut self, func: &Value, args: Vec<Value>) -> WeldResult<Value> {
+        match *func {
+            Value::Function(ref params, ref body, ref captured) => {
+                if params.len() != args.len() {
+                    return weld_err!(Runtime, "Function takes {} arguments but got {}",
+                        params.len(), args.len());
+                }
+                let mut env = captured.clone();
+                for (param, arg) in params.iter().zip(args) {
+                    self.record(TraceEvent::Bind(param.clone(), arg.clone()));
+                    env.insert(param.clone(), arg);
+                }
+                self.eval(body, &mut env)
+            }
+            ref other => weld_err!(Runtime, "Called a value that is not a function: {}", other)
+        }
+    }
+}
+
+/// Merge a value into a builder, returning the new builder.
+pub fn merge(builder: Value, value: Value) -> WeldResult<Value> {
+    let merged = match builder {
+        Value::Builder(BuilderValue::Appender(mut values)) => {
+            values.push(value);
+            BuilderValue::Appender(values)
+        }
+        Value::Builder(BuilderValue::Merger(current, op)) => {
+            BuilderValue::Merger(Box::new(try!(eval_binop(op, &current, &value))), op)
+        }
+        Value::Builder(BuilderValue::MutVec(mut values)) => {
+            let (index, element) = match value {
+                Value::Struct(mut fields) => match (fields.pop(), fields.pop()) {
+                    (Some(element), Some(Value::I64(index))) if fields.is_empty() =>
+                        (index, element),
+                    _ => return weld_err!(Runtime, "mutvec merges must be {{i64, value}}")
+                },
+                other => return weld_err!(Runtime, "mutvec merges must be {{i64, value}}, \
+                    got {}", other)
+            };
+            if index < 0 || index as usize >= values.len() {
+                return weld_err!(Runtime, "mutvec index {} out of bounds for length {}",
+                    index, values.len());
+            }
+            values[index as usize] = element;
+            BuilderValue::MutVec(values)
+        }
+        Value::Builder(BuilderValue::StatsMerger(stats)) =>
+            BuilderValue::StatsMerger(try!(stats.combine(&try!(Stats::of(value))))),
+        Value::Builder(BuilderValue::Histogram(lo, hi, mut counts)) => {
+            let x = match to_f64(&value) {
+                Some(x) => x,
+                None => return weld_err!(Runtime, "Cannot merge {} into a histogram", value)
+            };
+            // Bins include their lower bound, except that the last one also includes hi; values
+            // outside [lo, hi] are not counted
+            if x >= lo && x <= hi {
+                let nbins = counts.len();
+                let bin = ((x - lo) / (hi - lo) * nbins as f64) as usize;
+                counts[if bin < nbins { bin } else { nbins - 1 }] += 1;
+            }
+            BuilderValue::Histogram(lo, hi, counts)
+        }
+        other => return weld_err!(Runtime, "Merge called on non-builder {}", other)
+    };
+    Ok(Value::Builder(merged))
+}
+
+#[cfg(test)]
+fn typed_expr(code: &str) -> TypedExpr {
+    let mut e = parse_expr(code).unwrap();
+    infer_types(&mut e).unwrap();
+    e.to_typed().unwrap()
+}
+
+#[test]
+fn binops() {
+    use self::Value::*;
+    assert_eq!(eval_binop(Add, &I32(i32::max_value()), &I32(1)).unwrap(), I32(i32::min_value()));
+    assert_eq!(eval_binop(Modulo, &I64(-7), &I64(2)).unwrap(), I64(-1));
+    assert_eq!(eval_binop(Divide, &F64(1.0), &F64(4.0)).unwrap(), F64(0.25));
+    assert_eq!(eval_binop(LessThanOrEqual, &F32(1.0), &F32(1.0)).unwrap(), Bool(true));
+    assert_eq!(eval_binop(Xor, &Bool(true), &Bool(true)).unwrap(), Bool(false));
+    assert_eq!(eval_binop(Divide, &I32(1), &I32(0)).unwrap_err().kind(), ErrorKind::Runtime);
+    assert!(eval_binop(Add, &I32(1), &I64(1)).is_err());
+}
+
+#[test]
+fn trace_loops() {
+    let func = typed_expr("|v:vec[i32], k:i32| \
+        result(for(v, appender[i32], |b,x| if(x > k, merge(b, x * 2), b)))");
+    let args = [Value::Vector(vec![Value::I32(1), Value::I32(5), Value::I32(7)]), Value::I32(4)];
+    let (result, trace) = trace_function(&func, &args).unwrap();
+    assert_eq!(result, Value::Vector(vec![Value::I32(10), Value::I32(14)]));
+
+    let iterations = trace.iterations(0);
+    assert_eq!(iterations.len(), 3);
+    assert_eq!(iterations[1].event,
+        TraceEvent::Iteration { loop_id: 0, index: 1, element: Value::I32(5) });
+    assert_eq!(trace.bindings("x"), vec![&Value::I32(1), &Value::I32(5), &Value::I32(7)]);
+    assert_eq!(trace.values_of("(x>k)"),
+        vec![&Value::Bool(false), &Value::Bool(true), &Value::Bool(true)]);
+
+    let merges = trace.merges();
+    assert_eq!(merges.len(), 2);
+    assert_eq!(merges[0].loops, vec![(0, 1)]);
+    assert_eq!(merges[1].event, TraceEvent::Merge {
+        value: Value::I32(14),
+        builder: Value::Builder(BuilderValue::Appender(vec![Value::I32(10), Value::I32(14)])),
+    });
+    assert!(trace.during_iteration(0, 0).iter().all(|e| match e.event {
+        TraceEvent::Merge { .. } => false,
+        _ => true
+    }));
+    assert!(trace.to_string().contains("  merge 10 -> appender[10]\n"));
+}
+
+#[test]
+fn trace_builders() {
+    let mut sum = typed_expr("|v:vec[i64]| for(v, appender[i64], |b,x| merge(b, x))");
+    // The parser has no syntax for mergers yet, so make one by changing the builder's type
+    if let Lambda(_, ref mut body) = sum.kind {
+        let merger = Type::Builder(Merger(Box::new(Type::Scalar(I64)), Add));
+        body.ty = merger.clone();
+        if let For(_, ref mut builder, ref mut func) = body.kind {
+            builder.ty = merger.clone();
+            func.ty = Type::Function(vec![merger.clone(), Type::Scalar(I64)],
+                Box::new(merger.clone()));
+        }
+    }
+    let args = [Value::Vector(vec![Value::I64(2), Value::I64(3)])];
+    let (result, _) = trace_function(&sum, &args).unwrap();
+    assert_eq!(result, Value::Builder(BuilderValue::Merger(Box::new(Value::I64(5)), Add)));
+
+    let func = typed_expr("|v:vec[f64]| result(merge(mutvec(v), {1L, 0.5}))");
+    let args = [Value::Vector(vec![Value::F64(1.0), Value::F64(2.0)])];
+    let (result, _) = trace_function(&func, &args).unwrap();
+    assert_eq!(result, Value::Vector(vec![Value::F64(1.0), Value::F64(0.5)]));
+    assert_eq!(result.to_string(), "[1.0,0.5]");
+
+    let func = typed_expr("|v:vec[f64]| result(merge(mutvec(v), {5L, 0.5}))");
+    let err = trace_function(&func, &args).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Runtime);
+    assert_eq!(err.message(), "mutvec index 5 out of bounds for length 2");
+}
+
+#[test]
+fn stats_merger() {
+    let func = typed_expr("|v:vec[i32]| result(for(v, statsmerger, |b,x| merge(b, x)))");
+    let values: Vec<Value> = [2, 4, 4, 4, 5, 5, 7, 9].iter().map(|v| Value::I32(*v)).collect();
+    let (result, trace) = trace_function(&func, &[Value::Vector(values.clone())]).unwrap();
+    assert_eq!(result.to_string(), "{8L,5.0,4.0,2,9}");
+    assert!(trace.to_string().contains("merge 4 -> statsmerger{2L,3.0,1.0,2,4}\n"));
+
+    let (result, _) = trace_function(&func, &[Value::Vector(vec![])]).unwrap();
+    assert_eq!(result.to_string(), "{0L,0.0,0.0,0,0}");
+
+    // Combining the statistics of two halves gives the same result as merging every value
+    let half = |values: &[Value]| values.iter().fold(Stats::empty(&Type::Scalar(I32)).unwrap(),
+        |s, v| s.combine(&Stats::of(v.clone()).unwrap()).unwrap());
+    let combined = half(&values[..3]).combine(&half(&values[3..])).unwrap();
+    assert_eq!(combined.result(), half(&values).result());
+
+    // Large offsets do not lose precision as a sum of squares would
+    let func = typed_expr("|v:vec[f64]| result(for(v, statsmerger, |b,x| merge(b, x))).$2");
+    let values = vec![Value::F64(1e9 + 4.0), Value::F64(1e9 + 7.0), Value::F64(1e9 + 13.0),
+        Value::F64(1e9 + 16.0)];
+    assert_eq!(trace_function(&func, &[Value::Vector(values)]).unwrap().0, Value::F64(22.5));
+}
+
+#[test]
+fn histogram() {
+    let func = typed_expr("|v:vec[f64], n:i64| result(for(v, histogram(0.0, 1.0, n), \
+        |b,x| merge(b, x)))");
+    let values = [0.0, 0.1, 0.25, 0.5, 0.99, 1.0, -0.5, 1.5];
+    let values = Value::Vector(values.iter().map(|v| Value::F64(*v)).collect());
+    let (result, trace) = trace_function(&func, &[values.clone(), Value::I64(4)]).unwrap();
+    assert_eq!(result.to_string(), "[2L,1L,1L,2L]");
+    assert!(trace.to_string().contains("merge 0.1 -> histogram[2L,0L,0L,0L]\n"));
+
+    let err = trace_function(&func, &[values.clone(), Value::I64(0)]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Runtime);
+    let err = trace_function(&func, &[values, Value::I64(1 << 62)]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Runtime);
+}
+
+#[test]
+fn builtins() {
+    let func = typed_expr("|a:vec[f64], b:vec[f64]| {dot(a, b), gemm(a, b, 2L, 2L, 2L)}");
+    let a = Value::Vector([1.0, 2.0, 3.0, 4.0].iter().map(|v| Value::F64(*v)).collect());
+    let b = Value::Vector([5.0, 6.0, 7.0, 8.0].iter().map(|v| Value::F64(*v)).collect());
+    let (result, _) = trace_function(&func, &[a.clone(), b]).unwrap();
+    assert_eq!(result.to_string(), "{70.0,[19.0,22.0,43.0,50.0]}");
+
+    // A 1 x 4 by 4 x 1 product is a dot product
+    let func = typed_expr("|a:vec[f32]| gemm(a, a, 1L, 1L, 4L)");
+    let a = Value::Vector([1.0, 2.0, 3.0, 4.0].iter().map(|v| Value::F32(*v)).collect());
+    assert_eq!(trace_function(&func, &[a.clone()]).unwrap().0.to_string(), "[30.0F]");
+
+    let func = typed_expr("|a:vec[f32]| dot(a, a)");
+    assert_eq!(trace_function(&func, &[Value::Vector(vec![])]).unwrap().0, Value::F32(0.0));
+
+    let func = typed_expr("|a:vec[f32]| gemm(a, a, 2L, 2L, 1L)");
+    let err = trace_function(&func, &[a]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Runtime);
+
+    // Dimensions whose products overflow, and a huge result of empty inputs
+    for code in ["|a:vec[f32]| gemm(a, a, 0L, 4611686018427387904L, 4L)",
+            "|a:vec[f32]| gemm(a, a, 3037000500L, 3037000500L, 0L)",
+            "|a:vec[f32]| gemm(a, a, 2147483648L, 2147483648L, 0L)"].iter() {
+        let err = trace_function(&typed_expr(code), &[Value::Vector(vec![])]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Runtime);
+    }
+}
+
+#[test]
+fn timestamps() {
+    // 2017-03-14 15:09:26 UTC
+    let func = typed_expr("|t:timestamp| {extract_year(t), extract_month(t), extract_day(t), \
+        extract_hour(t), to_micros(trunc_month(t)), interval_between(trunc_day(t), t)}");
+    let (result, _) = trace_function(&func, &[Value::Timestamp(1489504166000000)]).unwrap();
+    assert_eq!(result.to_string(), "{2017,3,14,15,1488326400000000L,54566000000L}");
+
+    let func = typed_expr("|t:timestamp| let u = add_interval(t, 0L - 1L); {u, u < t}");
+    let (result, _) = trace_function(&func, &[Value::Timestamp(0)]).unwrap();
+    assert_eq!(result.to_string(), "{to_timestamp(-1L),true}");
+    let func = typed_expr("|t:timestamp| extract_year(add_interval(t, 0L - 1L))");
+    assert_eq!(trace_function(&func, &[Value::Timestamp(0)]).unwrap().0, Value::I32(1969));
+}
+
+#[test]
+fn decimals() {
+    let func = typed_expr("|x:f64| let d = decimal[10,2](x); \
+        {d, d * d, d / decimal[10,2](3), d - decimal[10,2](1L), d < decimal[10,2](0)}");
+    let (result, _) = trace_function(&func, &[Value::F64(-1.005)]).unwrap();
+    assert_eq!(result.to_string(), "{decimal[10,2](-1.00),decimal[10,2](1.00),\
+        decimal[10,2](-0.33),decimal[10,2](-2.00),true}");
+
+    // Sums are exact where binary floating point is not
+    let func = typed_expr("|x:f64| let d = decimal[20,1](x); let s = d+d+d+d+d+d+d+d+d+d; \
+        {s, f64(s), i32(s), decimal[20,0](s), decimal[20,3](s)}");
+    let (result, _) = trace_function(&func, &[Value::F64(0.1)]).unwrap();
+    assert_eq!(result.to_string(), "{decimal[20,1](1.0),1.0,1,decimal[20,0](1),\
+        decimal[20,3](1.000)}");
+
+    assert_eq!(eval_cast(I32, &Value::F64(-2.7)).unwrap(), Value::I32(-2));
+    assert_eq!(eval_cast(F32, &Value::I64(3)).unwrap(), Value::F32(3.0));
+    assert_eq!(eval_cast(Decimal(4, 1), &Value::Decimal(-125, 5, 2)).unwrap(),
+        Value::Decimal(-13, 4, 1));
+    assert_eq!(eval_cast(I64, &Value::Decimal(-199, 5, 2)).unwrap(), Value::I64(-1));
+    let func = typed_expr("|d:decimal[5,2]| d / decimal[5,2](0)");
+    assert!(trace_function(&func, &[Value::Decimal(1, 5, 2)]).is_err());
+}
+
+#[test]
+fn random_numbers() {
+    let func = typed_expr("|v:vec[i32]| \
+        result(for(v, appender, |b,x| merge(b, {rand(), rand()})))");
+    let args = [Value::Vector(vec![Value::I32(0); 3])];
+    let (result, _) = trace_function(&func, &args).unwrap();
+    let draws: Vec<f64> = match result {
+        Value::Vector(ref pairs) => pairs.iter().flat_map(|p| match *p {
+            Value::Struct(ref fields) => fields.iter().map(|f| match *f {
+                Value::F64(x) => x,
+                _ => panic!("expected f64")
+            }).collect::<Vec<_>>(),
+            _ => panic!("expected struct")
+        }).collect(),
+        _ => panic!("expected vector")
+    };
+    assert_eq!(draws.len(), 6);
+    for (i, x) in draws.iter().enumerate() {
+        assert!(*x >= 0.0 && *x < 1.0);
+        assert!(draws[i + 1..].iter().all(|y| y != x));
+    }
+
+    // Results are reproducible, and the seed selects the stream
+    assert_eq!(trace_function(&func, &args).unwrap().0, result);
+    let seeded = typed_expr("|s:i64| {rand_seeded(s), rand()}");
+    let (a, _) = trace_function(&seeded, &[Value::I64(0)]).unwrap();
+    let (b, _) = trace_function(&seeded, &[Value::I64(7)]).unwrap();
+    match (a, b) {
+        (Value::Struct(a), Value::Struct(b)) => {
+            assert!(a[0] != b[0]);
+            assert_eq!(a[1], b[1]);
+        }
+        _ => panic!("expected structs")
+    }
+}
@@ -7,26 +7,65 @@
 extern crate regex;
 extern crate easy_ll;
 
-/// Utility macro to create an Err result with a WeldError from a format string.
+/// Utility macro to create an Err result with a WeldError from a format string, optionally
+/// preceded by an `ErrorKind` variant name (e.g. `weld_err!(Internal, "...")`).
 macro_rules! weld_err {
+    ( $kind:ident, $($arg:tt)* ) => ({
+        ::std::result::Result::Err($crate::error::WeldError::with_message(
+            $crate::error::ErrorKind::$kind, format!($($arg)*)))
+    });
     ( $($arg:tt)* ) => ({
         ::std::result::Result::Err($crate::error::WeldError::new(format!($($arg)*)))
     })
 }
 
 // TODO: Not all of these should be public
+pub mod abi;
 pub mod ast;
+pub mod cache;
+pub mod c_header;
 pub mod code_builder;
+pub mod conf;
+pub mod decimal;
 pub mod error;
+pub mod eval;
+pub mod explain;
+pub mod intern;
+pub mod interpreter;
 pub mod llvm;
+pub mod llvm_ir;
 pub mod macro_processor;
 pub mod parser;
 pub mod partial_types;
 pub mod pretty_print;
 pub mod program;
+pub mod random;
+pub mod stage_log;
+pub mod testgen;
+pub mod timestamp;
 pub mod tokenizer;
 pub mod transforms;
 pub mod type_inference;
 pub mod util;
+pub mod weld_capi;
 
 #[cfg(test)] mod tests;
+
+/// Check that a program is well-formed by expanding its macros and inferring all of its types,
+/// without generating any code. Returns the type of the program's result: for programs whose
+/// body is a function, as required by `compile_program`, this is the function's return type.
+pub fn validate(program: &program::Program) -> error::WeldResult<ast::Type> {
+    let depth = try!(util::check_depth(&program.body));
+    let expanded_depth = macro_processor::expanded_depth(program, depth);
+    let mut expr = try!(util::with_stack_for_depth(expanded_depth,
+        || macro_processor::process_program(program)));
+    let depth = try!(util::check_depth(&expr));
+    util::with_stack_for_depth(depth, || {
+        try!(type_inference::infer_types(&mut expr));
+        let expr = try!(expr.to_typed());
+        match expr.ty {
+            ast::Type::Function(_, ref result) => Ok((**result).clone()),
+            ref ty => Ok(ty.clone())
+        }
+    })
+}
@@ -1,19 +1,32 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::os::raw::c_void;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicI64};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use easy_ll;
 
+use super::abi::WeldAbi;
+use super::cache;
 use super::ast::*;
 use super::ast::Type::*;
 use super::ast::ExprKind::*;
 use super::ast::ScalarKind::*;
 use super::code_builder::CodeBuilder;
+use super::conf::*;
+use super::decimal;
 use super::error::*;
+use super::llvm_ir::{self, Instruction, Operand};
 use super::macro_processor;
 use super::pretty_print::*;
 use super::program::Program;
+use super::stage_log::StageLog;
+use super::transforms;
 use super::type_inference;
-use super::util::IdGenerator;
+use super::util::{check_depth, with_stack_for_depth, IdGenerator};
 
 #[cfg(test)] use super::parser::*;
 
@@ -63,36 +76,33 @@ impl LlvmGenerator {
         args: &Vec<TypedParameter>,
         body: &TypedExpr
     ) -> WeldResult<()> {
-        let mut ctx = &mut FunctionContext::new();
-        let mut arg_types = String::new();
-        for (i, arg) in args.iter().enumerate() {
-            let arg = format!("{} {}.in", try!(self.llvm_type(&arg.ty)), llvm_symbol(&arg.name));
-            arg_types.push_str(&arg);
-            if i < args.len() - 1 {
-                arg_types.push_str(", ");
-            }
+        let mut params = Vec::new();
+        for arg in args {
+            let ty = try!(self.llvm_type(&arg.ty)).to_string();
+            params.push((ty, Operand::local(format!("{}.in", llvm_symbol_name(&arg.name)))));
         }
         let res_type = try!(self.llvm_type(&body.ty)).to_string();
+        let func = llvm_ir::Function::new(name, &res_type, params, Some("entry"));
+        let ctx = &mut FunctionContext::new(func);
 
-        // Start the entry block by defining the function and storing all its arguments on the
-        // stack (this makes them consistent with other local variables). Later, expressions may
-        // add more local variables to alloca_code.
-        ctx.alloca_code.add(format!("define {} @{}({}) {{", res_type, name, arg_types));
-        ctx.alloca_code.add(format!("entry:"));
+        // Start the entry block by storing all the arguments on the stack (this makes them
+        // consistent with other local variables). Later, expressions may add more allocas, which
+        // all go at the start of the entry block.
         for arg in args {
             let name = llvm_symbol(&arg.name);
             let ty = try!(self.llvm_type(&arg.ty)).to_string();
             try!(ctx.add_alloca(&name, &ty));
-            ctx.code.add(format!("store {} {}.in, {}* {}", ty, name, ty, name));
+            let value = Operand::local(format!("{}.in", llvm_symbol_name(&arg.name)));
+            ctx.func.add(Instruction::Store { ty: ty, value: value, ptr: name, align: None });
         }
 
         // Generate an expression for the function body.
         let res_var = try!(self.gen_expr(&body, ctx));
-        ctx.code.add(format!("ret {} {}", res_type, res_var));
-        ctx.code.add(format!("}}\n\n"));
+        ctx.func.add(Instruction::Ret { ty: res_type, value: res_var });
 
-        self.body_code.add(&ctx.alloca_code.result());
-        self.body_code.add(&ctx.code.result());
+        let allocas = ctx.allocas.drain(..).collect::<Vec<_>>();
+        ctx.func.blocks[0].instructions.splice(0..0, allocas);
+        self.add_to_body(&ctx.func);
         Ok(())
     }
 
@@ -105,6 +115,8 @@ impl LlvmGenerator {
         args: &Vec<TypedParameter>,
         body: &TypedExpr
     ) -> WeldResult<()> {
+        use self::Instruction::*;
+
         // First add the function on raw values, which we'll call from the pointer version.
         let raw_function_name = format!("{}.raw", name);
         try!(self.add_function(&raw_function_name, args, body));
@@ -114,53 +126,105 @@ impl LlvmGenerator {
         let args_type = try!(self.llvm_type(&args_struct)).to_string();
 
         let res_type = try!(self.llvm_type(&body.ty)).to_string();
-        let mut code = &mut CodeBuilder::new();
-
-        code.add(format!("define i64 @{}(i64 %args) {{", name));
+        let res_ptr_type = format!("{}*", res_type);
+        let params = vec![("i64".to_string(), Operand::local("args"))];
+        let mut func = llvm_ir::Function::new(name, "i64", params, None);
 
         // Code to allocate a result structure
-        code.add(format!(
-            "%res_size_ptr = getelementptr {res_type}* null, i32 1
-             %res_size = ptrtoint {res_type}* %res_size_ptr to i64
-             %res_bytes = call i8* @malloc(i64 %res_size)
-             %res_typed = bitcast i8* %res_bytes to {res_type}*",
-            res_type = res_type
-        ));
+        func.add(GetElementPtr {
+            dest: Operand::local("res_size_ptr"),
+            ty: res_type.clone(),
+            ptr: Operand::constant("null"),
+            indices: vec![("i32".to_string(), Operand::constant(1))],
+        });
+        func.add(Cast {
+            dest: Operand::local("res_size"),
+            op: "ptrtoint",
+            value: Operand::local("res_size_ptr"),
+            from: res_ptr_type.clone(),
+            to: "i64".to_string(),
+        });
+        func.add(Call {
+            dest: Some(Operand::local("res_bytes")),
+            ty: "i8*".to_string(),
+            func: Operand::Global("malloc".to_string()),
+            args: vec![("i64".to_string(), Operand::local("res_size"))],
+        });
+        func.add(Cast {
+            dest: Operand::local("res_typed"),
+            op: "bitcast",
+            value: Operand::local("res_bytes"),
+            from: "i8*".to_string(),
+            to: res_ptr_type.clone(),
+        });
 
         // Code to load args and call function
-        code.add(format!(
-            "%args_typed = inttoptr i64 %args to {args_type}*
-             %args_val = load {args_type}* %args_typed",
-            args_type = args_type
-        ));
-        let mut arg_decls: Vec<String> = Vec::new();
+        func.add(Cast {
+            dest: Operand::local("args_typed"),
+            op: "inttoptr",
+            value: Operand::local("args"),
+            from: "i64".to_string(),
+            to: format!("{}*", args_type),
+        });
+        func.add(Load {
+            dest: Operand::local("args_val"),
+            ty: args_type.clone(),
+            ptr: Operand::local("args_typed"),
+            align: None,
+        });
+        let mut call_args = Vec::new();
         for (i, arg) in args.iter().enumerate() {
-            code.add(format!("%arg{} = extractvalue {} %args_val, {}", i, args_type, i));
-            arg_decls.push(format!("{} %arg{}", try!(self.llvm_type(&arg.ty)), i));
+            let arg_var = Operand::local(format!("arg{}", i));
+            func.add(ExtractValue {
+                dest: arg_var.clone(),
+                ty: args_type.clone(),
+                aggregate: Operand::local("args_val"),
+                index: i as u32,
+            });
+            call_args.push((try!(self.llvm_type(&arg.ty)).to_string(), arg_var));
         }
-        code.add(format!(
-            "%res_val = call {res_type} @{raw_function_name}({arg_list})
-             store {res_type} %res_val, {res_type}* %res_typed
-             %res_address = ptrtoint {res_type}* %res_typed to i64
-             ret i64 %res_address",
-            res_type = res_type,
-            raw_function_name = raw_function_name,
-            arg_list = arg_decls.join(", ")
-        ));
-        code.add(format!("}}\n\n"));
-
-        self.body_code.add_code(code);
+        func.add(Call {
+            dest: Some(Operand::local("res_val")),
+            ty: res_type.clone(),
+            func: Operand::Global(raw_function_name),
+            args: call_args,
+        });
+        func.add(Store {
+            ty: res_type,
+            value: Operand::local("res_val"),
+            ptr: Operand::local("res_typed"),
+            align: None,
+        });
+        func.add(Cast {
+            dest: Operand::local("res_address"),
+            op: "ptrtoint",
+            value: Operand::local("res_typed"),
+            from: res_ptr_type,
+            to: "i64".to_string(),
+        });
+        func.add(Ret { ty: "i64".to_string(), value: Operand::local("res_address") });
+
+        self.add_to_body(&func);
         Ok(())
     }
 
+    /// Write out a finished function, followed by a blank line.
+    fn add_to_body(&mut self, func: &llvm_ir::Function) {
+        self.body_code.add(func.to_string());
+        self.body_code.add_line("");
+    }
+
     /// Return the LLVM type name corresponding to a Weld type.
     fn llvm_type(&mut self, ty: &Type) -> WeldResult<&str> {
         match *ty {
             Scalar(Bool) => Ok("i1"),
             Scalar(I32) => Ok("i32"),
             Scalar(I64) => Ok("i64"),
-            Scalar(F32) => Ok("f32"),
-            Scalar(F64) => Ok("f64"),
+            Scalar(F32) => Ok("float"),
+            Scalar(F64) => Ok("double"),
+            Scalar(Timestamp) => Ok("i64"),
+            Scalar(Decimal(precision, _)) if precision <= MAX_DECIMAL64_PRECISION => Ok("i64"),
+            Scalar(Decimal(_, _)) => Ok("i128"),
 
             Struct(ref fields) => {
                 if self.struct_names.get(fields) == None {
@@ -190,25 +254,51 @@ impl LlvmGenerator {
         }
     }
 
-    /// Add an expression to a CodeBuilder, possibly generating prelude code earlier, and return
-    /// a string that can be used to represent its result later (e.g. %var if introducing a local
-    /// variable or an integer constant otherwise).
+    /// Add an expression to a function, possibly generating prelude code earlier, and return
+    /// an operand that can be used to represent its result later (e.g. %var if introducing a
+    /// local variable or an integer constant otherwise).
     fn gen_expr(
         &mut self,
         expr: &TypedExpr,
         ctx: &mut FunctionContext
-    ) -> WeldResult<String> {
+    ) -> WeldResult<Operand> {
+        use self::Instruction::*;
         match expr.kind {
-            I32Literal(value) => Ok(format!("{}", value)),
-            I64Literal(value) => Ok(format!("{}", value)),
-            F32Literal(value) => Ok(format!("{}", value)),
-            F64Literal(value) => Ok(format!("{}", value)),
-            BoolLiteral(value) => Ok(format!("{}", if value {1} else {0})),
+            I32Literal(value) => Ok(Operand::constant(value)),
+            I64Literal(value) => Ok(Operand::constant(value)),
+            F32Literal(value) => Ok(float_constant(value as f64)),
+            F64Literal(value) => Ok(float_constant(value)),
+            BoolLiteral(value) => Ok(Operand::constant(if value {1} else {0})),
 
             Ident(ref symbol) => {
-                let var = ctx.var_ids.next();
-                ctx.code.add(format!("{} = load {}* {}",
-                    var, try!(self.llvm_type(&expr.ty)), llvm_symbol(symbol)));
+                let var = ctx.next_var();
+                let ty = try!(self.llvm_type(&expr.ty)).to_string();
+                let ptr = llvm_symbol(symbol);
+                ctx.func.add(Load { dest: var.clone(), ty: ty, ptr: ptr, align: None });
+                Ok(var)
+            },
+
+            BinOp(kind, ref left, ref right) if is_decimal_product(kind, &left.ty) => {
+                // Rescale the product or quotient, which needs more than one instruction
+                let left_var = try!(self.gen_expr(left, ctx));
+                let right_var = try!(self.gen_expr(right, ctx));
+                let ty = try!(self.llvm_type(&left.ty)).to_string();
+                let scale = match left.ty {
+                    Scalar(Decimal(_, scale)) => scale,
+                    _ => unreachable!()
+                };
+                let func = match kind {
+                    BinOpKind::Multiply => "decimal64.mul",
+                    _ => "decimal64.div",
+                };
+                let var = ctx.next_var();
+                ctx.func.add(Call {
+                    dest: Some(var.clone()),
+                    ty: ty.clone(),
+                    func: Operand::Global(func.to_string()),
+                    args: vec![(ty.clone(), left_var), (ty.clone(), right_var),
+                        (ty, Operand::constant(decimal::pow10(scale)))],
+                });
                 Ok(var)
             },
 
@@ -216,9 +306,15 @@ impl LlvmGenerator {
                 let op_name = try!(llvm_binop(kind, &left.ty));
                 let left_var = try!(self.gen_expr(left, ctx));
                 let right_var = try!(self.gen_expr(right, ctx));
-                let var = ctx.var_ids.next();
-                ctx.code.add(format!("{} = {} {} {}, {}",
-                    var, op_name, try!(self.llvm_type(&left.ty)), left_var, right_var));
+                let var = ctx.next_var();
+                let ty = try!(self.llvm_type(&left.ty)).to_string();
+                ctx.func.add(BinOp {
+                    dest: var.clone(),
+                    op: op_name,
+                    ty: ty,
+                    left: left_var,
+                    right: right_var,
+                });
                 Ok(var)
             },
 
@@ -227,7 +323,7 @@ impl LlvmGenerator {
                 let name = llvm_symbol(name);
                 let ty = try!(self.llvm_type(&value.ty)).to_string();
                 try!(ctx.add_alloca(&name, &ty));
-                ctx.code.add(format!("store {} {}, {}* {}", ty, value_var, ty, name));
+                ctx.func.add(Store { ty: ty, value: value_var, ptr: name, align: None });
                 self.gen_expr(body, ctx)
             },
 
@@ -240,43 +336,204 @@ impl LlvmGenerator {
                 let end_false_label = format!("{}.false.end", id);
                 let end_label = format!("{}.end", id);
 
-                ctx.code.add(format!("br i1 {}, label %{}, label %{}",
-                    cond_var, true_label, false_label));
-                ctx.code.add(format!("{}:", true_label));
+                ctx.func.add(CondBr {
+                    cond: cond_var,
+                    on_true: true_label.clone(),
+                    on_false: false_label.clone(),
+                });
+                ctx.func.start_block(&true_label);
                 let true_var = try!(self.gen_expr(on_true, ctx));
-                ctx.code.add(format!("br label %{}", end_true_label));
-                ctx.code.add(format!("{}:", end_true_label));
-                ctx.code.add(format!("br label %{}", end_label));
+                ctx.func.add(Br { label: end_true_label.clone() });
+                ctx.func.start_block(&end_true_label);
+                ctx.func.add(Br { label: end_label.clone() });
 
-                ctx.code.add(format!("{}:", false_label));
+                ctx.func.start_block(&false_label);
                 let false_var = try!(self.gen_expr(on_false, ctx));
-                ctx.code.add(format!("br label %{}", end_false_label));
-                ctx.code.add(format!("{}:", end_false_label));
-                ctx.code.add(format!("br label %{}", end_label));
+                ctx.func.add(Br { label: end_false_label.clone() });
+                ctx.func.start_block(&end_false_label);
+                ctx.func.add(Br { label: end_label.clone() });
 
-                ctx.code.add(format!("{}:", end_label));
-                let var = ctx.var_ids.next();
+                ctx.func.start_block(&end_label);
+                let var = ctx.next_var();
                 let ty = try!(self.llvm_type(&expr.ty)).to_string();
-                ctx.code.add(format!("{} = phi {} [{}, %{}], [{}, %{}]",
-                    var, ty, true_var, end_true_label, false_var, end_false_label));
+                ctx.func.add(Phi {
+                    dest: var.clone(),
+                    ty: ty,
+                    incoming: vec![(true_var, end_true_label), (false_var, end_false_label)],
+                });
                 Ok(var)
             },
 
+            Builtin(kind, ref args) => {
+                let mut arg_vars = Vec::new();
+                for arg in args {
+                    arg_vars.push(try!(self.gen_expr(arg, ctx)));
+                }
+                match kind {
+                    BuiltinKind::Cast(kind) => match args[0].ty {
+                        Scalar(from) => self.gen_cast(arg_vars.remove(0), from, kind, ctx),
+                        _ => weld_err!("Unsupported expression: {}", print_expr(expr))
+                    },
+
+                    // Timestamps are already i64s of microseconds
+                    BuiltinKind::ToTimestamp | BuiltinKind::ToMicros => Ok(arg_vars.remove(0)),
+
+                    // interval_between(start, end) is end - start
+                    BuiltinKind::AddInterval | BuiltinKind::IntervalBetween => {
+                        let var = ctx.next_var();
+                        let (op, left, right) = match kind {
+                            BuiltinKind::AddInterval => ("add", &arg_vars[0], &arg_vars[1]),
+                            _ => ("sub", &arg_vars[1], &arg_vars[0]),
+                        };
+                        ctx.func.add(BinOp {
+                            dest: var.clone(),
+                            op: op,
+                            ty: "i64".to_string(),
+                            left: left.clone(),
+                            right: right.clone(),
+                        });
+                        Ok(var)
+                    }
+
+                    // Calendar functions are defined in the prelude
+                    BuiltinKind::Extract(_) | BuiltinKind::Trunc(_) => {
+                        let var = ctx.next_var();
+                        ctx.func.add(Call {
+                            dest: Some(var.clone()),
+                            ty: try!(self.llvm_type(&expr.ty)).to_string(),
+                            func: Operand::Global(format!("timestamp.{}", kind)),
+                            args: vec![("i64".to_string(), arg_vars.remove(0))],
+                        });
+                        Ok(var)
+                    }
+
+                    _ => weld_err!("Unsupported expression: {}", print_expr(expr))
+                }
+            },
+
             _ => weld_err!("Unsupported expression: {}", print_expr(expr))
         }
     }
+
+    /// Convert a value between numeric types, as described in `BuiltinKind::Cast`.
+    fn gen_cast(
+        &mut self,
+        value: Operand,
+        from: ScalarKind,
+        to: ScalarKind,
+        ctx: &mut FunctionContext
+    ) -> WeldResult<Operand> {
+        if from == to {
+            return Ok(value);
+        }
+        let from_ty = try!(self.llvm_type(&Scalar(from))).to_string();
+        let to_ty = try!(self.llvm_type(&Scalar(to))).to_string();
+        let f64_ty = try!(self.llvm_type(&Scalar(F64))).to_string();
+        let is_float = |kind| kind == F32 || kind == F64;
+        let is_fixed = |kind: ScalarKind| kind == I32 || kind == I64 || kind.is_decimal();
+        let scale = |kind| match kind {
+            Decimal(_, scale) => scale,
+            _ => 0
+        };
+
+        if is_fixed(from) && is_fixed(to) {
+            // Rescale in the wider of the two types, then resize to the result
+            let (from_scale, to_scale) = (scale(from), scale(to));
+            let wide_ty = if from_ty == "i128" || to_ty == "i128" { "i128" } else { "i64" };
+            let mut value = ctx.resize_int(value, &from_ty, wide_ty);
+            if to_scale > from_scale {
+                let factor = Operand::constant(decimal::pow10(to_scale - from_scale));
+                value = ctx.add_binop("mul", wide_ty, value, factor);
+            } else if to_scale < from_scale && to.is_decimal() {
+                let factor = Operand::constant(decimal::pow10(from_scale - to_scale));
+                let value128 = ctx.resize_int(value, wide_ty, "i128");
+                let var = ctx.next_var();
+                ctx.func.add(Instruction::Call {
+                    dest: Some(var.clone()),
+                    ty: "i128".to_string(),
+                    func: Operand::Global("i128.div_round".to_string()),
+                    args: vec![("i128".to_string(), value128), ("i128".to_string(), factor)],
+                });
+                value = ctx.resize_int(var, "i128", wide_ty);
+            } else if to_scale < from_scale {
+                // Conversions to integers truncate
+                let factor = Operand::constant(decimal::pow10(from_scale - to_scale));
+                value = ctx.add_binop("sdiv", wide_ty, value, factor);
+            }
+            Ok(ctx.resize_int(value, wide_ty, &to_ty))
+        } else if is_fixed(from) && is_float(to) {
+            let from_scale = scale(from);
+            if from_scale == 0 {
+                return Ok(ctx.add_cast("sitofp", value, &from_ty, &to_ty));
+            }
+            let value = ctx.add_cast("sitofp", value, &from_ty, &f64_ty);
+            let factor = float_constant(decimal::pow10_f64(from_scale));
+            let value = ctx.add_binop("fdiv", &f64_ty, value, factor);
+            if to == F32 {
+                Ok(ctx.add_cast("fptrunc", value, &f64_ty, &to_ty))
+            } else {
+                Ok(value)
+            }
+        } else if is_float(from) && is_fixed(to) {
+            let value = if to.is_decimal() {
+                // Round to the nearest unit of the last digit
+                let value = if from == F32 {
+                    ctx.add_cast("fpext", value, &from_ty, &f64_ty)
+                } else {
+                    value
+                };
+                let factor = float_constant(decimal::pow10_f64(scale(to)));
+                let scaled = ctx.add_binop("fmul", &f64_ty, value, factor);
+                let var = ctx.next_var();
+                ctx.func.add(Instruction::Call {
+                    dest: Some(var.clone()),
+                    ty: f64_ty.clone(),
+                    func: Operand::Global("llvm.round.f64".to_string()),
+                    args: vec![(f64_ty.clone(), scaled)],
+                });
+                ctx.add_cast("fptosi", var, &f64_ty, &to_ty)
+            } else {
+                ctx.add_cast("fptosi", value, &from_ty, &to_ty)
+            };
+            Ok(value)
+        } else if from == F32 && to == F64 {
+            Ok(ctx.add_cast("fpext", value, &from_ty, &to_ty))
+        } else if from == F64 && to == F32 {
+            Ok(ctx.add_cast("fptrunc", value, &from_ty, &to_ty))
+        } else {
+            weld_err!("Unsupported cast from {} to {}", from, to)
+        }
+    }
 }
 
-/// Return the LLVM version of a Weld symbol (encoding any special characters for LLVM).
-fn llvm_symbol(symbol: &Symbol) -> String {
+/// Return the LLVM name of a Weld symbol, without the leading `%`.
+fn llvm_symbol_name(symbol: &Symbol) -> String {
     if symbol.id == 0 {
-        format!("%{}", symbol.name)
+        symbol.name.to_string()
     } else {
-        format!("%{}.{}", symbol.name, symbol.id)
+        format!("{}.{}", symbol.name, symbol.id)
     }
 }
 
+/// Return the local variable that holds a Weld symbol.
+fn llvm_symbol(symbol: &Symbol) -> Operand {
+    Operand::Local(llvm_symbol_name(symbol))
+}
+
+/// Whether a binary operation multiplies or divides decimals, which generates a call to a prelude
+/// function rather than a single instruction.
+fn is_decimal_product(op_kind: BinOpKind, ty: &Type) -> bool {
+    match *ty {
+        Scalar(Decimal(_, _)) => op_kind == BinOpKind::Multiply || op_kind == BinOpKind::Divide,
+        _ => false
+    }
+}
 
+/// Return a floating-point constant in the exact hexadecimal form LLVM accepts for any value. An
+/// f32 is written as the f64 with the same value, as LLVM expects for `float` constants too.
+fn float_constant(value: f64) -> Operand {
+    Operand::Const(format!("0x{:016X}", value.to_bits()))
+}
 
 /// Return the name of the LLVM instruction for a binary operation on a specific type.
 fn llvm_binop(op_kind: BinOpKind, ty: &Type) -> WeldResult<&'static str> {
@@ -285,11 +542,13 @@ fn llvm_binop(op_kind: BinOpKind, ty: &Type) -> WeldResult<&'static str> {
         (BinOpKind::Add, &Scalar(I64)) => Ok("add"),
         (BinOpKind::Add, &Scalar(F32)) => Ok("fadd"),
         (BinOpKind::Add, &Scalar(F64)) => Ok("fadd"),
+        (BinOpKind::Add, &Scalar(Decimal(_, _))) => Ok("add"),
 
         (BinOpKind::Subtract, &Scalar(I32)) => Ok("sub"),
         (BinOpKind::Subtract, &Scalar(I64)) => Ok("sub"),
         (BinOpKind::Subtract, &Scalar(F32)) => Ok("fsub"),
         (BinOpKind::Subtract, &Scalar(F64)) => Ok("fsub"),
+        (BinOpKind::Subtract, &Scalar(Decimal(_, _))) => Ok("sub"),
 
         (BinOpKind::Multiply, &Scalar(I32)) => Ok("mul"),
         (BinOpKind::Multiply, &Scalar(I64)) => Ok("mul"),
@@ -305,31 +564,43 @@ fn llvm_binop(op_kind: BinOpKind, ty: &Type) -> WeldResult<&'static str> {
         (BinOpKind::Equal, &Scalar(I64)) => Ok("icmp eq"),
         (BinOpKind::Equal, &Scalar(F32)) => Ok("fcmp oeq"),
         (BinOpKind::Equal, &Scalar(F64)) => Ok("fcmp oeq"),
+        (BinOpKind::Equal, &Scalar(Timestamp)) => Ok("icmp eq"),
+        (BinOpKind::Equal, &Scalar(Decimal(_, _))) => Ok("icmp eq"),
 
         (BinOpKind::NotEqual, &Scalar(I32)) => Ok("icmp ne"),
         (BinOpKind::NotEqual, &Scalar(I64)) => Ok("icmp ne"),
         (BinOpKind::NotEqual, &Scalar(F32)) => Ok("fcmp one"),
         (BinOpKind::NotEqual, &Scalar(F64)) => Ok("fcmp one"),
+        (BinOpKind::NotEqual, &Scalar(Timestamp)) => Ok("icmp ne"),
+        (BinOpKind::NotEqual, &Scalar(Decimal(_, _))) => Ok("icmp ne"),
 
         (BinOpKind::LessThan, &Scalar(I32)) => Ok("icmp slt"),
         (BinOpKind::LessThan, &Scalar(I64)) => Ok("icmp slt"),
         (BinOpKind::LessThan, &Scalar(F32)) => Ok("fcmp olt"),
         (BinOpKind::LessThan, &Scalar(F64)) => Ok("fcmp olt"),
+        (BinOpKind::LessThan, &Scalar(Timestamp)) => Ok("icmp slt"),
+        (BinOpKind::LessThan, &Scalar(Decimal(_, _))) => Ok("icmp slt"),
 
         (BinOpKind::LessThanOrEqual, &Scalar(I32)) => Ok("icmp sle"),
         (BinOpKind::LessThanOrEqual, &Scalar(I64)) => Ok("icmp sle"),
         (BinOpKind::LessThanOrEqual, &Scalar(F32)) => Ok("fcmp ole"),
         (BinOpKind::LessThanOrEqual, &Scalar(F64)) => Ok("fcmp ole"),
+        (BinOpKind::LessThanOrEqual, &Scalar(Timestamp)) => Ok("icmp sle"),
+        (BinOpKind::LessThanOrEqual, &Scalar(Decimal(_, _))) => Ok("icmp sle"),
 
         (BinOpKind::GreaterThan, &Scalar(I32)) => Ok("icmp sgt"),
         (BinOpKind::GreaterThan, &Scalar(I64)) => Ok("icmp sgt"),
         (BinOpKind::GreaterThan, &Scalar(F32)) => Ok("fcmp ogt"),
         (BinOpKind::GreaterThan, &Scalar(F64)) => Ok("fcmp ogt"),
+        (BinOpKind::GreaterThan, &Scalar(Timestamp)) => Ok("icmp sgt"),
+        (BinOpKind::GreaterThan, &Scalar(Decimal(_, _))) => Ok("icmp sgt"),
 
         (BinOpKind::GreaterThanOrEqual, &Scalar(I32)) => Ok("icmp sge"),
         (BinOpKind::GreaterThanOrEqual, &Scalar(I64)) => Ok("icmp sge"),
         (BinOpKind::GreaterThanOrEqual, &Scalar(F32)) => Ok("fcmp oge"),
         (BinOpKind::GreaterThanOrEqual, &Scalar(F64)) => Ok("fcmp oge"),
+        (BinOpKind::GreaterThanOrEqual, &Scalar(Timestamp)) => Ok("icmp sge"),
+        (BinOpKind::GreaterThanOrEqual, &Scalar(Decimal(_, _))) => Ok("icmp sge"),
 
         _ => weld_err!("Unsupported binary op: {} on {}", op_kind, print_type(ty))
     }
@@ -337,50 +608,315 @@ fn llvm_binop(op_kind: BinOpKind, ty: &Type) -> WeldResult<&'static str> {
 
 /// Struct used to track state while generating a function.
 struct FunctionContext {
-    /// Code section at the start of the function with alloca instructions for local symbols
-    alloca_code: CodeBuilder,
-    /// Other code in function
-    code: CodeBuilder,
-    defined_symbols: HashSet<String>,
+    /// Alloca instructions for local symbols, which are placed at the start of the entry block
+    allocas: Vec<Instruction>,
+    /// The function being generated
+    func: llvm_ir::Function,
+    defined_symbols: HashSet<Operand>,
     var_ids: IdGenerator,
     if_ids: IdGenerator,
 }
 
 impl FunctionContext {
-    fn new() -> FunctionContext {
+    fn new(func: llvm_ir::Function) -> FunctionContext {
         FunctionContext {
-            alloca_code: CodeBuilder::new(),
-            code: CodeBuilder::new(),
-            var_ids: IdGenerator::new("%"),
+            allocas: Vec::new(),
+            func: func,
+            var_ids: IdGenerator::new(""),
             if_ids: IdGenerator::new("if"),
             defined_symbols: HashSet::new(),
         }
     }
 
-    fn add_alloca(&mut self, symbol: &str, ty: &str) -> WeldResult<()> {
-        if !self.defined_symbols.insert(symbol.to_string()) {
+    /// Create a new local variable for a temporary value.
+    fn next_var(&mut self) -> Operand {
+        Operand::local(self.var_ids.next())
+    }
+
+    /// Add a binary operation on two values of type `ty` and return its result.
+    fn add_binop(&mut self, op: &'static str, ty: &str, left: Operand, right: Operand)
+            -> Operand {
+        let var = self.next_var();
+        self.func.add(Instruction::BinOp {
+            dest: var.clone(),
+            op: op,
+            ty: ty.to_string(),
+            left: left,
+            right: right,
+        });
+        var
+    }
+
+    /// Add a conversion of a value from type `from` to type `to` and return its result.
+    fn add_cast(&mut self, op: &'static str, value: Operand, from: &str, to: &str) -> Operand {
+        let var = self.next_var();
+        self.func.add(Instruction::Cast {
+            dest: var.clone(),
+            op: op,
+            value: value,
+            from: from.to_string(),
+            to: to.to_string(),
+        });
+        var
+    }
+
+    /// Sign-extend or truncate an integer from type `from` to type `to`, if they differ.
+    fn resize_int(&mut self, value: Operand, from: &str, to: &str) -> Operand {
+        let bits = |ty: &str| ty[1..].parse::<u32>().unwrap();
+        if bits(from) < bits(to) {
+            self.add_cast("sext", value, from, to)
+        } else if bits(from) > bits(to) {
+            self.add_cast("trunc", value, from, to)
+        } else {
+            value
+        }
+    }
+
+    fn add_alloca(&mut self, symbol: &Operand, ty: &str) -> WeldResult<()> {
+        if !self.defined_symbols.insert(symbol.clone()) {
             weld_err!("Symbol already defined in function: {}", symbol)
         } else {
-            self.alloca_code.add(format!("{} = alloca {}", symbol, ty));
+            self.allocas.push(Instruction::Alloca { dest: symbol.clone(), ty: ty.to_string() });
             Ok(())
         }
     }
 }
 
-/// Generate a compiled LLVM module from a program whose body is a function.
-pub fn compile_program(program: &Program) -> WeldResult<easy_ll::CompiledModule> {
+extern "C" {
+    fn free(ptr: *mut c_void);
+}
+
+static NEXT_RUN_ID: AtomicI64 = AtomicI64::new(0);
+
+/// Pick an ID for a new run of a module, unique within the process. Errors from the run carry
+/// it, so that they can be matched up with whatever else the caller recorded about the run.
+pub fn new_run_id() -> i64 {
+    NEXT_RUN_ID.fetch_add(1, atomic::Ordering::Relaxed)
+}
+
+/// A compiled Weld program, along with the types of its parameters and result.
+pub struct WeldModule {
+    module: easy_ll::CompiledModule,
+    param_types: Vec<Type>,
+    return_type: Type,
+    llvm_optimization_level: u32,
+    skipped_passes: Vec<String>,
+}
+
+impl WeldModule {
+    /// The types of the program's parameters, in order.
+    pub fn param_types(&self) -> &[Type] {
+        &self.param_types
+    }
+
+    /// The type of the program's result.
+    pub fn return_type(&self) -> &Type {
+        &self.return_type
+    }
+
+    /// The LLVM optimization level the module was compiled with. This is lower than the
+    /// configured level if the compile time budget ran low.
+    pub fn llvm_optimization_level(&self) -> u32 {
+        self.llvm_optimization_level
+    }
+
+    /// Optimization passes that were skipped because the compile time budget ran out.
+    pub fn skipped_passes(&self) -> &[String] {
+        &self.skipped_passes
+    }
+
+    /// Run the program on a pointer to a struct of its parameters, encoded as an i64. Returns a
+    /// pointer to a newly malloc'd result, which the caller must free.
+    pub fn run(&self, arg: i64) -> i64 {
+        self.module.run(arg)
+    }
+
+    /// Run the program on a Rust value holding its parameters and return a copy of the result.
+    /// `Args` may be a struct of all the parameters or, for single-parameter programs, the
+    /// parameter type itself; `()` can be passed to programs without parameters.
+    pub fn run_typed<Args: WeldAbi, Out: WeldAbi>(&self, args: &Args) -> WeldResult<Out> {
+        let run_id = new_run_id();
+        try!(check_signature::<Args, Out>(&self.param_types, &self.return_type)
+            .map_err(|e| e.with_run_id(run_id)));
+        unsafe {
+            let result = self.run(args as *const Args as i64) as *mut Out;
+            let value = *result;
+            free(result as *mut c_void);
+            Ok(value)
+        }
+    }
+}
+
+/// Check that Rust types `Args` and `Out` can be used to call a program with the given
+/// parameter and return types, and that their layouts match what generated code expects.
+fn check_signature<Args: WeldAbi, Out: WeldAbi>(param_types: &[Type], return_type: &Type)
+        -> WeldResult<()> {
+    let args_type = Args::weld_type();
+    let params_struct = Struct(param_types.to_vec());
+    if args_type != params_struct && !(param_types.len() == 1 && args_type == param_types[0]) {
+        return weld_err!(Runtime, "Argument type {} does not match program parameters {}",
+            print_type(&args_type), print_type(&params_struct));
+    }
+    if Out::weld_type() != *return_type {
+        return weld_err!(Runtime, "Result type {} does not match program result {}",
+            Out::weld_type_string(), print_type(return_type));
+    }
+    try!(Args::check_layout().map_err(|e| e.with_kind(ErrorKind::Runtime)));
+    try!(Out::check_layout().map_err(|e| e.with_kind(ErrorKind::Runtime)));
+    Ok(())
+}
+
+/// LLVM code generated for a program whose body is a function, along with its signature.
+#[derive(Clone)]
+pub struct GeneratedCode {
+    pub llvm_code: String,
+    /// The program after macro expansion, transforms and type inference.
+    pub expr: TypedExpr,
+    pub param_types: Vec<Type>,
+    pub return_type: Type,
+    /// Optimization passes that were skipped because the compile time budget ran out.
+    pub skipped_passes: Vec<String>,
+    /// Time taken to generate the code.
+    pub elapsed: Duration,
+}
+
+/// Generate LLVM code for a program whose body is a function, without compiling it. The stages
+/// listed in the configuration's `dump_stages` are dumped as described in `stage_log`.
+pub fn generate_code(program: &Program, conf: &CompileConf) -> WeldResult<GeneratedCode> {
+    let depth = try!(check_depth(&program.body));
+    with_stack_for_depth(macro_processor::expanded_depth(program, depth),
+        || generate_code_with_log(program, conf, &mut StageLog::new(conf)))
+}
+
+/// Same as `generate_code`, but sends stage dumps to the given log. Unlike `generate_code`, this
+/// runs on the caller's thread, so programs nested thousands of levels deep need a large stack.
+pub fn generate_code_with_log(program: &Program, conf: &CompileConf, log: &mut StageLog)
+        -> WeldResult<GeneratedCode> {
+    let start = Instant::now();
+    try!(log.dump("parse", || print_program(program)));
+    try!(check_depth(&program.body));
     let mut expr = try!(macro_processor::process_program(program));
+    try!(log.dump("macros", || print_expr(&expr)));
+    let mut skipped_passes = Vec::new();
+    for pass in &conf.optimization_passes {
+        let over_budget = conf.time_budget_ms.map_or(false, |b| millis(start.elapsed()) >= b);
+        if over_budget && !REQUIRED_TRANSFORMS.contains(&pass.as_str()) {
+            skipped_passes.push(pass.clone());
+            continue;
+        }
+        try!(transforms::apply_pass(pass, &mut expr));
+        try!(log.dump(pass, || print_expr(&expr)));
+    }
+    try!(check_depth(&expr));
     try!(type_inference::infer_types(&mut expr));
+    try!(log.dump("types", || print_typed_expr(&expr)));
     let expr = try!(expr.to_typed());
-    match expr.kind {
+    let (llvm_code, param_types, return_type) = match expr.kind {
         Lambda(ref params, ref body) => {
             let mut gen = LlvmGenerator::new();
-            try!(gen.add_function_on_pointers("run", params, body));
-            println!("{}", gen.result());
-            Ok(try!(easy_ll::compile_module(&gen.result())))
+            try!(gen.add_function_on_pointers("run", params, body)
+                .map_err(|e| e.with_kind(ErrorKind::Codegen)));
+            (gen.result(), params.iter().map(|p| p.ty.clone()).collect(), body.ty.clone())
         },
-        _ => weld_err!("Expression passed to compile_function must be a Lambda")
+        _ => return weld_err!(Type, "Expression passed to compile_function must be a Lambda")
+    };
+    try!(log.dump("llvm", || llvm_code.clone()));
+    Ok(GeneratedCode {
+        llvm_code: llvm_code,
+        expr: expr,
+        param_types: param_types,
+        return_type: return_type,
+        skipped_passes: skipped_passes,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Pick the LLVM optimization level to use given the time already spent compiling. LLVM's
+/// optimizations usually take longer than the rest of compilation, so we drop to level 1 once
+/// half of the budget is spent and to level 0 once all of it is.
+fn budget_optimization_level(level: u32, budget_ms: Option<u64>, elapsed: Duration) -> u32 {
+    match budget_ms {
+        Some(budget) if millis(elapsed) >= budget => 0,
+        Some(budget) if millis(elapsed) * 2 >= budget => ::std::cmp::min(level, 1),
+        _ => level
+    }
+}
+
+/// Run `compile` at optimization level `level`, or at level 0 if that takes longer than
+/// `deadline`, and return its result with the level it was run at. The first attempt runs on
+/// its own thread so that we can stop waiting for it at the deadline; it is then left to finish
+/// in the background and its result is dropped.
+fn compile_with_deadline<T, F>(level: u32, deadline: Option<Duration>, compile: F) -> (T, u32)
+        where T: Send + 'static, F: Fn(u32) -> T + Send + Sync + 'static {
+    let deadline = match deadline {
+        Some(deadline) if level > 0 => deadline,
+        _ => return (compile(level), level)
+    };
+    let compile = Arc::new(compile);
+    let (sender, receiver) = mpsc::channel();
+    let attempt = compile.clone();
+    let spawned = thread::Builder::new().name("weld-llvm".to_string()).spawn(move || {
+        // The receiver is gone if we already gave up on this attempt
+        let _ = sender.send(attempt(level));
+    });
+    if spawned.is_ok() {
+        if let Ok(result) = receiver.recv_timeout(deadline) {
+            return (result, level);
+        }
+    }
+    (compile(0), 0)
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+/// Generate a compiled LLVM module from a program whose body is a function.
+pub fn compile_program(program: &Program, conf: &WeldConf) -> WeldResult<WeldModule> {
+    let conf = try!(CompileConf::parse(conf));
+    let code = try!(generate_code(program, &conf));
+    compile_generated(code, &conf)
+}
+
+/// Compile a program given as source code, like `compile_program`. Programs that were compiled
+/// recently with the same transforms skip parsing, macro expansion, type inference and code
+/// generation by reusing their results from the front-end cache (see `cache`).
+pub fn compile_source(source: &str, conf: &WeldConf) -> WeldResult<WeldModule> {
+    let conf = try!(CompileConf::parse(conf));
+    let code = try!(cache::generate_code_cached(source, &conf, &cache::FRONTEND_CACHE));
+    compile_generated(code, &conf)
+}
+
+/// Compile generated LLVM code into a module.
+fn compile_generated(code: GeneratedCode, conf: &CompileConf) -> WeldResult<WeldModule> {
+    if conf.dump_code {
+        println!("{}", code.llvm_code);
     }
+    let opt_level = budget_optimization_level(
+        conf.llvm_optimization_level, conf.time_budget_ms, code.elapsed);
+    let remaining = conf.time_budget_ms.map(|budget| {
+        Duration::from_millis(budget).checked_sub(code.elapsed).unwrap_or(Duration::from_millis(0))
+    });
+    let llvm_code = code.llvm_code;
+    let (module, opt_level) = compile_with_deadline(opt_level, remaining,
+        move |level| easy_ll::compile_module_with_opt_level(&llvm_code, level));
+    let module = try!(module);
+    Ok(WeldModule {
+        module: module,
+        param_types: code.param_types,
+        return_type: code.return_type,
+        llvm_optimization_level: opt_level,
+        skipped_passes: code.skipped_passes,
+    })
+}
+
+/// Whether the LLVM this crate is linked against accepts generated code. Code generation emits
+/// the getelementptr and load syntax of LLVM versions before 3.7, which newer versions reject,
+/// so tests that run compiled programs against other results check this first and are skipped
+/// where it fails.
+#[cfg(test)]
+pub fn jit_available() -> bool {
+    compile_program(&parse_program("|| 40 + 2").unwrap(), &WeldConf::new()).is_ok()
 }
 
 #[test]
@@ -389,8 +925,8 @@ fn types() {
 
     assert_eq!(gen.llvm_type(&Scalar(I32)).unwrap(), "i32");
     assert_eq!(gen.llvm_type(&Scalar(I64)).unwrap(), "i64");
-    assert_eq!(gen.llvm_type(&Scalar(F32)).unwrap(), "f32");
-    assert_eq!(gen.llvm_type(&Scalar(F64)).unwrap(), "f64");
+    assert_eq!(gen.llvm_type(&Scalar(F32)).unwrap(), "float");
+    assert_eq!(gen.llvm_type(&Scalar(F64)).unwrap(), "double");
     assert_eq!(gen.llvm_type(&Scalar(Bool)).unwrap(), "i1");
 
     let struct1 = parse_type("{i32,bool,i32}").unwrap().to_type().unwrap();
@@ -404,7 +940,7 @@ fn types() {
 #[test]
 fn basic_program() {
     let code = "|| 40 + 2";
-    let module = compile_program(&parse_program(code).unwrap()).unwrap();
+    let module = compile_program(&parse_program(code).unwrap(), &WeldConf::new()).unwrap();
     let result = module.run(0) as *const i32;
     let result = unsafe { *result };
     assert_eq!(result, 42);
@@ -414,7 +950,7 @@ fn basic_program() {
 #[test]
 fn program_with_args() {
     let code = "|x:i32| 40 + x";
-    let module = compile_program(&parse_program(code).unwrap()).unwrap();
+    let module = compile_program(&parse_program(code).unwrap(), &WeldConf::new()).unwrap();
     let input: i32 = 2;
     let result = module.run(&input as *const i32 as i64) as *const i32;
     let result = unsafe { *result };
@@ -425,7 +961,7 @@ fn program_with_args() {
 #[test]
 fn let_statement() {
     let code = "|x:i32| let y = 40 + x; y + 2";
-    let module = compile_program(&parse_program(code).unwrap()).unwrap();
+    let module = compile_program(&parse_program(code).unwrap(), &WeldConf::new()).unwrap();
     let input: i32 = 2;
     let result = module.run(&input as *const i32 as i64) as *const i32;
     let result = unsafe { *result };
@@ -436,7 +972,7 @@ fn let_statement() {
 #[test]
 fn if_statement() {
     let code = "|x:i32| if(true, 3, 4)";
-    let module = compile_program(&parse_program(code).unwrap()).unwrap();
+    let module = compile_program(&parse_program(code).unwrap(), &WeldConf::new()).unwrap();
     let input: i32 = 2;
     let result = module.run(&input as *const i32 as i64) as *const i32;
     let result = unsafe { *result };
@@ -447,7 +983,7 @@ fn if_statement() {
 #[test]
 fn comparison() {
     let code = "|x:i32| if(x>10, x, 10)";
-    let module = compile_program(&parse_program(code).unwrap()).unwrap();
+    let module = compile_program(&parse_program(code).unwrap(), &WeldConf::new()).unwrap();
     let input: i32 = 2;
     let result = module.run(&input as *const i32 as i64) as *const i32;
     let result = unsafe { *result };
@@ -459,3 +995,105 @@ fn comparison() {
     assert_eq!(result, 20);
     // TODO: Free result
 }
+
+#[test]
+fn time_budget() {
+    let ms = |n| Duration::from_millis(n);
+    assert_eq!(budget_optimization_level(3, None, ms(10000)), 3);
+    assert_eq!(budget_optimization_level(3, Some(100), ms(10)), 3);
+    assert_eq!(budget_optimization_level(3, Some(100), ms(50)), 1);
+    assert_eq!(budget_optimization_level(0, Some(100), ms(50)), 0);
+    assert_eq!(budget_optimization_level(2, Some(100), ms(100)), 0);
+
+    // Passes that code generation needs still run when the budget is used up
+    let mut conf = WeldConf::new();
+    conf.set(TIME_BUDGET_KEY, "0");
+    let conf = CompileConf::parse(&conf).unwrap();
+    let program = parse_program("|x:i32| (|y| y + 1)(x)").unwrap();
+    let code = generate_code(&program, &conf).unwrap();
+    assert_eq!(code.skipped_passes.len(), 0);
+
+    // LLVM is given what is left of the budget, after which we compile at level 0 instead
+    let compile = |level: u32| {
+        if level > 0 {
+            thread::sleep(Duration::from_millis(200));
+        }
+        level * 10
+    };
+    assert_eq!(compile_with_deadline(3, None, compile), (30, 3));
+    assert_eq!(compile_with_deadline(3, Some(Duration::from_secs(60)), compile), (30, 3));
+    assert_eq!(compile_with_deadline(3, Some(Duration::from_millis(10)), compile), (0, 0));
+    assert_eq!(compile_with_deadline(0, Some(Duration::from_millis(0)), compile), (0, 0));
+}
+
+#[test]
+fn timestamp_code() {
+    let program = parse_program("|t:timestamp| \
+        if(t < add_interval(t, 1L), extract_year(t), 0)").unwrap();
+    let code = generate_code(&program, &CompileConf::parse(&WeldConf::new()).unwrap()).unwrap();
+    assert!(code.llvm_code.contains("define i32 @run.raw(i64 %t.in)"));
+    assert!(code.llvm_code.contains("add i64 %1, 1"));
+    assert!(code.llvm_code.contains("call i32 @timestamp.extract_year(i64 %"));
+}
+
+#[test]
+fn decimal_code() {
+    let conf = CompileConf::parse(&WeldConf::new()).unwrap();
+    let program = parse_program("|a:decimal[10,2], x:f64| \
+        f64(a * decimal[10,2](x) + decimal[10,2](1))").unwrap();
+    let code = generate_code(&program, &conf).unwrap().llvm_code;
+    assert!(code.contains("call double @llvm.round.f64(double %"));
+    assert!(code.contains("call i64 @decimal64.mul(i64 %0, i64 %4, i64 100)"));
+    assert!(code.contains("%6 = sext i32 1 to i64\n  %7 = mul i64 %6, 100"));
+
+    let program = parse_program("|a:decimal[18,3], b:decimal[18,3]| a / b").unwrap();
+    let code = generate_code(&program, &conf).unwrap().llvm_code;
+    assert!(code.contains("call i64 @decimal64.div(i64 %0, i64 %1, i64 1000)"));
+    assert!(code.contains("%is_zero = icmp eq i64 %b, 0"));
+
+    let program = parse_program("|a:decimal[30,4]| i32(decimal[20,1](a) - a)").unwrap();
+    assert!(generate_code(&program, &conf).is_err());
+    let program = parse_program("|a:decimal[30,4]| i32(decimal[20,1](a))").unwrap();
+    let code = generate_code(&program, &conf).unwrap().llvm_code;
+    assert!(code.contains("define i32 @run.raw(i128 %a.in)"));
+    assert!(code.contains("call i128 @i128.div_round(i128 %0, i128 1000)"));
+    assert!(code.contains("sdiv i128 %1, 10"));
+    assert!(code.contains("trunc i128 %2 to i32"));
+}
+
+#[test]
+fn typed_signatures() {
+    let params = vec![Scalar(I32), Scalar(I64)];
+    assert!(check_signature::<i32, i64>(&params, &Scalar(I64)).is_err());
+    assert!(check_signature::<(), i64>(&params, &Scalar(I64)).is_err());
+
+    let params = vec![Scalar(I32)];
+    assert!(check_signature::<i32, i32>(&params, &Scalar(I32)).is_ok());
+    assert!(check_signature::<i32, i64>(&params, &Scalar(I32)).is_err());
+    assert!(check_signature::<f32, i32>(&params, &Scalar(I32)).is_err());
+
+    assert!(check_signature::<(), bool>(&vec![], &Scalar(Bool)).is_ok());
+}
+
+#[test]
+fn run_typed() {
+    let code = "|x:i32| if(x>10, x, 10)";
+    let module = compile_program(&parse_program(code).unwrap(), &WeldConf::new()).unwrap();
+    assert_eq!(module.run_typed::<i32, i32>(&20).unwrap(), 20);
+    assert!(module.run_typed::<i64, i32>(&20).is_err());
+}
+
+#[test]
+fn run_ids() {
+    let module = WeldModule {
+        module: easy_ll::compile_module("define i64 @run(i64 %arg) {\n ret i64 0\n}").unwrap(),
+        param_types: vec![Scalar(I32)],
+        return_type: Scalar(I32),
+        llvm_optimization_level: 2,
+        skipped_passes: vec![],
+    };
+    // Each run gets a new ID, which errors from it carry
+    let first = module.run_typed::<i64, i32>(&1).unwrap_err().run_id().unwrap();
+    let second = module.run_typed::<i64, i32>(&1).unwrap_err().run_id().unwrap();
+    assert!(second > first);
+}
@@ -0,0 +1,269 @@
+//! A structured form of the LLVM IR that Weld generates.
+//!
+//! Code generation builds `Function`s out of typed `Instruction`s instead of formatting IR text
+//! directly, and only turns them into text when the module is finished. Keeping instructions
+//! structured means names are always escaped the same way and that attributes such as alignment
+//! are emitted in one place rather than in every format string that mentions a load or store.
+
+use std::fmt;
+
+/// A value used by an instruction: a local variable, a global, or a constant written inline.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Local(String),
+    Global(String),
+    Const(String),
+}
+
+impl Operand {
+    /// A local variable with the given name (without the leading `%`).
+    pub fn local<S: Into<String>>(name: S) -> Operand {
+        Operand::Local(name.into())
+    }
+
+    /// A constant written as the given text, e.g. `1`, `2.5` or `null`.
+    pub fn constant<T: fmt::Display>(value: T) -> Operand {
+        Operand::Const(value.to_string())
+    }
+}
+
+/// Write a local or global name, quoting it if LLVM would not accept it unquoted: if it contains
+/// characters other than letters, digits and `._$-`, or starts with a digit without being a
+/// plain number like the `%0` of an unnamed value.
+fn write_name(f: &mut fmt::Formatter, sigil: char, name: &str) -> fmt::Result {
+    try!(write!(f, "{}", sigil));
+    write_unprefixed_name(f, name)
+}
+
+/// Write a name as `write_name` does but without its sigil, as block labels are defined.
+fn write_unprefixed_name(f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+    let valid_chars = !name.is_empty() && name.chars().all(|c| {
+        c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '$' || c == '-'
+    });
+    let numbered = name.chars().all(|c| c.is_ascii_digit());
+    let starts_with_digit = name.starts_with(|c: char| c.is_ascii_digit());
+    if valid_chars && (numbered || !starts_with_digit) {
+        f.write_str(name)
+    } else {
+        try!(write!(f, "\""));
+        for b in name.bytes() {
+            if b == b'"' || b == b'\\' || b < 0x20 || b >= 0x7f {
+                try!(write!(f, "\\{:02X}", b));
+            } else {
+                try!(write!(f, "{}", b as char));
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Local(ref name) => write_name(f, '%', name),
+            Operand::Global(ref name) => write_name(f, '@', name),
+            Operand::Const(ref text) => f.write_str(text),
+        }
+    }
+}
+
+/// A single instruction. Types are written as LLVM type names, e.g. `i32` or `%s0`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instruction {
+    Alloca { dest: Operand, ty: String },
+    Load { dest: Operand, ty: String, ptr: Operand, align: Option<u32> },
+    Store { ty: String, value: Operand, ptr: Operand, align: Option<u32> },
+    /// A binary arithmetic instruction or comparison; `op` is e.g. `add` or `icmp slt`.
+    BinOp { dest: Operand, op: &'static str, ty: String, left: Operand, right: Operand },
+    /// A conversion such as `bitcast`, `ptrtoint` or `inttoptr`.
+    Cast { dest: Operand, op: &'static str, value: Operand, from: String, to: String },
+    /// Index into a pointer to `ty` with the given typed indices.
+    GetElementPtr { dest: Operand, ty: String, ptr: Operand, indices: Vec<(String, Operand)> },
+    ExtractValue { dest: Operand, ty: String, aggregate: Operand, index: u32 },
+    Call { dest: Option<Operand>, ty: String, func: Operand, args: Vec<(String, Operand)> },
+    /// Incoming values are paired with the labels of the blocks they come from.
+    Phi { dest: Operand, ty: String, incoming: Vec<(Operand, String)> },
+    Br { label: String },
+    CondBr { cond: Operand, on_true: String, on_false: String },
+    Ret { ty: String, value: Operand },
+}
+
+fn write_align(f: &mut fmt::Formatter, align: Option<u32>) -> fmt::Result {
+    match align {
+        Some(align) => write!(f, ", align {}", align),
+        None => Ok(())
+    }
+}
+
+fn write_typed_list(f: &mut fmt::Formatter, values: &[(String, Operand)]) -> fmt::Result {
+    for (i, &(ref ty, ref value)) in values.iter().enumerate() {
+        try!(write!(f, "{}{} {}", if i > 0 { ", " } else { "" }, ty, value));
+    }
+    Ok(())
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Instruction::*;
+        match *self {
+            Alloca { ref dest, ref ty } => write!(f, "{} = alloca {}", dest, ty),
+            Load { ref dest, ref ty, ref ptr, align } => {
+                try!(write!(f, "{} = load {}* {}", dest, ty, ptr));
+                write_align(f, align)
+            }
+            Store { ref ty, ref value, ref ptr, align } => {
+                try!(write!(f, "store {} {}, {}* {}", ty, value, ty, ptr));
+                write_align(f, align)
+            }
+            BinOp { ref dest, op, ref ty, ref left, ref right } =>
+                write!(f, "{} = {} {} {}, {}", dest, op, ty, left, right),
+            Cast { ref dest, op, ref value, ref from, ref to } =>
+                write!(f, "{} = {} {} {} to {}", dest, op, from, value, to),
+            GetElementPtr { ref dest, ref ty, ref ptr, ref indices } => {
+                try!(write!(f, "{} = getelementptr {}* {}, ", dest, ty, ptr));
+                write_typed_list(f, indices)
+            }
+            ExtractValue { ref dest, ref ty, ref aggregate, index } =>
+                write!(f, "{} = extractvalue {} {}, {}", dest, ty, aggregate, index),
+            Call { ref dest, ref ty, ref func, ref args } => {
+                if let Some(ref dest) = *dest {
+                    try!(write!(f, "{} = ", dest));
+                }
+                try!(write!(f, "call {} {}(", ty, func));
+                try!(write_typed_list(f, args));
+                write!(f, ")")
+            }
+            Phi { ref dest, ref ty, ref incoming } => {
+                try!(write!(f, "{} = phi {} ", dest, ty));
+                for (i, &(ref value, ref label)) in incoming.iter().enumerate() {
+                    try!(write!(f, "{}[{}, ", if i > 0 { ", " } else { "" }, value));
+                    try!(write_name(f, '%', label));
+                    try!(write!(f, "]"));
+                }
+                Ok(())
+            }
+            Br { ref label } => {
+                try!(write!(f, "br label "));
+                write_name(f, '%', label)
+            }
+            CondBr { ref cond, ref on_true, ref on_false } => {
+                try!(write!(f, "br i1 {}, label ", cond));
+                try!(write_name(f, '%', on_true));
+                try!(write!(f, ", label "));
+                write_name(f, '%', on_false)
+            }
+            Ret { ref ty, ref value } => write!(f, "ret {} {}", ty, value),
+        }
+    }
+}
+
+/// A basic block; the first block of a function may be left without a label.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    pub label: Option<String>,
+    pub instructions: Vec<Instruction>,
+}
+
+/// A function definition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub return_type: String,
+    pub params: Vec<(String, Operand)>,
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl Function {
+    /// Create a function with a single, empty block.
+    pub fn new(name: &str, return_type: &str, params: Vec<(String, Operand)>,
+            entry_label: Option<&str>) -> Function {
+        Function {
+            name: name.to_string(),
+            return_type: return_type.to_string(),
+            params: params,
+            blocks: vec![BasicBlock {
+                label: entry_label.map(|l| l.to_string()),
+                instructions: Vec::new(),
+            }],
+        }
+    }
+
+    /// Add an instruction to the end of the last block.
+    pub fn add(&mut self, instruction: Instruction) {
+        self.blocks.last_mut().unwrap().instructions.push(instruction);
+    }
+
+    /// Start a new block with the given label; later instructions are added to it.
+    pub fn start_block(&mut self, label: &str) {
+        self.blocks.push(BasicBlock { label: Some(label.to_string()), instructions: Vec::new() });
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "define {} ", self.return_type));
+        try!(write_name(f, '@', &self.name));
+        try!(write!(f, "("));
+        try!(write_typed_list(f, &self.params));
+        try!(writeln!(f, ") {{"));
+        for block in &self.blocks {
+            if let Some(ref label) = block.label {
+                try!(write_unprefixed_name(f, label));
+                try!(writeln!(f, ":"));
+            }
+            for instruction in &block.instructions {
+                try!(writeln!(f, "  {}", instruction));
+            }
+        }
+        writeln!(f, "}}")
+    }
+}
+
+#[test]
+fn print_instructions() {
+    let load = Instruction::Load {
+        dest: Operand::local("0"),
+        ty: "i32".to_string(),
+        ptr: Operand::local("x.1"),
+        align: None,
+    };
+    assert_eq!(load.to_string(), "%0 = load i32* %x.1");
+    let store = Instruction::Store {
+        ty: "float".to_string(),
+        value: Operand::constant(2.5),
+        ptr: Operand::local("a b\""),
+        align: Some(16),
+    };
+    assert_eq!(store.to_string(), "store float 2.5, float* %\"a b\\22\", align 16");
+
+    let mut func = Function::new("f.raw", "i32", vec![("i32".to_string(), Operand::local("a"))],
+        Some("entry"));
+    func.add(Instruction::CondBr {
+        cond: Operand::constant(1),
+        on_true: "t".to_string(),
+        on_false: "f".to_string(),
+    });
+    func.start_block("t");
+    func.add(Instruction::Br { label: "f".to_string() });
+    func.start_block("f");
+    func.add(Instruction::Phi {
+        dest: Operand::local("1"),
+        ty: "i32".to_string(),
+        incoming: vec![(Operand::local("a"), "entry".to_string()),
+            (Operand::constant(0), "t".to_string())],
+    });
+    func.add(Instruction::Ret { ty: "i32".to_string(), value: Operand::local("1") });
+    assert_eq!(func.to_string(), "define i32 @f.raw(i32 %a) {\nentry:\n  \
+        br i1 1, label %t, label %f\nt:\n  br label %f\nf:\n  \
+        %1 = phi i32 [%a, %entry], [0, %t]\n  ret i32 %1\n}\n");
+
+    // Labels are quoted the same way where they are defined and where they are used, and names
+    // that start with a digit are quoted unless they are plain numbers
+    let mut func = Function::new("1x", "void", vec![], Some("a b"));
+    func.add(Instruction::Br { label: "2c".to_string() });
+    func.start_block("2c");
+    func.add(Instruction::Br { label: "a b".to_string() });
+    func.start_block("12");
+    assert_eq!(func.to_string(), "define void @\"1x\"() {\n\"a b\":\n  br label %\"2c\"\n\
+        \"2c\":\n  br label %\"a b\"\n12:\n}\n");
+}
@@ -1,10 +1,26 @@
 //! Applies macros to an expression or program, yielding a final `PartialExpr`.
 //!
+//! Arguments are moved into their last use in an expansion rather than cloned, so expanding an
+//! invocation copies the macro's body but not its arguments. Otherwise, nested invocations such
+//! as map(map(map(v, f), g), h) would copy each inner invocation once for every invocation
+//! around it.
+//!
+//! Expansions are also memoized within a call to `process_expression`. Before each round of
+//! expansion, the invocations in the expression are keyed by a hash of their macro and argument
+//! expressions. An invocation whose key appears more than once is expanded the first time and
+//! copied after that, with the symbols its expansion defined renamed to fresh IDs. Invocations
+//! that appear once are not kept, so nested ones still copy nothing but the macro bodies.
+//!
 //! Caveats:
 //! - Macros that reuse a parameter twice have its expansion appear twice, instead of assigning
-//!   it to a temporary as would happen with function application.
+//!   it to a temporary as would happen with function application. Arguments that draw random
+//!   numbers are the exception: they are bound with a Let before the call, so that they draw
+//!   once and in order, as a function's arguments would.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::vec::Vec;
 
 use super::ast::*;
@@ -13,6 +29,7 @@ use super::program::*;
 use super::parser::*;
 use super::partial_types::*;
 use super::error::*;
+use super::transforms::builtin_is_random;
 use super::util::SymbolGenerator;
 
 #[cfg(test)] use super::pretty_print::*;
@@ -33,83 +50,443 @@ pub fn process_program(program: &Program) -> WeldResult<PartialExpr> {
     process_expression(&program.body, &all_macros)
 }
 
+/// Estimate how deep a program's expression gets while its macros are expanded and it is
+/// transformed, given the depth of its body, to decide which stack to compile it on. Expanding
+/// a call puts the macro's body in its place, so each level can get as deep as the deepest
+/// macro, and inlining functions does the same with their bodies.
+pub fn expanded_depth(program: &Program, depth: usize) -> usize {
+    let macro_depth = STANDARD_MACROS.iter().chain(&program.macros)
+        .map(|m| m.body.depth())
+        .max()
+        .unwrap_or(0);
+    depth * (1 + macro_depth)
+}
+
 /// Apply a specific list of macros to an expression (does not load the standard macros).
 pub fn process_expression(expr: &PartialExpr, macros: &Vec<Macro>) -> WeldResult<PartialExpr> {
+    expand(expr, macros).map(|(expr, _)| expr)
+}
+
+/// Counts of the work done to expand the macros in an expression.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ExpansionStats {
+    /// Invocations expanded by substituting their arguments into the macro's body.
+    expanded: usize,
+    /// Invocations replaced with a copy of an earlier expansion.
+    copied: usize,
+    /// Expression nodes cloned, for macro bodies, arguments used more than once and the cache.
+    cloned_nodes: usize,
+}
+
+/// An expansion of an invocation that appears more than once, kept so that the later ones can
+/// copy it.
+struct Expansion {
+    args: Vec<PartialExpr>,
+    result: PartialExpr,
+    /// Symbols given new IDs while expanding the invocation, in the order they were created.
+    defined: Vec<Symbol>,
+}
+
+/// The state of expanding the macros in one expression.
+struct Expander<'a> {
+    macros: HashMap<Symbol, &'a Macro>,
+    random_macros: HashSet<Symbol>,
+    sym_gen: SymbolGenerator,
+    /// How many times each invocation key appears in the expression in the current round.
+    counts: HashMap<u64, usize>,
+    /// The macros with an invocation that appears more than once in the current round, so that
+    /// invocations of other macros need not be hashed.
+    repeated: HashSet<Symbol>,
+    cache: HashMap<u64, Vec<Expansion>>,
+    /// Symbols given new IDs so far, in the order they were created.
+    defined: Vec<Symbol>,
+    stats: ExpansionStats,
+}
+
+/// Apply macros to an expression like `process_expression`, also counting the work done.
+fn expand(expr: &PartialExpr, macros: &Vec<Macro>) -> WeldResult<(PartialExpr, ExpansionStats)> {
     let mut macro_map: HashMap<Symbol, &Macro> = HashMap::new();
     for m in macros {
         if macro_map.contains_key(&m.name) {
-            return weld_err!("Duplicate macro: {}", m.name);
+            return weld_err!(Macro, "Duplicate macro: {}", m.name);
         } else {
             macro_map.insert(m.name.clone(), &m);
         }
     }
 
-    let mut sym_gen = SymbolGenerator::from_expression(&expr);
+    let mut expander = Expander {
+        random_macros: find_random_macros(&macro_map),
+        macros: macro_map,
+        sym_gen: SymbolGenerator::from_expression(&expr),
+        counts: HashMap::new(),
+        repeated: HashSet::new(),
+        cache: HashMap::new(),
+        defined: Vec::new(),
+        stats: ExpansionStats::default(),
+    };
 
     let mut expr = expr.clone();
+    expander.bind_random_args(&mut expr);
     for _ in 1..MAX_MACRO_DEPTH {
-        if !try!(apply_macros(&mut expr, &macro_map, &mut sym_gen)) {
-            return Ok(expr)
+        expander.count_invocations(&expr);
+        if !try!(expander.apply_macros(&mut expr)) {
+            return Ok((expr, expander.stats))
         }
     }
 
-    weld_err!("Marco expansion recursed past {} levels", MAX_MACRO_DEPTH)
+    weld_err!(Macro, "Macro expansion recursed past {} levels", MAX_MACRO_DEPTH)
 }
 
-fn apply_macros(
-    expr: &mut PartialExpr,
-    macros: &HashMap<Symbol, &Macro>,
-    sym_gen: &mut SymbolGenerator
-) -> WeldResult<bool> {
-    let mut new_expr = None;
-    if let Apply(ref func, ref params) = expr.kind {
-        if let Ident(ref name) = func.kind {
-            if let Some(mac) = macros.get(name) {
-                let mut new_body = mac.body.clone();
-                if params.len() != mac.parameters.len() {
-                    return weld_err!("Wrong number of parameters for macro {}", mac.name);
-                }
-                update_defined_ids(&mut new_body, sym_gen);
-                for (name, value) in mac.parameters.iter().zip(params) {
-                    new_body.substitute(name, value);
+impl<'a> Expander<'a> {
+    /// Count the invocations in an expression by key, before a round of expansion.
+    fn count_invocations(&mut self, expr: &PartialExpr) {
+        let mut counts = HashMap::new();
+        let mut repeated = HashSet::new();
+        hash_expr(expr, &self.macros, &mut |name, key| {
+            let count = counts.entry(key).or_insert(0);
+            *count += 1;
+            if *count == 2 {
+                repeated.insert(name.clone());
+            }
+        });
+        self.counts = counts;
+        self.repeated = repeated;
+    }
+
+    fn apply_macros(&mut self, expr: &mut PartialExpr) -> WeldResult<bool> {
+        let mac = match called_macro(expr, &self.macros) {
+            Some(mac) => mac,
+            None => {
+                let mut changed = false;
+                for c in expr.children_mut() {
+                    changed |= try!(self.apply_macros(c));
                 }
-                new_expr = Some(new_body);
+                return Ok(changed)
             }
+        };
+        let args = match expr.kind {
+            Apply(_, ref mut args) => mem::replace(args, Vec::new()),
+            _ => unreachable!()
+        };
+        if args.len() != mac.parameters.len() {
+            return weld_err!(Macro, "Wrong number of parameters for macro {}", mac.name);
         }
+
+        let mut key = None;
+        if self.repeated.contains(&mac.name) {
+            let hashes: Vec<u64> = args.iter()
+                .map(|arg| hash_expr(arg, &self.macros, &mut |_, _| ()))
+                .collect();
+            key = Some(invocation_key(&mac.name, &hashes));
+        }
+        if let Some(key) = key {
+            if let Some(copy) = self.copy_expansion(key, &args) {
+                *expr = copy;
+                return Ok(true)
+            }
+        }
+        // Keep the arguments of invocations that appear again, to check that later ones match
+        let saved_args = match key {
+            Some(key) if self.counts.get(&key).map_or(false, |&count| count > 1) => {
+                self.stats.cloned_nodes += args.iter().map(count_nodes).sum::<usize>();
+                Some(args.clone())
+            }
+            _ => None
+        };
+
+        let first_defined = self.defined.len();
+        let mut new_body = mac.body.clone();
+        self.stats.cloned_nodes += count_nodes(&mac.body);
+        self.update_defined_ids(&mut new_body);
+        // The arguments have been bound already, so only the body's own calls need it
+        self.bind_random_args(&mut new_body);
+        self.stats.cloned_nodes += substitute_args(&mut new_body, &mac.parameters, args);
+        self.stats.expanded += 1;
+        *expr = new_body;
+        for c in expr.children_mut() {
+            try!(self.apply_macros(c));
+        }
+
+        if let (Some(key), Some(args)) = (key, saved_args) {
+            self.stats.cloned_nodes += count_nodes(expr);
+            let expansion = Expansion {
+                args: args,
+                result: expr.clone(),
+                defined: self.defined[first_defined..].to_vec(),
+            };
+            self.cache.entry(key).or_insert(Vec::new()).push(expansion);
+        }
+        Ok(true)
     }
-    let mut changed = false;
-    if let Some(new_expr) = new_expr {
-        *expr = new_expr;
-        changed = true;
-    }
-    for c in expr.children_mut() {
-        changed |= try!(apply_macros(c, macros, sym_gen));
+
+    /// Copy an earlier expansion of an invocation with the given key and arguments, if there is
+    /// one, giving the symbols that it defined fresh IDs.
+    fn copy_expansion(&mut self, key: u64, args: &[PartialExpr]) -> Option<PartialExpr> {
+        let expansion = match self.cache.get(&key)
+            .and_then(|expansions| expansions.iter().find(|e| e.args[..] == *args)) {
+            Some(expansion) => expansion,
+            None => return None
+        };
+        let mut renames = HashMap::new();
+        for sym in &expansion.defined {
+            let new_sym = self.sym_gen.new_symbol(sym.name);
+            self.defined.push(new_sym.clone());
+            renames.insert(sym.clone(), new_sym);
+        }
+        let mut copy = expansion.result.clone();
+        rename_symbols(&mut copy, &renames);
+        self.stats.copied += 1;
+        self.stats.cloned_nodes += count_nodes(&copy);
+        Some(copy)
     }
-    Ok(changed)
-}
 
-fn update_defined_ids(expr: &mut PartialExpr, sym_gen: &mut SymbolGenerator) {
-    if let Let(ref mut sym, ref value, ref mut body) = expr.kind {
-        if sym.id == 0 {
-            let new_sym = sym_gen.new_symbol(&sym.name);
-            let new_ident = PartialExpr { kind: Ident(new_sym.clone()), ty: value.ty.clone() };
-            body.substitute(sym, &new_ident);
-            sym.id = new_sym.id;
+    /// Bind the arguments of macro calls that draw random numbers to fresh symbols with a Let
+    /// around the call, in argument order, so that expanding the call neither repeats nor drops
+    /// their draws. Lambdas are left in place, since they only draw when called. Returns whether
+    /// the expression draws random numbers, which is worked out on the way up so that nested
+    /// calls stay linear.
+    fn bind_random_args(&mut self, expr: &mut PartialExpr) -> bool {
+        let mut random = Vec::new();
+        for c in expr.children_mut() {
+            random.push(self.bind_random_args(c));
         }
+        let mac = called_macro(expr, &self.macros);
+        let expr_random = random.contains(&true) || builtin_is_random(expr) ||
+            mac.map_or(false, |m| self.random_macros.contains(&m.name));
+
+        if let Some(mac) = mac {
+            let mut bindings = Vec::new();
+            if let Apply(_, ref mut args) = expr.kind {
+                // The function comes first among the children, followed by the arguments
+                for (i, arg) in args.iter_mut().enumerate() {
+                    let is_lambda = match arg.kind {
+                        Lambda(_, _) => true,
+                        _ => false
+                    };
+                    if random[i + 1] && !is_lambda && i < mac.parameters.len() {
+                        // ID 0 could be a parameter of the macro whose body is being expanded
+                        let mut sym = self.sym_gen.new_symbol(mac.parameters[i].name);
+                        if sym.id == 0 {
+                            sym = self.sym_gen.new_symbol(sym.name);
+                        }
+                        self.defined.push(sym.clone());
+                        let ident = PartialExpr { kind: Ident(sym.clone()), ty: arg.ty.clone() };
+                        bindings.push((sym, mem::replace(arg, ident)));
+                    }
+                }
+            }
+            for (sym, value) in bindings.into_iter().rev() {
+                let body = PartialExpr {
+                    kind: mem::replace(&mut expr.kind, BoolLiteral(false)),
+                    ty: expr.ty.clone()
+                };
+                expr.kind = Let(sym, Box::new(value), Box::new(body));
+            }
+        }
+        expr_random
     }
-    if let Lambda(ref mut params, ref mut body) = expr.kind {
-        for ref mut param in params {
-            let sym = &mut param.name;
+
+    fn update_defined_ids(&mut self, expr: &mut PartialExpr) {
+        if let Let(ref mut sym, ref value, ref mut body) = expr.kind {
             if sym.id == 0 {
-                let new_sym = sym_gen.new_symbol(&sym.name);
-                let new_ident = PartialExpr { kind: Ident(new_sym.clone()), ty: param.ty.clone() };
+                let new_sym = self.sym_gen.new_symbol(sym.name);
+                let new_ident = PartialExpr { kind: Ident(new_sym.clone()), ty: value.ty.clone() };
                 body.substitute(sym, &new_ident);
                 sym.id = new_sym.id;
+                self.defined.push(new_sym);
+            }
+        }
+        if let Lambda(ref mut params, ref mut body) = expr.kind {
+            for ref mut param in params {
+                let sym = &mut param.name;
+                if sym.id == 0 {
+                    let new_sym = self.sym_gen.new_symbol(sym.name);
+                    let new_ident = PartialExpr {
+                        kind: Ident(new_sym.clone()),
+                        ty: param.ty.clone()
+                    };
+                    body.substitute(sym, &new_ident);
+                    sym.id = new_sym.id;
+                    self.defined.push(new_sym);
+                }
             }
         }
+        for c in expr.children_mut() {
+            self.update_defined_ids(c);
+        }
+    }
+}
+
+/// The macro that an expression invokes, if it is a call to one.
+fn called_macro<'a>(
+    expr: &PartialExpr,
+    macros: &HashMap<Symbol, &'a Macro>
+) -> Option<&'a Macro> {
+    match expr.kind {
+        Apply(ref func, _) => match func.kind {
+            Ident(ref name) => macros.get(name).cloned(),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+/// Hash the structure of an expression, calling `found` with the macro and key of each macro
+/// invocation in it. Each node's hash is made from its children's, so every node is read once.
+fn hash_expr<F>(expr: &PartialExpr, macros: &HashMap<Symbol, &Macro>, found: &mut F) -> u64
+    where F: FnMut(&Symbol, u64)
+{
+    let mut hasher = DefaultHasher::new();
+    mem::discriminant(&expr.kind).hash(&mut hasher);
+    expr.ty.hash(&mut hasher);
+    match expr.kind {
+        BoolLiteral(value) => value.hash(&mut hasher),
+        I32Literal(value) => value.hash(&mut hasher),
+        I64Literal(value) => value.hash(&mut hasher),
+        F32Literal(value) => value.to_bits().hash(&mut hasher),
+        F64Literal(value) => value.to_bits().hash(&mut hasher),
+        BinOp(kind, _, _) => kind.hash(&mut hasher),
+        Ident(ref sym) | Let(ref sym, _, _) => sym.hash(&mut hasher),
+        GetField(_, index) => index.hash(&mut hasher),
+        Lambda(ref params, _) => {
+            for param in params {
+                param.name.hash(&mut hasher);
+                param.ty.hash(&mut hasher);
+            }
+        }
+        Builtin(kind, _) => kind.hash(&mut hasher),
+        _ => ()
+    }
+    let children: Vec<u64> = expr.children().map(|c| hash_expr(c, macros, found)).collect();
+    children.hash(&mut hasher);
+    if let Some(mac) = called_macro(expr, macros) {
+        // The function comes first among the children, followed by the arguments
+        found(&mac.name, invocation_key(&mac.name, &children[1..]));
+    }
+    hasher.finish()
+}
+
+/// The key of an invocation of the named macro, given the hashes of its arguments.
+fn invocation_key(name: &Symbol, arg_hashes: &[u64]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    arg_hashes.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn count_nodes(expr: &PartialExpr) -> usize {
+    let mut count = 0;
+    expr.traverse(&mut |_| count += 1);
+    count
+}
+
+/// Rename every occurrence of the given symbols, both where they are defined and where they are
+/// used. The symbols must not be defined or used outside the expression.
+fn rename_symbols(expr: &mut PartialExpr, renames: &HashMap<Symbol, Symbol>) {
+    match expr.kind {
+        Ident(ref mut sym) | Let(ref mut sym, _, _) => {
+            if let Some(new_sym) = renames.get(sym) {
+                *sym = new_sym.clone();
+            }
+        }
+        Lambda(ref mut params, _) => {
+            for param in params {
+                if let Some(new_sym) = renames.get(&param.name) {
+                    param.name = new_sym.clone();
+                }
+            }
+        }
+        _ => ()
     }
     for c in expr.children_mut() {
-        update_defined_ids(c, sym_gen);
+        rename_symbols(c, renames);
+    }
+}
+
+/// Find the macros whose expansions draw random numbers, either directly or by invoking other
+/// such macros.
+fn find_random_macros(macros: &HashMap<Symbol, &Macro>) -> HashSet<Symbol> {
+    let mut random_macros = HashSet::new();
+    loop {
+        let mut changed = false;
+        for (name, mac) in macros {
+            if !random_macros.contains(name) {
+                let mut random = false;
+                mac.body.traverse(&mut |e| {
+                    random |= builtin_is_random(e) || called_macro(e, macros)
+                        .map_or(false, |m| random_macros.contains(&m.name));
+                });
+                if random {
+                    random_macros.insert(name.clone());
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            return random_macros;
+        }
+    }
+}
+
+/// Substitute macro arguments for the uses of their parameters in an expansion, like
+/// `Expr::substitute`, but moving each argument into its last use instead of cloning it. The
+/// uses are all found before anything is substituted, so the arguments are never traversed.
+/// Returns the number of nodes cloned.
+fn substitute_args(body: &mut PartialExpr, params: &[Symbol], args: Vec<PartialExpr>) -> usize {
+    let mut uses = Vec::new();
+    find_uses(body, params, &mut Vec::new(), &mut uses);
+    let mut remaining = vec![0; params.len()];
+    for &(i, _) in &uses {
+        remaining[i] += 1;
+    }
+    let mut cloned_nodes = 0;
+    let mut args: Vec<Option<PartialExpr>> = args.into_iter().map(Some).collect();
+    for (i, slot) in uses {
+        remaining[i] -= 1;
+        *slot = if remaining[i] == 0 {
+            args[i].take().unwrap()
+        } else {
+            cloned_nodes += args[i].as_ref().map_or(0, count_nodes);
+            args[i].clone().unwrap()
+        };
+    }
+    cloned_nodes
+}
+
+/// Collect the Ident nodes that refer to one of `params`, along with the parameter's index,
+/// skipping uses under expressions that redefine the parameter.
+fn find_uses<'a>(
+    expr: &'a mut PartialExpr,
+    params: &[Symbol],
+    shadowed: &mut Vec<Symbol>,
+    uses: &mut Vec<(usize, &'a mut PartialExpr)>
+) {
+    let index = match expr.kind {
+        Ident(ref sym) if !shadowed.contains(sym) => params.iter().position(|p| p == sym),
+        _ => None
+    };
+    if let Some(i) = index {
+        uses.push((i, expr));
+        return;
+    }
+    match expr.kind {
+        Let(ref name, ref mut value, ref mut body) => {
+            find_uses(value, params, shadowed, uses);
+            shadowed.push(name.clone());
+            find_uses(body, params, shadowed, uses);
+            shadowed.pop();
+        }
+        Lambda(ref lambda_params, ref mut body) => {
+            let len = shadowed.len();
+            shadowed.extend(lambda_params.iter().map(|p| p.name.clone()));
+            find_uses(body, params, shadowed, uses);
+            shadowed.truncate(len);
+        }
+        _ => {
+            for c in expr.children_mut() {
+                find_uses(c, params, shadowed, uses);
+            }
+        }
     }
 }
 
@@ -178,6 +555,28 @@ fn macros_introducing_symbols() {
     assert_eq!(print_expr(&result).as_str(), "|x#1|(x#1+(let x#2=(x);(x#2+x#2)))");
 }
 
+#[test]
+fn repeated_macro_invocations() {
+    // Each invocation must get its own IDs for the symbols it defines
+    let macros = parse_macros("macro adder(a) = |x| x+a;").unwrap();
+    let expr = parse_expr("adder(x)(1) + adder(x)(2) + adder(y)(3)").unwrap();
+    let result = process_expression(&expr, &macros).unwrap();
+    assert_eq!(print_expr(&result).as_str(),
+        "(((|x#1|(x#1+x))(1)+(|x#2|(x#2+x))(2))+(|x#3|(x#3+y))(3))");
+
+    let macros = parse_macros("macro twice(a) = (let x = a; x+x);").unwrap();
+    let expr = parse_expr("{twice(x), twice(x)}").unwrap();
+    let result = process_expression(&expr, &macros).unwrap();
+    assert_eq!(print_expr(&result).as_str(), "{(let x#1=(x);(x#1+x#1)),(let x#2=(x);(x#2+x#2))}");
+
+    // Invocations whose arguments were themselves expanded from identical invocations
+    let macros = parse_macros("macro adder(a)=|x|x+a; macro twice(a)=(let x=a; x+x);").unwrap();
+    let expr = parse_expr("{adder(twice(x)), adder(twice(x))}").unwrap();
+    let result = process_expression(&expr, &macros).unwrap();
+    assert_eq!(print_expr(&result).as_str(),
+        "{|x#1|(x#1+(let x#2=(x);(x#2+x#2))),|x#3|(x#3+(let x#4=(x);(x#4+x#4)))}");
+}
+
 #[test]
 fn standard_macros() {
     // Check that the standard macros file is loaded
@@ -185,4 +584,84 @@ fn standard_macros() {
     let result = process_program(&program).unwrap();
     assert_eq!(print_expr(&result).as_str(),
         "result(for([1,2,3],appender[?],|b,x|merge(b,(|a|(a+1))(x))))");
-}
\ No newline at end of file
+}
+
+#[test]
+fn arguments_using_parameter_names() {
+    // Arguments are substituted together, so a later parameter is not replaced inside them
+    let macros = parse_macros("macro foo(a, b) = a + b;").unwrap();
+    let expr = parse_expr("foo(b, 1)").unwrap();
+    let result = process_expression(&expr, &macros).unwrap();
+    assert_eq!(print_expr(&result).as_str(), "(b+1)");
+}
+
+#[test]
+fn random_arguments() {
+    // Arguments that draw random numbers are bound first, so each draw happens once
+    let macros = parse_macros("macro twice(a) = a + a;").unwrap();
+    let expr = parse_expr("twice(rand())").unwrap();
+    let result = process_expression(&expr, &macros).unwrap();
+    assert_eq!(print_expr(&result).as_str(), "(let a#1=(rand());(a#1+a#1))");
+
+    // Bindings keep the order of the arguments, and pure arguments stay in place
+    let macros = parse_macros("macro swap(a, b, c) = {c, b, a};").unwrap();
+    let expr = parse_expr("swap(rand(), x, rand_seeded(1L))").unwrap();
+    let result = process_expression(&expr, &macros).unwrap();
+    assert_eq!(print_expr(&result).as_str(),
+        "(let a#1=(rand());(let c#1=(rand_seeded(1L));{c#1,x,a#1}))");
+
+    // Bindings made inside a macro's body do not capture that macro's parameters
+    let macros = parse_macros("macro swap(a, b, c) = {c, b, a}; macro m(a) = swap(rand(), a, 1);")
+        .unwrap();
+    let expr = parse_expr("m(x)").unwrap();
+    let result = process_expression(&expr, &macros).unwrap();
+    assert_eq!(print_expr(&result).as_str(), "(let a#1=(rand());{1,x,a#1})");
+
+    // Calls to macros that draw random numbers are bound too, including inside macro bodies
+    let macros = parse_macros("macro twice(a) = a + a; macro noise(a) = a * rand(); \
+        macro double_noise(a) = twice(noise(a));").unwrap();
+    let expr = parse_expr("double_noise(x)").unwrap();
+    let result = process_expression(&expr, &macros).unwrap();
+    assert_eq!(print_expr(&result).as_str(), "(let a#1=((x*rand()));(a#1+a#1))");
+
+    // Lambdas only draw when they are called, so they are substituted as usual
+    let macros = parse_macros("macro twice(a) = a + a;").unwrap();
+    let expr = parse_expr("twice(|x| x * rand())").unwrap();
+    let result = process_expression(&expr, &macros).unwrap();
+    assert_eq!(print_expr(&result).as_str(), "(|x|(x*rand())+|x|(x*rand()))");
+}
+
+#[test]
+fn repeated_invocations_are_copied() {
+    // The second adder(twice(x)) copies the first, including its expansion of twice(x)
+    let macros = parse_macros("macro adder(a)=|x|x+a; macro twice(a)=(let x=a; x+x);").unwrap();
+    let expr = parse_expr("{adder(twice(x)), adder(twice(x)), adder(twice(y))}").unwrap();
+    let (result, stats) = expand(&expr, &macros).unwrap();
+    assert_eq!(print_expr(&result).as_str(), "{|x#1|(x#1+(let x#2=(x);(x#2+x#2))),\
+        |x#3|(x#3+(let x#4=(x);(x#4+x#4))),|x#5|(x#5+(let x#6=(y);(x#6+x#6)))}");
+    assert_eq!((stats.expanded, stats.copied), (4, 1));
+
+    // Invocations that only look alike are expanded separately
+    let expr = parse_expr("{twice(1), twice(1L), twice(1.0), twice(1.0f), twice(x), twice(y)}")
+        .unwrap();
+    let (_, stats) = expand(&expr, &macros).unwrap();
+    assert_eq!((stats.expanded, stats.copied), (6, 0));
+}
+
+#[test]
+fn nested_invocations_scale_linearly() {
+    use super::util::with_compile_stack;
+
+    // Copying arguments into each expansion made the nodes cloned grow with the square of n
+    let cloned_nodes = |n: usize| -> usize {
+        let mut code = "v".to_string();
+        for i in 0..n {
+            code = format!("map({}, |x| x + {})", code, i);
+        }
+        let expr = parse_expr(&code).unwrap();
+        let (_, stats) = with_compile_stack(|| expand(&expr, &STANDARD_MACROS)).unwrap();
+        assert_eq!((stats.expanded, stats.copied), (n, 0));
+        stats.cloned_nodes
+    };
+    assert_eq!(cloned_nodes(1000), 4 * cloned_nodes(250));
+}
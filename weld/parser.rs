@@ -5,92 +5,133 @@
 
 use std::vec::Vec;
 
-use super::ast::Symbol;
+use super::ast::{BuiltinKind, ScalarKind, Symbol, MAX_DECIMAL_PRECISION};
 use super::ast::BinOpKind::*;
 use super::ast::ExprKind::*;
 use super::ast::ScalarKind::*;
 use super::error::*;
+use super::intern::Name;
 use super::partial_types::*;
 use super::partial_types::PartialBuilderKind::*;
 use super::partial_types::PartialType::*;
 use super::program::*;
 use super::tokenizer::*;
 use super::tokenizer::Token::*;
+use super::util::{with_compile_stack, CALLER_STACK_DEPTH};
 
 #[cfg(test)] use super::pretty_print::*;
 
+/// The deepest that expressions may be nested inside each other in the source (e.g. with
+/// parentheses or function arguments), to bound the parser's recursion. Chains of lets and of
+/// binary operators are parsed iteratively and do not count towards this.
+pub const MAX_NESTING_DEPTH: usize = 2000;
+
 /// Parse the complete input string as a Weld program (optional macros plus one expression).
 pub fn parse_program(input: &str) -> WeldResult<Program> {
-    let tokens = try!(tokenize(input));
-    let mut parser = Parser::new(&tokens);
-    let res = parser.program();
-    if res.is_ok() && !parser.is_done() {
-        return weld_err!("Unexpected token: {}", parser.peek())
-    }
-    res
+    parse_input(input, |p| p.program())
 }
 
 /// Parse the complete input string as a list of macros.
 pub fn parse_macros(input: &str) -> WeldResult<Vec<Macro>> {
-    let tokens = try!(tokenize(input));
-    let mut parser = Parser::new(&tokens);
-    let res = parser.macros();
-    if res.is_ok() && !parser.is_done() {
-        return weld_err!("Unexpected token: {}", parser.peek())
-    }
-    res
+    parse_input(input, |p| p.macros())
 }
 
 /// Parse the complete input string as an expression.
 pub fn parse_expr(input: &str) -> WeldResult<PartialExpr> {
-    let tokens = try!(tokenize(input));
-    let mut parser = Parser::new(&tokens);
-    let res = parser.expr().map(|b| *b);
-    if res.is_ok() && !parser.is_done() {
-        return weld_err!("Unexpected token: {}", parser.peek())
-    }
-    res
+    parse_input(input, |p| p.expr().map(|b| *b))
 }
 
 /// Parse the complete input string as a PartialType.
 pub fn parse_type(input: &str) -> WeldResult<PartialType> {
-    let tokens = try!(tokenize(input));
-    let mut parser = Parser::new(&tokens);
-    let res = parser.type_();
-    if res.is_ok() && !parser.is_done() {
-        return weld_err!("Unexpected token: {}", parser.peek())
-    }
-    res
+    parse_input(input, |p| p.type_())
+}
+
+/// Tokenize the input and run `func` on a parser over it, checking that all the input is used.
+/// Errors are tagged as parse errors and their messages give the line and column they occurred.
+///
+/// The parser recurses once per level of nesting. Input is parsed on the caller's stack up to
+/// `CALLER_STACK_DEPTH` levels deep, and parsed again on a thread with a large stack if it turns
+/// out to be nested more deeply than that.
+fn parse_input<T, F>(input: &str, func: F) -> WeldResult<T>
+        where T: Send, F: Fn(&mut Parser) -> WeldResult<T> + Sync {
+    let parse = |tokens: &[(Token, Span)], max_depth| {
+        let mut parser = Parser::new(tokens, max_depth);
+        let mut res = func(&mut parser);
+        if res.is_ok() && !parser.is_done() {
+            let position = parser.position;
+            res = parser.error(position, format!("Unexpected token: {}", parser.peek()));
+        }
+        (res, parser.too_deep)
+    };
+    let result = tokenize_with_spans(input).and_then(|tokens| {
+        match parse(&tokens, CALLER_STACK_DEPTH) {
+            (_, true) => with_compile_stack(|| parse(&tokens, MAX_NESTING_DEPTH).0),
+            (res, false) => res
+        }
+    });
+    result.map_err(|e| e.with_kind(ErrorKind::Parse).with_source_location(input))
 }
 
 /// A stateful object that parses a sequence of tokens, tracking its position at each point.
 /// Assumes that the tokens end with a TEndOfInput.
 struct Parser<'t> {
-    tokens: &'t [Token],
-    position: usize
+    tokens: &'t [(Token, Span)],
+    position: usize,
+    /// How many calls to `expr` we are currently inside.
+    depth: usize,
+    /// The most calls to `expr` that may be nested; at most `MAX_NESTING_DEPTH`.
+    max_depth: usize,
+    /// Whether parsing stopped at `max_depth` while that was less than `MAX_NESTING_DEPTH`.
+    too_deep: bool,
+    /// Names bound by the enclosing lets, lambdas and macros, and the macros defined so far. A
+    /// call to one of these is an application even if a builtin has the same name.
+    bound: Vec<Name>
 }
 
 impl<'t> Parser<'t> {
-    fn new(tokens: &[Token]) -> Parser {
-        Parser { tokens: tokens, position: 0 }
+    fn new(tokens: &[(Token, Span)], max_depth: usize) -> Parser {
+        Parser {
+            tokens: tokens,
+            position: 0,
+            depth: 0,
+            max_depth: max_depth,
+            too_deep: false,
+            bound: Vec::new()
+        }
     }
 
     /// Look at the next token to be parsed.
     fn peek(&self) -> &'t Token {
-        &self.tokens[self.position]
+        &self.tokens[self.position].0
     }
 
     /// Consume and return the next token.
     fn next(&mut self) -> &'t Token {
-        let token = &self.tokens[self.position];
+        let token = &self.tokens[self.position].0;
         self.position += 1;
         token
     }
 
+    /// Return an error located at the token at `position`.
+    fn error<T>(&self, position: usize, message: String) -> WeldResult<T> {
+        let span = self.tokens[position].1;
+        Err(WeldError::new(message).with_span(span))
+    }
+
+    /// Return an error located at the next token to be parsed.
+    fn error_at_next<T>(&self, message: String) -> WeldResult<T> {
+        self.error(self.position, message)
+    }
+
+    /// Return an error located at the token that was just consumed.
+    fn error_at_last<T>(&self, message: String) -> WeldResult<T> {
+        self.error(self.position - 1, message)
+    }
+
     /// Consume the next token and check that it equals `expected`. If not, return an Err.
     fn consume(&mut self, expected: Token) -> WeldResult<()> {
         if *self.next() != expected {
-            weld_err!("Expected '{}'", expected)
+            self.error_at_last(format!("Expected '{}'", expected))
         } else {
             Ok(())
         }
@@ -112,7 +153,9 @@ impl<'t> Parser<'t> {
     fn macros(&mut self) -> WeldResult<Vec<Macro>> {
         let mut res: Vec<Macro> = Vec::new();
         while *self.peek() == TMacro {
-            res.push(try!(self.macro_()));
+            let macro_ = try!(self.macro_());
+            self.bound.push(macro_.name.name);
+            res.push(macro_);
         }
         Ok(res)
     }
@@ -128,38 +171,59 @@ impl<'t> Parser<'t> {
             if *self.peek() == TComma {
                 self.next();
             } else if *self.peek() != TCloseParen {
-                return weld_err!("Expected ',' or ')'");
+                return self.error_at_next("Expected ',' or ')'".to_string());
             }
         }
         try!(self.consume(TCloseParen));
         try!(self.consume(TEqual));
+        let scope = self.bound.len();
+        self.bound.extend(params.iter().map(|p| p.name));
         let body = try!(self.expr());
+        self.bound.truncate(scope);
         try!(self.consume(TSemicolon));
         Ok(Macro { name: name, parameters: params, body: *body })
     }
 
     /// Parse an expression starting at the current position.
     fn expr(&mut self) -> WeldResult<Box<PartialExpr>> {
-        if *self.peek() == TLet {
+        if self.depth > self.max_depth {
+            self.too_deep = self.max_depth < MAX_NESTING_DEPTH;
+            return self.error_at_next(
+                format!("Expression is nested more than {} levels deep", MAX_NESTING_DEPTH))
+        }
+        self.depth += 1;
+        let res = if *self.peek() == TLet {
             self.let_expr()
         } else if *self.peek() == TBar || *self.peek() == TLogicalOr {
             self.lambda_expr()
         } else {
             self.operator_expr()
-        }
+        };
+        self.depth -= 1;
+        res
     }
 
-    /// Parse 'let name = value; body' starting at the current position.
+    /// Parse 'let name = value; body' starting at the current position. A chain of lets is
+    /// parsed in one loop rather than recursively, since generated programs can have thousands.
     fn let_expr(&mut self) -> WeldResult<Box<PartialExpr>> {
-        try!(self.consume(TLet));
-        let name = try!(self.symbol());
-        let ty = try!(self.optional_type());
-        try!(self.consume(TEqual));
-        let value = try!(self.operator_expr());
-        try!(self.consume(TSemicolon));
-        let body = try!(self.expr());
-        let mut expr = expr_box(Let(name, value, body));
-        expr.ty = ty;
+        let mut bindings = Vec::new();
+        let scope = self.bound.len();
+        while *self.peek() == TLet {
+            try!(self.consume(TLet));
+            let name = try!(self.symbol());
+            let ty = try!(self.optional_type());
+            try!(self.consume(TEqual));
+            let value = try!(self.operator_expr());
+            try!(self.consume(TSemicolon));
+            self.bound.push(name.name);
+            bindings.push((name, ty, value));
+        }
+        let mut expr = try!(self.expr());
+        self.bound.truncate(scope);
+        for (name, ty, value) in bindings.into_iter().rev() {
+            expr = expr_box(Let(name, value, expr));
+            expr.ty = ty;
+        }
         Ok(expr)
     }
 
@@ -176,14 +240,17 @@ impl<'t> Parser<'t> {
                 if *self.peek() == TComma {
                     self.next();
                 } else if *self.peek() != TBar {
-                    return weld_err!("Expected ',' or '|'")
+                    return self.error_at_next("Expected ',' or '|'".to_string())
                 }
             }
             try!(self.consume(TBar));
         } else if *token != TLogicalOr {
-            return weld_err!("Expected '|' or '||'")
+            return self.error_at_last("Expected '|' or '||'".to_string())
         }
+        let scope = self.bound.len();
+        self.bound.extend(params.iter().map(|p| p.name.name));
         let body = try!(self.expr());
+        self.bound.truncate(scope);
         Ok(expr_box(Lambda(params, body)))
     }
 
@@ -330,12 +397,14 @@ impl<'t> Parser<'t> {
                         if value.starts_with("$") {
                             match u32::from_str_radix(&value[1..], 10) {
                                 Ok(index) => expr = expr_box(GetField(expr, index)),
-                                _ => return weld_err!("Expected field index but got '{}'", value)
+                                _ => return self.error_at_last(
+                                    format!("Expected field index but got '{}'", value))
                             }
                         }
                     }
 
-                    ref other => return weld_err!("Expected field index but got '{}'", other)
+                    ref other => return self.error_at_last(
+                        format!("Expected field index but got '{}'", other))
                 }
             } else {  // TOpenParen
                 let mut params: Vec<PartialExpr> = Vec::new();
@@ -345,7 +414,7 @@ impl<'t> Parser<'t> {
                     if *self.peek() == TComma {
                         self.next();
                     } else if *self.peek() != TCloseParen {
-                        return weld_err!("Expected ',' or ')'")
+                        return self.error_at_next("Expected ',' or ')'".to_string())
                     }
                 }
                 try!(self.consume(TCloseParen));
@@ -363,12 +432,74 @@ impl<'t> Parser<'t> {
             TF32Literal(value) => Ok(expr_box(F32Literal(value))),
             TF64Literal(value) => Ok(expr_box(F64Literal(value))),
             TBoolLiteral(value) => Ok(expr_box(BoolLiteral(value))),
-            TIdent(ref name) => Ok(expr_box(Ident(Symbol{name: name.clone(), id: 0}))),
+            // Names of builtins and of the newer types are not reserved, so they only call a
+            // builtin, cast or build when no enclosing let, lambda or macro binds them
+            TIdent(name) if self.bound.contains(&name) => {
+                Ok(expr_box(Ident(Symbol { name: name, id: 0 })))
+            }
+            TIdent(name) if name == "mutvec" => {
+                let mut elem_type = Unknown;
+                if *self.peek() == TOpenBracket {
+                    try!(self.consume(TOpenBracket));
+                    elem_type = try!(self.type_());
+                    try!(self.consume(TCloseBracket));
+                }
+                try!(self.consume(TOpenParen));
+                let data = try!(self.expr());
+                try!(self.consume(TCloseParen));
+                let mut expr = expr_box(NewBuilder(Some(data)));
+                expr.ty = Builder(MutVec(Box::new(elem_type)));
+                Ok(expr)
+            }
+            TIdent(name) if name == "statsmerger" => {
+                let mut elem_type = Unknown;
+                if *self.peek() == TOpenBracket {
+                    try!(self.consume(TOpenBracket));
+                    elem_type = try!(self.type_());
+                    try!(self.consume(TCloseBracket));
+                }
+                let mut expr = expr_box(NewBuilder(None));
+                expr.ty = Builder(StatsMerger(Box::new(elem_type)));
+                Ok(expr)
+            }
+            TIdent(name) if name == "histogram" => {
+                let mut elem_type = Unknown;
+                if *self.peek() == TOpenBracket {
+                    try!(self.consume(TOpenBracket));
+                    elem_type = try!(self.type_());
+                    try!(self.consume(TCloseBracket));
+                }
+                let args = try!(self.builder_args());
+                let mut expr = expr_box(NewBuilder(Some(args)));
+                expr.ty = Builder(Histogram(Box::new(elem_type)));
+                Ok(expr)
+            }
+            TIdent(name) if name == "decimal" && *self.peek() == TOpenBracket => {
+                let kind = try!(self.decimal_type());
+                self.cast(kind)
+            }
+            TIdent(name) => match BuiltinKind::from_name(&name) {
+                Some(kind) if *self.peek() == TOpenParen => {
+                    try!(self.consume(TOpenParen));
+                    let mut args = Vec::new();
+                    while *self.peek() != TCloseParen {
+                        args.push(*try!(self.expr()));
+                        if *self.peek() == TComma {
+                            self.next();
+                        } else if *self.peek() != TCloseParen {
+                            return self.error_at_next("Expected ',' or ')'".to_string())
+                        }
+                    }
+                    try!(self.consume(TCloseParen));
+                    Ok(expr_box(Builtin(kind, args)))
+                }
+                _ => Ok(expr_box(Ident(Symbol { name: name, id: 0 }))),
+            },
 
             TOpenParen => {
                 let expr = try!(self.expr());
                 if *self.next() != TCloseParen {
-                    return weld_err!("Expected ')'")
+                    return self.error_at_last("Expected ')'".to_string())
                 }
                 Ok(expr)
             },
@@ -381,7 +512,7 @@ impl<'t> Parser<'t> {
                     if *self.peek() == TComma {
                         self.next();
                     } else if *self.peek() != TCloseBracket {
-                        return weld_err!("Expected ',' or ']'")
+                        return self.error_at_next("Expected ',' or ']'".to_string())
                     }
                 }
                 try!(self.consume(TCloseBracket));
@@ -396,7 +527,7 @@ impl<'t> Parser<'t> {
                     if *self.peek() == TComma {
                         self.next();
                     } else if *self.peek() != TCloseBrace {
-                        return weld_err!("Expected ',' or '}}'")
+                        return self.error_at_next("Expected ',' or '}'".to_string())
                     }
                 }
                 try!(self.consume(TCloseBrace));
@@ -448,20 +579,77 @@ impl<'t> Parser<'t> {
                     elem_type = try!(self.type_());
                     try!(self.consume(TCloseBracket));
                 }
-                let mut expr = expr_box(NewBuilder);
+                let mut expr = expr_box(NewBuilder(None));
                 expr.ty = Builder(Appender(Box::new(elem_type)));
                 Ok(expr)
             }
 
-            ref other => weld_err!("Expected expression but got '{}'", other)
+            // Casts are written as a numeric type applied to a value, e.g. i64(x)
+            TI32 => self.cast(I32),
+            TI64 => self.cast(I64),
+            TF32 => self.cast(F32),
+            TF64 => self.cast(F64),
+
+            ref other => self.error_at_last(format!("Expected expression but got '{}'", other))
+        }
+    }
+
+    /// Parse the parenthesized value of a cast to the given type.
+    fn cast(&mut self, kind: ScalarKind) -> WeldResult<Box<PartialExpr>> {
+        try!(self.consume(TOpenParen));
+        let value = try!(self.expr());
+        try!(self.consume(TCloseParen));
+        Ok(expr_box(Builtin(BuiltinKind::Cast(kind), vec![*value])))
+    }
+
+    /// Parse the [precision,scale] of a decimal type, after the decimal name.
+    fn decimal_type(&mut self) -> WeldResult<ScalarKind> {
+        try!(self.consume(TOpenBracket));
+        let mut digits = Vec::new();
+        for i in 0..2 {
+            if i > 0 {
+                try!(self.consume(TComma));
+            }
+            match *self.next() {
+                TI32Literal(value) if value >= 0 && value <= MAX_DECIMAL_PRECISION as i32 =>
+                    digits.push(value as u8),
+                ref other => return self.error_at_last(format!(
+                    "Expected a number of digits from 0 to {} but got '{}'",
+                    MAX_DECIMAL_PRECISION, other))
+            }
+        }
+        try!(self.consume(TCloseBracket));
+        let (precision, scale) = (digits[0], digits[1]);
+        if precision == 0 || scale > precision {
+            let position = self.position - 1;
+            return self.error(position, format!("Invalid decimal type decimal[{},{}]: the \
+                precision must be at least 1 and at least the scale", precision, scale));
+        }
+        Ok(Decimal(precision, scale))
+    }
+
+    /// Parse the parenthesized arguments of a builder. A single argument is returned as is, and
+    /// several are wrapped in a MakeStruct.
+    fn builder_args(&mut self) -> WeldResult<Box<PartialExpr>> {
+        try!(self.consume(TOpenParen));
+        let mut args = vec![*try!(self.expr())];
+        while *self.peek() == TComma {
+            try!(self.consume(TComma));
+            args.push(*try!(self.expr()));
+        }
+        try!(self.consume(TCloseParen));
+        if args.len() == 1 {
+            Ok(Box::new(args.pop().unwrap()))
+        } else {
+            Ok(expr_box(MakeStruct(args)))
         }
     }
 
     /// Parse a symbol starting at the current input position.
     fn symbol(&mut self) -> WeldResult<Symbol> {
         match *self.next() {
-            TIdent(ref name) => Ok(Symbol { name: name.clone(), id: 0 }),
-            ref other => weld_err!("Expected identifier but got '{}'", other)
+            TIdent(name) => Ok(Symbol { name: name, id: 0 }),
+            ref other => self.error_at_last(format!("Expected identifier but got '{}'", other))
         }
     }
 
@@ -507,7 +695,7 @@ impl<'t> Parser<'t> {
                     if *self.peek() == TComma {
                         self.next();
                     } else if *self.peek() != TCloseBrace {
-                        return weld_err!("Expected ',' or '}}'")
+                        return self.error_at_next("Expected ',' or '}'".to_string())
                     }
                 }
                 try!(self.consume(TCloseBrace));
@@ -516,7 +704,32 @@ impl<'t> Parser<'t> {
 
             TQuestion => Ok(Unknown),
 
-            ref other => weld_err!("Expected type but got '{}'", other)
+            // Names of the newer types are not reserved, so they are only types here
+            TIdent(name) => match name.as_str() {
+                "timestamp" => Ok(Scalar(Timestamp)),
+                "decimal" => Ok(Scalar(try!(self.decimal_type()))),
+                "mutvec" => {
+                    try!(self.consume(TOpenBracket));
+                    let elem_type = try!(self.type_());
+                    try!(self.consume(TCloseBracket));
+                    Ok(Builder(MutVec(Box::new(elem_type))))
+                }
+                "statsmerger" => {
+                    try!(self.consume(TOpenBracket));
+                    let elem_type = try!(self.type_());
+                    try!(self.consume(TCloseBracket));
+                    Ok(Builder(StatsMerger(Box::new(elem_type))))
+                }
+                "histogram" => {
+                    try!(self.consume(TOpenBracket));
+                    let elem_type = try!(self.type_());
+                    try!(self.consume(TCloseBracket));
+                    Ok(Builder(Histogram(Box::new(elem_type))))
+                }
+                _ => self.error_at_last(format!("Expected type but got '{}'", name))
+            },
+
+            ref other => self.error_at_last(format!("Expected type but got '{}'", other))
         }
     }
 }
@@ -565,10 +778,44 @@ fn basic_parsing() {
     let e = parse_expr("appender[i32]").unwrap();
     assert_eq!(print_expr(&e), "appender[i32]");
 
+    let e = parse_expr("mutvec[i32](v)").unwrap();
+    assert_eq!(print_expr(&e), "mutvec[i32](v)");
+
+    let e = parse_expr("mutvec([1, 2])").unwrap();
+    assert_eq!(print_expr(&e), "mutvec[?]([1,2])");
+
+    assert!(parse_expr("mutvec[i32]").is_err());
+
+    let e = parse_expr("statsmerger[f64]").unwrap();
+    assert_eq!(print_expr(&e), "statsmerger[f64]");
+
+    let e = parse_expr("statsmerger").unwrap();
+    assert_eq!(print_expr(&e), "statsmerger[?]");
+
+    let e = parse_expr("gemm(a, b, 2L, 2L, 1L)").unwrap();
+    assert_eq!(print_expr(&e), "gemm(a,b,2L,2L,1L)");
+
+    assert!(parse_expr("dot(a b)").is_err());
+
+    let e = parse_expr("histogram[f64](0.0, x, 10L)").unwrap();
+    assert_eq!(print_expr(&e), "histogram[f64](0.0,x,10L)");
+
+    let e = parse_expr("|d:decimal[12,2]| f64(d) + f64(decimal[4,1](1.25))").unwrap();
+    assert_eq!(print_typed_expr(&e),
+        "|d:decimal[12,2]|(f64(d:?)+f64(decimal[4,1](1.25)))");
+    assert!(parse_type("decimal[39,2]").is_err());
+    assert!(parse_type("decimal[2,3]").is_err());
+    assert!(parse_type("decimal").is_err());
+    let e = parse_expr("decimal(1.0)").unwrap();
+    assert_eq!(print_expr(&e), "(decimal)(1.0)");
+
+    let e = parse_expr("|t:timestamp| trunc_day(add_interval(t, 1L))").unwrap();
+    assert_eq!(print_typed_expr(&e), "|t:timestamp|trunc_day(add_interval(t:?,1L))");
+
     let e = parse_expr("a: i32 + b").unwrap();
     assert_eq!(print_typed_expr(&e), "(a:i32+b:?)");
 
-    assert!(parse_expr("10 * * 2").is_err());
+    assert_eq!(parse_expr("10 * * 2").unwrap_err().kind(), ErrorKind::Parse);
 
     let p = parse_program("macro a(x) = x+x; macro b() = 5; a(b)").unwrap();
     assert_eq!(p.macros.len(), 2);
@@ -579,6 +826,15 @@ fn basic_parsing() {
     let t = parse_type("{i32, vec[vec[?]], ?}").unwrap();
     assert_eq!(print_type(&t), "{i32,vec[vec[?]],?}");
 
+    let t = parse_type("mutvec[f64]").unwrap();
+    assert_eq!(print_type(&t), "mutvec[f64]");
+
+    let t = parse_type("statsmerger[i64]").unwrap();
+    assert_eq!(print_type(&t), "statsmerger[i64]");
+
+    let t = parse_type("histogram[i32]").unwrap();
+    assert_eq!(print_type(&t), "histogram[i32]");
+
     let t = parse_type("{}").unwrap();
     assert_eq!(print_type(&t), "{}");
 }
@@ -610,3 +866,25 @@ fn read_to_end_of_input() {
     assert!(parse_program("macro a() = b; a() + b").is_ok());
     assert!(parse_program("macro a() = b; a() + b;").is_err());
 }
+
+#[test]
+fn error_locations() {
+    let err = parse_expr("10 * * 2").unwrap_err();
+    assert_eq!(err.to_string(), "Expected expression but got '*' at 1:6");
+    assert_eq!(err.span(), Some(Span { start: 5, end: 6 }));
+
+    let code = "let a = 1;\nlet b = foo(a b);\nb";
+    let err = parse_expr(code).unwrap_err();
+    assert_eq!(err.to_string(), "Expected ',' or ')' at 2:15");
+    assert_eq!(err.format_with_source(code),
+        "Expected ',' or ')' at 2:15\n  let b = foo(a b);\n                ^");
+
+    let err = parse_expr("(1 + 2").unwrap_err();
+    assert_eq!(err.to_string(), "Expected ')' at 1:7");
+
+    let err = parse_expr("1 2").unwrap_err();
+    assert_eq!(err.to_string(), "Unexpected token: 2 at 1:3");
+
+    let err = parse_program("macro a(x) = x;\n  a(# + 1)").unwrap_err();
+    assert_eq!(err.to_string(), "Invalid input token: # at 2:5");
+}
@@ -19,7 +19,10 @@ pub enum PartialType {
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum PartialBuilderKind {
     Appender(Box<PartialType>),
-    Merger(Box<PartialType>, BinOpKind)
+    Merger(Box<PartialType>, BinOpKind),
+    MutVec(Box<PartialType>),
+    StatsMerger(Box<PartialType>),
+    Histogram(Box<PartialType>),
 }
 
 /// A partially typed expression.
@@ -49,6 +52,12 @@ impl PartialType {
                 Ok(Type::Builder(BuilderKind::Appender(Box::new(try!(elem.to_type()))))),
             Builder(Merger(ref elem, op)) =>
                 Ok(Type::Builder(BuilderKind::Merger(Box::new(try!(elem.to_type())), op))),
+            Builder(MutVec(ref elem)) =>
+                Ok(Type::Builder(BuilderKind::MutVec(Box::new(try!(elem.to_type()))))),
+            Builder(StatsMerger(ref elem)) =>
+                Ok(Type::Builder(BuilderKind::StatsMerger(Box::new(try!(elem.to_type()))))),
+            Builder(Histogram(ref elem)) =>
+                Ok(Type::Builder(BuilderKind::Histogram(Box::new(try!(elem.to_type()))))),
             Struct(ref elems) => {
                 let mut new_elems = Vec::with_capacity(elems.len());
                 for e in elems {
@@ -77,6 +86,9 @@ impl PartialType {
             Vector(ref elem) => elem.is_complete(),
             Builder(Appender(ref elem)) => elem.is_complete(),
             Builder(Merger(ref elem, _)) => elem.is_complete(),
+            Builder(MutVec(ref elem)) => elem.is_complete(),
+            Builder(StatsMerger(ref elem)) => elem.is_complete(),
+            Builder(Histogram(ref elem)) => elem.is_complete(),
             Struct(ref elems) => elems.iter().all(|e| e.is_complete()),
             Function(ref params, ref res) =>
                 params.iter().all(|p| p.is_complete()) && res.is_complete()
@@ -85,19 +97,16 @@ impl PartialType {
 }
 
 impl PartialBuilderKind {
-    pub fn merge_type(&mut self) -> &PartialType {
-        use self::PartialBuilderKind::*;
-        match *self {
-            Appender(ref elem) => elem.as_ref(),
-            Merger(ref elem, _) => elem.as_ref(),
-        }
-    }
-
-    pub fn merge_type_mut(&mut self) -> &mut PartialType {
+    /// Return the type of values merged into this builder.
+    pub fn merge_type(&self) -> PartialType {
+        use self::PartialType::*;
         use self::PartialBuilderKind::*;
         match *self {
-            Appender(ref mut elem) => elem.as_mut(),
-            Merger(ref mut elem, _) => elem.as_mut(),
+            Appender(ref elem) => *elem.clone(),
+            Merger(ref elem, _) => *elem.clone(),
+            MutVec(ref elem) => Struct(vec![Scalar(ScalarKind::I64), *elem.clone()]),
+            StatsMerger(ref elem) => *elem.clone(),
+            Histogram(ref elem) => *elem.clone(),
         }
     }
 
@@ -107,6 +116,10 @@ impl PartialBuilderKind {
         match *self {
             Appender(ref elem) => Vector((*elem).clone()),
             Merger(ref elem, _) => *elem.clone(),
+            MutVec(ref elem) => Vector((*elem).clone()),
+            StatsMerger(ref elem) => Struct(vec![Scalar(ScalarKind::I64), Scalar(ScalarKind::F64),
+                Scalar(ScalarKind::F64), *elem.clone(), *elem.clone()]),
+            Histogram(_) => Vector(Box::new(Scalar(ScalarKind::I64))),
         }
     }
 }
@@ -134,7 +147,8 @@ impl PartialExpr {
             F32Literal(i) => F32Literal(i),
             F64Literal(i) => F64Literal(i),
             Ident(ref name) => Ident(name.clone()),
-            NewBuilder => NewBuilder,
+            NewBuilder(None) => NewBuilder(None),
+            NewBuilder(Some(ref arg)) => NewBuilder(Some(try!(typed_box(arg)))),
 
             BinOp(op, ref left, ref right) =>
                 BinOp(op, try!(typed_box(left)), try!(typed_box(right))),
@@ -164,6 +178,11 @@ impl PartialExpr {
 
             Res(ref bldr) => Res(try!(typed_box(bldr))),
 
+            Builtin(kind, ref args) => {
+                let args: WeldResult<Vec<_>> = args.iter().map(|e| e.to_typed()).collect();
+                Builtin(kind, try!(args))
+            }
+
             For(ref data, ref bldr, ref func) =>
                 For(try!(typed_box(data)), try!(typed_box(bldr)), try!(typed_box(func))),
 
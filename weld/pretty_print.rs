@@ -1,3 +1,4 @@
+use std::fmt::Write;
 use std::iter::Iterator;
 
 use super::ast::*;
@@ -6,12 +7,16 @@ use super::ast::ScalarKind::*;
 use super::ast::Type::*;
 use super::ast::ExprKind::*;
 use super::partial_types::*;
+use super::program::Program;
 
 // TODO: These methods could take a mutable string as an argument, or even a fmt::Format.
 
 /// A trait for printing types.
 pub trait PrintableType: Clone {
     fn print(&self) -> String;
+
+    /// Whether this is the type of a builder whose several arguments are kept as a struct.
+    fn has_builder_args(&self) -> bool;
 }
 
 /// Print implementation for full Types
@@ -23,6 +28,8 @@ impl PrintableType for Type {
             Scalar(I64) => "i64".to_string(),
             Scalar(F32) => "f32".to_string(),
             Scalar(F64) => "f64".to_string(),
+            Scalar(Timestamp) => "timestamp".to_string(),
+            Scalar(Decimal(precision, scale)) => format!("decimal[{},{}]", precision, scale),
             Vector(ref elem) => format!("vec[{}]", elem.print()),
             Struct(ref elems) => join("{", ",", "}", elems.iter().map(|e| e.print())),
             Function(ref params, ref ret) => {
@@ -33,6 +40,16 @@ impl PrintableType for Type {
             },
             Builder(Appender(ref t)) => format!("appender[{}]", t.print()),
             Builder(Merger(ref t, op)) => format!("merger[{},{}]", t.print(), op),
+            Builder(MutVec(ref t)) => format!("mutvec[{}]", t.print()),
+            Builder(StatsMerger(ref t)) => format!("statsmerger[{}]", t.print()),
+            Builder(Histogram(ref t)) => format!("histogram[{}]", t.print()),
+        }
+    }
+
+    fn has_builder_args(&self) -> bool {
+        match *self {
+            Builder(Histogram(_)) => true,
+            _ => false
         }
     }
 }
@@ -49,6 +66,8 @@ impl PrintableType for PartialType {
             Scalar(I64) => "i64".to_string(),
             Scalar(F32) => "f32".to_string(),
             Scalar(F64) => "f64".to_string(),
+            Scalar(Timestamp) => "timestamp".to_string(),
+            Scalar(Decimal(precision, scale)) => format!("decimal[{},{}]", precision, scale),
             Vector(ref elem) => format!("vec[{}]", elem.print()),
             Struct(ref elems) => join("{", ",", "}", elems.iter().map(|e| e.print())),
             Function(ref params, ref ret) => {
@@ -58,6 +77,18 @@ impl PrintableType for PartialType {
             },
             Builder(Appender(ref elem)) => format!("appender[{}]", elem.print()),
             Builder(Merger(ref t, op)) => format!("merger[{},{}]", t.print(), op),
+            Builder(MutVec(ref elem)) => format!("mutvec[{}]", elem.print()),
+            Builder(StatsMerger(ref elem)) => format!("statsmerger[{}]", elem.print()),
+            Builder(Histogram(ref elem)) => format!("histogram[{}]", elem.print()),
+        }
+    }
+
+    fn has_builder_args(&self) -> bool {
+        use partial_types::PartialType::*;
+        use partial_types::PartialBuilderKind::*;
+        match *self {
+            Builder(Histogram(_)) => true,
+            _ => false
         }
     }
 }
@@ -67,6 +98,17 @@ pub fn print_type<T: PrintableType>(ty: &T) -> String {
     ty.print()
 }
 
+/// Print a program's macro definitions, one per line, followed by its body.
+pub fn print_program(program: &Program) -> String {
+    let mut res = String::new();
+    for m in &program.macros {
+        let params = join("(", ",", ")", m.parameters.iter().map(|p| p.to_string()));
+        res.push_str(&format!("macro {}{} = {};\n", m.name, params, print_expr(&m.body)));
+    }
+    res.push_str(&print_expr(&program.body));
+    res
+}
+
 /// Print an expression concisely (without any type annotations).
 pub fn print_expr<T: PrintableType>(expr: &Expr<T>) -> String {
     print_expr_impl(expr, false)
@@ -142,7 +184,15 @@ fn print_expr_impl<T: PrintableType>(expr: &Expr<T>, typed: bool) -> String {
             res
         }
 
-        NewBuilder => expr.ty.print(),
+        NewBuilder(None) => expr.ty.print(),
+
+        // Several arguments are kept as a struct; print them as separate arguments again
+        NewBuilder(Some(ref arg)) => match arg.kind {
+            MakeStruct(ref args) if expr.ty.has_builder_args() =>
+                join(&format!("{}(", expr.ty.print()), ",", ")",
+                    args.iter().map(|e| print_expr_impl(e, typed))),
+            _ => format!("{}({})", expr.ty.print(), print_expr_impl(arg, typed))
+        },
 
         Res(ref builder) => format!("result({})", print_expr_impl(builder, typed)),
 
@@ -164,6 +214,9 @@ fn print_expr_impl<T: PrintableType>(expr: &Expr<T>, typed: bool) -> String {
                 print_expr_impl(on_false, typed))
         }
 
+        Builtin(kind, ref args) =>
+            join(&format!("{}(", kind), ",", ")", args.iter().map(|e| print_expr_impl(e, typed))),
+
         Apply(ref func, ref params) => {
             let mut res = format!("({})", print_expr_impl(func, typed));
             res.push_str(&join("(", ",", ")", params.iter().map(|e| print_expr_impl(e, typed))));
@@ -181,6 +234,291 @@ fn print_parameter<T: PrintableType>(param: &Parameter<T>, typed: bool) -> Strin
     }
 }
 
+/// Render an expression tree as a Graphviz DOT graph, with one node per expression labeled by
+/// its kind and type.
+pub fn to_dot<T: PrintableType>(expr: &Expr<T>) -> String {
+    to_dot_impl(expr, false)
+}
+
+/// Same as `to_dot`, but fills in builder operations (NewBuilder, Merge and Res) and For loops
+/// with colors so that they stand out in large programs.
+pub fn to_dot_highlighted<T: PrintableType>(expr: &Expr<T>) -> String {
+    to_dot_impl(expr, true)
+}
+
+fn to_dot_impl<T: PrintableType>(expr: &Expr<T>, highlight: bool) -> String {
+    let mut res = String::new();
+    res.push_str("digraph weld {\n");
+    res.push_str("  ordering=out;\n");
+    res.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+    let mut next_id = 0;
+    add_dot_node(expr, highlight, &mut next_id, &mut res);
+    res.push_str("}\n");
+    res
+}
+
+/// Add a node for `expr` and its children to a DOT graph, returning the node's ID.
+fn add_dot_node<T: PrintableType>(expr: &Expr<T>, highlight: bool, next_id: &mut i32,
+        out: &mut String) -> i32 {
+    let id = *next_id;
+    *next_id += 1;
+    let label = format!("{}\n{}", dot_kind_label(expr), expr.ty.print());
+    let style = match expr.kind {
+        NewBuilder(_) | Merge(_, _) | Res(_) if highlight =>
+            ", style=filled, fillcolor=lightblue",
+        For(_, _, _) if highlight => ", style=filled, fillcolor=orange",
+        _ => ""
+    };
+    // Writing to a String cannot fail.
+    writeln!(out, "  n{} [label=\"{}\"{}];", id, dot_escape(&label), style).unwrap();
+    for child in expr.children() {
+        let child_id = add_dot_node(child, highlight, next_id, out);
+        writeln!(out, "  n{} -> n{};", id, child_id).unwrap();
+    }
+    id
+}
+
+/// Describe the kind of an expression, plus any data it holds that is not a child expression.
+fn dot_kind_label<T: PrintableType>(expr: &Expr<T>) -> String {
+    match expr.kind {
+        BoolLiteral(_) | I32Literal(_) | I64Literal(_) | F32Literal(_) | F64Literal(_) =>
+            print_expr(expr),
+        BinOp(kind, _, _) => format!("BinOp {}", kind),
+        Ident(ref symbol) => format!("Ident {}", symbol),
+        NewBuilder(_) => "NewBuilder".to_string(),
+        MakeStruct(_) => "MakeStruct".to_string(),
+        MakeVector(_) => "MakeVector".to_string(),
+        GetField(_, index) => format!("GetField ${}", index),
+        Let(ref symbol, _, _) => format!("Let {}", symbol),
+        If(_, _, _) => "If".to_string(),
+        Lambda(ref params, _) =>
+            join("Lambda |", ",", "|", params.iter().map(|p| print_parameter(p, true))),
+        Apply(_, _) => "Apply".to_string(),
+        For(_, _, _) => "For".to_string(),
+        Merge(_, _) => "Merge".to_string(),
+        Res(_) => "Res".to_string(),
+        Builtin(kind, _) => format!("Builtin {}", kind),
+    }
+}
+
+/// Escape a string for use inside a double-quoted DOT label.
+fn dot_escape(text: &str) -> String {
+    text.replace("\\", "\\\\").replace("\"", "\\\"").replace("\n", "\\n")
+}
+
+/// Indentation added for each level of nesting by `format_program`.
+const FORMAT_INDENT: &'static str = "  ";
+
+// Precedence levels used by `format_program` to decide where parentheses are needed; they
+// follow the grammar in the parser, with higher numbers binding more tightly.
+const ASCRIBE_PRECEDENCE: u32 = 10;
+const POSTFIX_PRECEDENCE: u32 = 11;
+const LEAF_PRECEDENCE: u32 = 12;
+
+/// Format a program as source code in the canonical style used by `weld-fmt`: one macro per
+/// line, each `let` in the program body on its own line, single spaces around binary operators
+/// and after commas, and parentheses only where they are needed. Parsing the result gives back
+/// the same program.
+pub fn format_program(program: &Program) -> String {
+    let mut res = String::new();
+    for m in &program.macros {
+        let params = join("(", ", ", ")", m.parameters.iter().map(|p| p.to_string()));
+        res.push_str(&format!("macro {}{} = {};\n", m.name, params, format_expr(&m.body, 0)));
+    }
+    if !program.macros.is_empty() {
+        res.push_str("\n");
+    }
+    res.push_str(&format_block(&program.body, "", true));
+    res.push_str("\n");
+    res
+}
+
+/// Format an expression in statement position, putting each `let` on its own line. If `top` is
+/// set, a function is also laid out this way, with its body on the following lines.
+fn format_block(expr: &PartialExpr, indent: &str, top: bool) -> String {
+    match expr.kind {
+        Let(_, _, ref body) => {
+            format!("{}\n{}{}", format_let_binding(expr), indent, format_block(body, indent, top))
+        }
+        Lambda(ref params, ref body) if top => {
+            if let Let(_, _, _) = body.kind {
+                let inner = format!("{}{}", indent, FORMAT_INDENT);
+                let body = format_block(body, &inner, false);
+                format!("{}\n{}{}", format_params(params), inner, body)
+            } else {
+                format_expr(expr, 0)
+            }
+        }
+        _ => format_expr(expr, 0)
+    }
+}
+
+/// Format the `let name = value;` part of a Let expression.
+fn format_let_binding(expr: &PartialExpr) -> String {
+    match expr.kind {
+        Let(ref symbol, ref value, _) => {
+            // The parser stores a type annotation on the name in the Let expression's type
+            if expr.ty == PartialType::Unknown {
+                format!("let {} = {};", symbol, format_expr(value, 1))
+            } else {
+                format!("let {}: {} = {};", symbol, expr.ty.print(), format_expr(value, 1))
+            }
+        }
+        _ => format_expr(expr, 0)
+    }
+}
+
+fn format_params(params: &Vec<PartialParameter>) -> String {
+    if params.is_empty() {
+        return "||".to_string();
+    }
+    join("|", ", ", "|", params.iter().map(|p| {
+        if p.ty == PartialType::Unknown {
+            p.name.to_string()
+        } else {
+            format!("{}: {}", p.name, p.ty.print())
+        }
+    }))
+}
+
+/// Format an expression on one line, adding parentheses if it binds less tightly than
+/// `min_precedence` requires.
+fn format_expr(expr: &PartialExpr, min_precedence: u32) -> String {
+    let (text, precedence) = format_with_precedence(expr);
+    if precedence < min_precedence {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+fn format_list(exprs: &Vec<PartialExpr>) -> Vec<String> {
+    exprs.iter().map(|e| format_expr(e, 0)).collect()
+}
+
+/// Format an expression, returning the text along with the precedence of its outermost part.
+fn format_with_precedence(expr: &PartialExpr) -> (String, u32) {
+    match expr.kind {
+        Let(_, _, _) | NewBuilder(_) => (),
+        _ if expr.ty != PartialType::Unknown => {
+            // Expressions parsed with a type ascription
+            let (text, precedence) = format_kind(expr);
+            let text = if precedence < POSTFIX_PRECEDENCE {
+                format!("({})", text)
+            } else {
+                text
+            };
+            return (format!("{}: {}", text, expr.ty.print()), ASCRIBE_PRECEDENCE);
+        }
+        _ => ()
+    }
+    format_kind(expr)
+}
+
+/// Format an expression without any type ascription on it.
+fn format_kind(expr: &PartialExpr) -> (String, u32) {
+    let call = |name: &str, args: Vec<String>| {
+        join(&format!("{}(", name), ", ", ")", args.into_iter())
+    };
+    match expr.kind {
+        BoolLiteral(_) | I32Literal(_) | I64Literal(_) | F32Literal(_) | F64Literal(_) =>
+            (print_expr(expr), LEAF_PRECEDENCE),
+
+        Ident(ref symbol) => (symbol.to_string(), LEAF_PRECEDENCE),
+
+        BinOp(op, ref left, ref right) => {
+            let precedence = binop_precedence(op);
+            // Comparisons do not chain, so neither side can be another one at the same level
+            let left_precedence = if op.is_comparison() {
+                precedence + 1
+            } else {
+                precedence
+            };
+            (format!("{} {} {}", format_expr(left, left_precedence), op,
+                format_expr(right, precedence + 1)), precedence)
+        }
+
+        Let(_, _, ref body) => {
+            (format!("{} {}", format_let_binding(expr), format_expr(body, 0)), 0)
+        }
+
+        Lambda(ref params, ref body) => {
+            (format!("{} {}", format_params(params), format_expr(body, 0)), 0)
+        }
+
+        MakeStruct(ref exprs) =>
+            (join("{", ", ", "}", format_list(exprs).into_iter()), LEAF_PRECEDENCE),
+
+        MakeVector(ref exprs) =>
+            (join("[", ", ", "]", format_list(exprs).into_iter()), LEAF_PRECEDENCE),
+
+        GetField(ref param, index) =>
+            (format!("{}.${}", format_expr(param, POSTFIX_PRECEDENCE), index), POSTFIX_PRECEDENCE),
+
+        Apply(ref func, ref params) => {
+            let func = format_expr(func, POSTFIX_PRECEDENCE);
+            (call(&func, format_list(params)), POSTFIX_PRECEDENCE)
+        }
+
+        NewBuilder(ref arg) => {
+            use partial_types::PartialType::*;
+            use partial_types::PartialBuilderKind::*;
+            // Leave out element types that are still to be inferred
+            let name = match expr.ty {
+                Builder(Appender(ref elem)) if **elem == Unknown => "appender".to_string(),
+                Builder(MutVec(ref elem)) if **elem == Unknown => "mutvec".to_string(),
+                Builder(StatsMerger(ref elem)) if **elem == Unknown => "statsmerger".to_string(),
+                Builder(Histogram(ref elem)) if **elem == Unknown => "histogram".to_string(),
+                ref ty => ty.print()
+            };
+            match *arg {
+                Some(ref arg) => match arg.kind {
+                    MakeStruct(ref args) if expr.ty.has_builder_args() =>
+                        (call(&name, format_list(args)), LEAF_PRECEDENCE),
+                    _ => (call(&name, vec![format_expr(arg, 0)]), LEAF_PRECEDENCE)
+                },
+                None => (name, LEAF_PRECEDENCE)
+            }
+        }
+
+        Res(ref builder) => (call("result", vec![format_expr(builder, 0)]), LEAF_PRECEDENCE),
+
+        Builtin(kind, ref args) => (call(&kind.to_string(), format_list(args)), LEAF_PRECEDENCE),
+
+        Merge(ref builder, ref value) => {
+            let args = vec![format_expr(builder, 0), format_expr(value, 0)];
+            (call("merge", args), LEAF_PRECEDENCE)
+        }
+
+        For(ref data, ref builder, ref func) => {
+            let args = vec![format_expr(data, 0), format_expr(builder, 0), format_expr(func, 0)];
+            (call("for", args), LEAF_PRECEDENCE)
+        }
+
+        If(ref cond, ref on_true, ref on_false) => {
+            let args = vec![
+                format_expr(cond, 0), format_expr(on_true, 0), format_expr(on_false, 0)];
+            (call("if", args), LEAF_PRECEDENCE)
+        }
+    }
+}
+
+/// The precedence of a binary operator in the parser's grammar, from 1 (`||`) to 9 (`*`).
+fn binop_precedence(op: BinOpKind) -> u32 {
+    use ast::BinOpKind::*;
+    match op {
+        LogicalOr => 1,
+        LogicalAnd => 2,
+        BitwiseOr => 3,
+        Xor => 4,
+        BitwiseAnd => 5,
+        Equal | NotEqual => 6,
+        LessThan | LessThanOrEqual | GreaterThan | GreaterThanOrEqual => 7,
+        Add | Subtract => 8,
+        Multiply | Divide | Modulo => 9,
+    }
+}
+
 fn join<T:Iterator<Item=String>>(start: &str, sep: &str, end: &str, strings: T) -> String {
     let mut res = String::new();
     res.push_str(start);
@@ -0,0 +1,100 @@
+//! Reproducible random numbers for the `rand` builtins.
+//!
+//! Random numbers come from a counter-based generator, Philox4x32-10, keyed by the seed. Rather
+//! than advancing a shared state, each number is computed from a counter made of the indices of
+//! the enclosing loop iterations and the number of draws made so far in the innermost one. Every
+//! loop iteration therefore draws the same numbers no matter which thread runs it or in what
+//! order the iterations run, which is what makes `rand()` safe to use in parallel loops.
+
+/// The Philox4x32-10 block function from Salmon et al., "Parallel Random Numbers: As Easy as
+/// 1, 2, 3" (SC 2011).
+pub fn philox4x32(counter: [u32; 4], key: [u32; 2]) -> [u32; 4] {
+    let (mut c, mut k) = (counter, key);
+    for round in 0..10 {
+        if round > 0 {
+            k = [k[0].wrapping_add(0x9E3779B9), k[1].wrapping_add(0xBB67AE85)];
+        }
+        let p0 = 0xD2511F53u64 * c[0] as u64;
+        let p1 = 0xCD9E8D57u64 * c[2] as u64;
+        c = [(p1 >> 32) as u32 ^ c[1] ^ k[0], p1 as u32,
+             (p0 >> 32) as u32 ^ c[3] ^ k[1], p0 as u32];
+    }
+    c
+}
+
+/// Tracks where the next random number comes from while evaluating a program.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RandomState {
+    /// The enclosing loop iterations, outermost first, as (index, draws made so far) pairs.
+    iterations: Vec<(usize, u64)>,
+    /// Draws made outside of any loop.
+    draws: u64,
+}
+
+impl RandomState {
+    pub fn new() -> RandomState {
+        RandomState::default()
+    }
+
+    /// Start an iteration of a loop nested in the current one.
+    pub fn enter_iteration(&mut self, index: usize) {
+        self.iterations.push((index, 0));
+    }
+
+    /// Finish the innermost loop iteration.
+    pub fn exit_iteration(&mut self) {
+        self.iterations.pop();
+    }
+
+    /// Draw a number uniformly distributed in [0, 1) from the stream with the given seed.
+    pub fn next(&mut self, seed: i64) -> f64 {
+        // Fold the loop indices into 64 bits; a single loop's index is used as is (plus one)
+        let mut path = 0u64;
+        for &(index, _) in &self.iterations {
+            path = path.wrapping_mul(0x100000001B3) ^ (index as u64 + 1);
+        }
+        let draws = match self.iterations.last_mut() {
+            Some(&mut (_, ref mut draws)) => draws,
+            None => &mut self.draws
+        };
+        let counter = [*draws as u32, (*draws >> 32) as u32, path as u32, (path >> 32) as u32];
+        *draws += 1;
+        let seed = seed as u64;
+        let out = philox4x32(counter, [seed as u32, (seed >> 32) as u32]);
+        // Use the top 53 bits of the first two words as the mantissa
+        let bits = ((out[0] as u64) << 32 | out[1] as u64) >> 11;
+        bits as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[test]
+fn philox_known_answers() {
+    // Known-answer tests from the Random123 distribution
+    assert_eq!(philox4x32([0; 4], [0; 2]), [0x6627e8d5, 0xe169c58d, 0xbc57ac4c, 0x9b00dbd8]);
+    assert_eq!(philox4x32([0xffffffff; 4], [0xffffffff; 2]),
+        [0x408f276d, 0x41c83b0e, 0xa20bc7c6, 0x6d5451fd]);
+}
+
+#[test]
+fn random_streams() {
+    let mut a = RandomState::new();
+    let first = a.next(1);
+    assert!(first >= 0.0 && first < 1.0);
+    assert!(a.next(1) != first);
+    assert!(RandomState::new().next(2) != first);
+
+    // Each iteration draws the same numbers regardless of what ran before it
+    let mut b = RandomState::new();
+    b.enter_iteration(3);
+    let x = b.next(1);
+    b.exit_iteration();
+    let mut c = RandomState::new();
+    for i in 0..4 {
+        c.enter_iteration(i);
+        if i == 3 {
+            assert_eq!(c.next(1), x);
+        }
+        c.next(1);
+        c.exit_iteration();
+    }
+}
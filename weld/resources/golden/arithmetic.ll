@@ -0,0 +1,39 @@
+; PRELUDE:
+
+
+%s0 = type { i32, i32 }
+
+; BODY:
+
+define i32 @run.raw(i32 %x.in, i32 %y.in) {
+entry:
+  %x = alloca i32
+  %y = alloca i32
+  store i32 %x.in, i32* %x
+  store i32 %y.in, i32* %y
+  %0 = load i32* %x
+  %1 = load i32* %y
+  %2 = add i32 %0, %1
+  %3 = load i32* %x
+  %4 = load i32* %y
+  %5 = sub i32 %3, %4
+  %6 = mul i32 %2, %5
+  %7 = sdiv i32 %6, 2
+  ret i32 %7
+}
+
+define i64 @run(i64 %args) {
+  %res_size_ptr = getelementptr i32* null, i32 1
+  %res_size = ptrtoint i32* %res_size_ptr to i64
+  %res_bytes = call i8* @malloc(i64 %res_size)
+  %res_typed = bitcast i8* %res_bytes to i32*
+  %args_typed = inttoptr i64 %args to %s0*
+  %args_val = load %s0* %args_typed
+  %arg0 = extractvalue %s0 %args_val, 0
+  %arg1 = extractvalue %s0 %args_val, 1
+  %res_val = call i32 @run.raw(i32 %arg0, i32 %arg1)
+  store i32 %res_val, i32* %res_typed
+  %res_address = ptrtoint i32* %res_typed to i64
+  ret i64 %res_address
+}
+
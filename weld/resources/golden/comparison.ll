@@ -0,0 +1,42 @@
+; PRELUDE:
+
+
+%s0 = type { i32 }
+
+; BODY:
+
+define i32 @run.raw(i32 %x.in) {
+entry:
+  %x = alloca i32
+  store i32 %x.in, i32* %x
+  %0 = load i32* %x
+  %1 = icmp sgt i32 %0, 10
+  br i1 %1, label %if0.true, label %if0.false
+if0.true:
+  %2 = load i32* %x
+  br label %if0.true.end
+if0.true.end:
+  br label %if0.end
+if0.false:
+  br label %if0.false.end
+if0.false.end:
+  br label %if0.end
+if0.end:
+  %3 = phi i32 [%2, %if0.true.end], [10, %if0.false.end]
+  ret i32 %3
+}
+
+define i64 @run(i64 %args) {
+  %res_size_ptr = getelementptr i32* null, i32 1
+  %res_size = ptrtoint i32* %res_size_ptr to i64
+  %res_bytes = call i8* @malloc(i64 %res_size)
+  %res_typed = bitcast i8* %res_bytes to i32*
+  %args_typed = inttoptr i64 %args to %s0*
+  %args_val = load %s0* %args_typed
+  %arg0 = extractvalue %s0 %args_val, 0
+  %res_val = call i32 @run.raw(i32 %arg0)
+  store i32 %res_val, i32* %res_typed
+  %res_address = ptrtoint i32* %res_typed to i64
+  ret i64 %res_address
+}
+
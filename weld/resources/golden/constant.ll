@@ -0,0 +1,26 @@
+; PRELUDE:
+
+
+%s0 = type {  }
+
+; BODY:
+
+define i32 @run.raw() {
+entry:
+  %0 = add i32 40, 2
+  ret i32 %0
+}
+
+define i64 @run(i64 %args) {
+  %res_size_ptr = getelementptr i32* null, i32 1
+  %res_size = ptrtoint i32* %res_size_ptr to i64
+  %res_bytes = call i8* @malloc(i64 %res_size)
+  %res_typed = bitcast i8* %res_bytes to i32*
+  %args_typed = inttoptr i64 %args to %s0*
+  %args_val = load %s0* %args_typed
+  %res_val = call i32 @run.raw()
+  store i32 %res_val, i32* %res_typed
+  %res_address = ptrtoint i32* %res_typed to i64
+  ret i64 %res_address
+}
+
@@ -0,0 +1,52 @@
+; PRELUDE:
+
+
+%s0 = type { double, double }
+
+; BODY:
+
+define double @run.raw(double %a.in, double %b.in) {
+entry:
+  %a = alloca double
+  %b = alloca double
+  store double %a.in, double* %a
+  store double %b.in, double* %b
+  %0 = load double* %a
+  %1 = load double* %b
+  %2 = fcmp ole double %0, %1
+  br i1 %2, label %if0.true, label %if0.false
+if0.true:
+  %3 = load double* %b
+  %4 = load double* %a
+  %5 = fsub double %3, %4
+  br label %if0.true.end
+if0.true.end:
+  br label %if0.end
+if0.false:
+  %6 = load double* %a
+  %7 = load double* %b
+  %8 = fsub double %6, %7
+  %9 = fmul double %8, 0x3FE0000000000000
+  br label %if0.false.end
+if0.false.end:
+  br label %if0.end
+if0.end:
+  %10 = phi double [%5, %if0.true.end], [%9, %if0.false.end]
+  ret double %10
+}
+
+define i64 @run(i64 %args) {
+  %res_size_ptr = getelementptr double* null, i32 1
+  %res_size = ptrtoint double* %res_size_ptr to i64
+  %res_bytes = call i8* @malloc(i64 %res_size)
+  %res_typed = bitcast i8* %res_bytes to double*
+  %args_typed = inttoptr i64 %args to %s0*
+  %args_val = load %s0* %args_typed
+  %arg0 = extractvalue %s0 %args_val, 0
+  %arg1 = extractvalue %s0 %args_val, 1
+  %res_val = call double @run.raw(double %arg0, double %arg1)
+  store double %res_val, double* %res_typed
+  %res_address = ptrtoint double* %res_typed to i64
+  ret i64 %res_address
+}
+
@@ -0,0 +1,39 @@
+; PRELUDE:
+
+
+%s0 = type { i64 }
+
+; BODY:
+
+define i64 @run.raw(i64 %x.in) {
+entry:
+  %x = alloca i64
+  %y = alloca i64
+  %z = alloca i64
+  store i64 %x.in, i64* %x
+  %0 = load i64* %x
+  %1 = mul i64 %0, 3
+  store i64 %1, i64* %y
+  %2 = load i64* %y
+  %3 = add i64 %2, 1
+  store i64 %3, i64* %z
+  %4 = load i64* %z
+  %5 = load i64* %x
+  %6 = sub i64 %4, %5
+  ret i64 %6
+}
+
+define i64 @run(i64 %args) {
+  %res_size_ptr = getelementptr i64* null, i32 1
+  %res_size = ptrtoint i64* %res_size_ptr to i64
+  %res_bytes = call i8* @malloc(i64 %res_size)
+  %res_typed = bitcast i8* %res_bytes to i64*
+  %args_typed = inttoptr i64 %args to %s0*
+  %args_val = load %s0* %args_typed
+  %arg0 = extractvalue %s0 %args_val, 0
+  %res_val = call i64 @run.raw(i64 %arg0)
+  store i64 %res_val, i64* %res_typed
+  %res_address = ptrtoint i64* %res_typed to i64
+  ret i64 %res_address
+}
+
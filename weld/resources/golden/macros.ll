@@ -0,0 +1,51 @@
+; PRELUDE:
+
+
+%s0 = type { i32, i1 }
+
+; BODY:
+
+define i32 @run.raw(i32 %x.in, i1 %flag.in) {
+entry:
+  %x = alloca i32
+  %flag = alloca i1
+  store i32 %x.in, i32* %x
+  store i1 %flag.in, i1* %flag
+  %0 = load i1* %flag
+  br i1 %0, label %if0.true, label %if0.false
+if0.true:
+  %1 = load i32* %x
+  %2 = load i32* %x
+  %3 = mul i32 %1, %2
+  br label %if0.true.end
+if0.true.end:
+  br label %if0.end
+if0.false:
+  %4 = load i32* %x
+  %5 = add i32 %4, 1
+  %6 = load i32* %x
+  %7 = add i32 %6, 1
+  %8 = mul i32 %5, %7
+  br label %if0.false.end
+if0.false.end:
+  br label %if0.end
+if0.end:
+  %9 = phi i32 [%3, %if0.true.end], [%8, %if0.false.end]
+  ret i32 %9
+}
+
+define i64 @run(i64 %args) {
+  %res_size_ptr = getelementptr i32* null, i32 1
+  %res_size = ptrtoint i32* %res_size_ptr to i64
+  %res_bytes = call i8* @malloc(i64 %res_size)
+  %res_typed = bitcast i8* %res_bytes to i32*
+  %args_typed = inttoptr i64 %args to %s0*
+  %args_val = load %s0* %args_typed
+  %arg0 = extractvalue %s0 %args_val, 0
+  %arg1 = extractvalue %s0 %args_val, 1
+  %res_val = call i32 @run.raw(i32 %arg0, i1 %arg1)
+  store i32 %res_val, i32* %res_typed
+  %res_address = ptrtoint i32* %res_typed to i64
+  ret i64 %res_address
+}
+
@@ -134,3 +134,255 @@ ne:
   %3 = select i1 %2, i32 -1, i32 1
   ret i32 %3
 }
+
+; Timestamp functions
+;
+; Timestamps are i64 microseconds since 1970-01-01 00:00:00 UTC. Dates are split into years,
+; months and days with Howard Hinnant's civil_from_days and days_from_civil algorithms; these
+; must stay in sync with timestamp.rs, which the interpreters use.
+
+declare {i64, i1} @llvm.smul.with.overflow.i64(i64, i64)
+declare {i64, i1} @llvm.ssub.with.overflow.i64(i64, i64)
+declare void @llvm.trap() noreturn nounwind
+
+; Division rounding towards negative infinity, and its remainder, for a positive divisor. These
+; correct the truncating sdiv and srem rather than offsetting a, which could overflow.
+define i64 @timestamp.floor_mod(i64 %a, i64 %b) {
+  %1 = srem i64 %a, %b
+  %2 = icmp slt i64 %1, 0
+  %3 = add i64 %1, %b
+  %4 = select i1 %2, i64 %3, i64 %1
+  ret i64 %4
+}
+
+define i64 @timestamp.floor_div(i64 %a, i64 %b) {
+  %1 = sdiv i64 %a, %b
+  %2 = srem i64 %a, %b
+  %3 = icmp slt i64 %2, 0
+  %4 = sext i1 %3 to i64
+  %5 = add i64 %1, %4
+  ret i64 %5
+}
+
+; The {year, month, day} of a number of days since the epoch
+define {i64, i64, i64} @timestamp.civil_from_days(i64 %days) {
+  %z = add i64 %days, 719468
+  %era = call i64 @timestamp.floor_div(i64 %z, i64 146097)
+  %1 = mul i64 %era, 146097
+  %doe = sub i64 %z, %1
+  ; yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365
+  %2 = sdiv i64 %doe, 1460
+  %3 = sdiv i64 %doe, 36524
+  %4 = sdiv i64 %doe, 146096
+  %5 = sub i64 %doe, %2
+  %6 = add i64 %5, %3
+  %7 = sub i64 %6, %4
+  %yoe = sdiv i64 %7, 365
+  ; doy = doe - (365*yoe + yoe/4 - yoe/100)
+  %8 = mul i64 %yoe, 365
+  %9 = sdiv i64 %yoe, 4
+  %10 = sdiv i64 %yoe, 100
+  %11 = add i64 %8, %9
+  %12 = sub i64 %11, %10
+  %doy = sub i64 %doe, %12
+  ; mp = (5*doy + 2) / 153; day = doy - (153*mp + 2)/5 + 1
+  %13 = mul i64 %doy, 5
+  %14 = add i64 %13, 2
+  %mp = sdiv i64 %14, 153
+  %15 = mul i64 %mp, 153
+  %16 = add i64 %15, 2
+  %17 = sdiv i64 %16, 5
+  %18 = sub i64 %doy, %17
+  %day = add i64 %18, 1
+  ; month = mp < 10 ? mp + 3 : mp - 9; year = yoe + era*400 + (month <= 2)
+  %19 = icmp slt i64 %mp, 10
+  %20 = add i64 %mp, 3
+  %21 = sub i64 %mp, 9
+  %month = select i1 %19, i64 %20, i64 %21
+  %22 = mul i64 %era, 400
+  %23 = add i64 %yoe, %22
+  %24 = icmp sle i64 %month, 2
+  %25 = zext i1 %24 to i64
+  %year = add i64 %23, %25
+  %26 = insertvalue {i64, i64, i64} undef, i64 %year, 0
+  %27 = insertvalue {i64, i64, i64} %26, i64 %month, 1
+  %28 = insertvalue {i64, i64, i64} %27, i64 %day, 2
+  ret {i64, i64, i64} %28
+}
+
+; The number of days since the epoch of a year, month and day
+define i64 @timestamp.days_from_civil(i64 %year, i64 %month, i64 %day) {
+  ; y = year - (month <= 2)
+  %1 = icmp sle i64 %month, 2
+  %2 = zext i1 %1 to i64
+  %y = sub i64 %year, %2
+  %era = call i64 @timestamp.floor_div(i64 %y, i64 400)
+  %3 = mul i64 %era, 400
+  %yoe = sub i64 %y, %3
+  ; mp = month > 2 ? month - 3 : month + 9; doy = (153*mp + 2)/5 + day - 1
+  %4 = icmp sgt i64 %month, 2
+  %5 = sub i64 %month, 3
+  %6 = add i64 %month, 9
+  %mp = select i1 %4, i64 %5, i64 %6
+  %7 = mul i64 %mp, 153
+  %8 = add i64 %7, 2
+  %9 = sdiv i64 %8, 5
+  %10 = add i64 %9, %day
+  %doy = sub i64 %10, 1
+  ; doe = yoe*365 + yoe/4 - yoe/100 + doy; days = era*146097 + doe - 719468
+  %11 = mul i64 %yoe, 365
+  %12 = sdiv i64 %yoe, 4
+  %13 = sdiv i64 %yoe, 100
+  %14 = add i64 %11, %12
+  %15 = sub i64 %14, %13
+  %doe = add i64 %15, %doy
+  %16 = mul i64 %era, 146097
+  %17 = add i64 %16, %doe
+  %18 = sub i64 %17, 719468
+  ret i64 %18
+}
+
+define {i64, i64, i64} @timestamp.civil(i64 %t) {
+  %1 = call i64 @timestamp.floor_div(i64 %t, i64 86400000000)
+  %2 = call {i64, i64, i64} @timestamp.civil_from_days(i64 %1)
+  ret {i64, i64, i64} %2
+}
+
+define i32 @timestamp.extract_year(i64 %t) {
+  %1 = call {i64, i64, i64} @timestamp.civil(i64 %t)
+  %2 = extractvalue {i64, i64, i64} %1, 0
+  %3 = trunc i64 %2 to i32
+  ret i32 %3
+}
+
+define i32 @timestamp.extract_month(i64 %t) {
+  %1 = call {i64, i64, i64} @timestamp.civil(i64 %t)
+  %2 = extractvalue {i64, i64, i64} %1, 1
+  %3 = trunc i64 %2 to i32
+  ret i32 %3
+}
+
+define i32 @timestamp.extract_day(i64 %t) {
+  %1 = call {i64, i64, i64} @timestamp.civil(i64 %t)
+  %2 = extractvalue {i64, i64, i64} %1, 2
+  %3 = trunc i64 %2 to i32
+  ret i32 %3
+}
+
+define i32 @timestamp.extract_hour(i64 %t) {
+  %1 = call i64 @timestamp.floor_mod(i64 %t, i64 86400000000)
+  %2 = sdiv i64 %1, 3600000000
+  %3 = trunc i64 %2 to i32
+  ret i32 %3
+}
+
+; The timestamp at the start of a day, and a timestamp rounded down to a multiple of unit.
+; Generated code cannot report errors yet, so these stop the program if the result is before
+; the earliest timestamp rather than wrap around.
+define i64 @timestamp.from_days(i64 %days) {
+  %1 = call {i64, i1} @llvm.smul.with.overflow.i64(i64 %days, i64 86400000000)
+  %2 = extractvalue {i64, i1} %1, 1
+  br i1 %2, label %overflow, label %done
+overflow:
+  call void @llvm.trap()
+  unreachable
+done:
+  %3 = extractvalue {i64, i1} %1, 0
+  ret i64 %3
+}
+
+define i64 @timestamp.round_down(i64 %t, i64 %unit) {
+  %1 = call i64 @timestamp.floor_mod(i64 %t, i64 %unit)
+  %2 = call {i64, i1} @llvm.ssub.with.overflow.i64(i64 %t, i64 %1)
+  %3 = extractvalue {i64, i1} %2, 1
+  br i1 %3, label %overflow, label %done
+overflow:
+  call void @llvm.trap()
+  unreachable
+done:
+  %4 = extractvalue {i64, i1} %2, 0
+  ret i64 %4
+}
+
+define i64 @timestamp.trunc_year(i64 %t) {
+  %1 = call {i64, i64, i64} @timestamp.civil(i64 %t)
+  %2 = extractvalue {i64, i64, i64} %1, 0
+  %3 = call i64 @timestamp.days_from_civil(i64 %2, i64 1, i64 1)
+  %4 = call i64 @timestamp.from_days(i64 %3)
+  ret i64 %4
+}
+
+define i64 @timestamp.trunc_month(i64 %t) {
+  %1 = call {i64, i64, i64} @timestamp.civil(i64 %t)
+  %2 = extractvalue {i64, i64, i64} %1, 0
+  %3 = extractvalue {i64, i64, i64} %1, 1
+  %4 = call i64 @timestamp.days_from_civil(i64 %2, i64 %3, i64 1)
+  %5 = call i64 @timestamp.from_days(i64 %4)
+  ret i64 %5
+}
+
+define i64 @timestamp.trunc_day(i64 %t) {
+  %1 = call i64 @timestamp.round_down(i64 %t, i64 86400000000)
+  ret i64 %1
+}
+
+define i64 @timestamp.trunc_hour(i64 %t) {
+  %1 = call i64 @timestamp.round_down(i64 %t, i64 3600000000)
+  ret i64 %1
+}
+
+; Decimal functions
+;
+; Decimals are integers counting units of their last digit, stored in an i64 for precisions up to
+; 18 and in an i128 otherwise. These must stay in sync with decimal.rs, which the interpreters use.
+
+declare double @llvm.round.f64(double)
+
+; Division rounding to the nearest integer, with ties away from zero
+define i128 @i128.div_round(i128 %n, i128 %d) {
+  %q = sdiv i128 %n, %d
+  %r = srem i128 %n, %d
+  ; Round away from zero if |r| >= |d| - |r|, which is 2|r| >= |d| without overflowing
+  %1 = icmp slt i128 %r, 0
+  %2 = sub i128 0, %r
+  %abs_r = select i1 %1, i128 %2, i128 %r
+  %3 = icmp slt i128 %d, 0
+  %4 = sub i128 0, %d
+  %abs_d = select i1 %3, i128 %4, i128 %d
+  %5 = sub i128 %abs_d, %abs_r
+  %6 = icmp sge i128 %abs_r, %5
+  %7 = icmp slt i128 %n, 0
+  %8 = xor i1 %7, %3
+  %9 = select i1 %8, i128 -1, i128 1
+  %10 = select i1 %6, i128 %9, i128 0
+  %11 = add i128 %q, %10
+  ret i128 %11
+}
+
+; Product and quotient of two decimals of the same type, given 10^scale
+define i64 @decimal64.mul(i64 %a, i64 %b, i64 %factor) {
+  %1 = sext i64 %a to i128
+  %2 = sext i64 %b to i128
+  %3 = sext i64 %factor to i128
+  %4 = mul i128 %1, %2
+  %5 = call i128 @i128.div_round(i128 %4, i128 %3)
+  %6 = trunc i128 %5 to i64
+  ret i64 %6
+}
+
+define i64 @decimal64.div(i64 %a, i64 %b, i64 %factor) {
+  ; Generated code cannot report errors yet, so stop the program rather than divide by zero
+  %is_zero = icmp eq i64 %b, 0
+  br i1 %is_zero, label %div_by_zero, label %divide
+div_by_zero:
+  call void @llvm.trap()
+  unreachable
+divide:
+  %1 = sext i64 %a to i128
+  %2 = sext i64 %b to i128
+  %3 = sext i64 %factor to i128
+  %4 = mul i128 %1, %3
+  %5 = call i128 @i128.div_round(i128 %4, i128 %2)
+  %6 = trunc i128 %5 to i64
+  ret i64 %6
+}
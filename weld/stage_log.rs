@@ -0,0 +1,185 @@
+//! Dumping of the program after each compilation stage, for debugging the compiler.
+//!
+//! The stages to dump are chosen with `weld.compile.dumpStages`, or with the `WELD_LOG`
+//! environment variable if that key is not set. Since `WELD_LOG` is often set for a whole shell
+//! session, an invalid value is only reported with a warning, once per process, and turns
+//! dumping off instead of making compilation fail. Each dump goes to a numbered file in
+//! `weld.compile.dumpDir` if it is set, to a callback for embedders that want to capture dumps
+//! themselves, or to stderr otherwise.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Once, ONCE_INIT};
+
+use super::conf::{parse_stages, CompileConf, LOG_ENV_VAR};
+use super::error::*;
+
+#[cfg(test)] use std::cell::RefCell;
+#[cfg(test)] use std::io::Read;
+#[cfg(test)] use std::rc::Rc;
+#[cfg(test)] use super::conf::*;
+#[cfg(test)] use super::llvm::generate_code_with_log;
+#[cfg(test)] use super::parser::parse_program;
+
+static INVALID_LOG_WARNING: Once = ONCE_INIT;
+
+/// The stages that a `CompileConf` asks to dump.
+pub fn logged_stages(conf: &CompileConf) -> Vec<String> {
+    stages_or_env(conf, env::var(LOG_ENV_VAR).ok())
+}
+
+/// The stages in `conf`, or in `env_value` (the value of `WELD_LOG`) if `conf` has none.
+fn stages_or_env(conf: &CompileConf, env_value: Option<String>) -> Vec<String> {
+    if let Some(ref stages) = conf.dump_stages {
+        return stages.clone();
+    }
+    match env_value.map(|value| parse_stages(LOG_ENV_VAR, &value)) {
+        Some(Ok(stages)) => stages,
+        Some(Err(e)) => {
+            INVALID_LOG_WARNING.call_once(|| {
+                let _ = writeln!(io::stderr(), "Warning: not dumping any stages: {}", e);
+            });
+            Vec::new()
+        }
+        None => Vec::new()
+    }
+}
+
+/// Where stage dumps are sent.
+enum Destination {
+    Stderr,
+    Directory(PathBuf),
+    Callback(Box<FnMut(&str, &str)>),
+}
+
+/// Records the program after the compilation stages that a `CompileConf` asks for.
+pub struct StageLog {
+    stages: Vec<String>,
+    destination: Destination,
+    /// Number of dumps written so far, used to order the files in a dump directory.
+    count: usize,
+}
+
+impl StageLog {
+    /// Create a log that dumps the stages in `conf` to its dump directory, or to stderr.
+    pub fn new(conf: &CompileConf) -> StageLog {
+        let destination = match conf.dump_dir {
+            Some(ref dir) => Destination::Directory(PathBuf::from(dir)),
+            None => Destination::Stderr
+        };
+        StageLog { stages: logged_stages(conf), destination: destination, count: 0 }
+    }
+
+    /// Create a log that passes the name and text of each stage in `conf` to `callback`.
+    pub fn with_callback(conf: &CompileConf, callback: Box<FnMut(&str, &str)>) -> StageLog {
+        StageLog {
+            stages: logged_stages(conf),
+            destination: Destination::Callback(callback),
+            count: 0,
+        }
+    }
+
+    /// Is the given stage being dumped?
+    pub fn enabled(&self, stage: &str) -> bool {
+        self.stages.iter().any(|s| s == stage)
+    }
+
+    /// Dump the program after `stage` if it was requested. The text is only computed if needed.
+    pub fn dump<F: FnOnce() -> String>(&mut self, stage: &str, text: F) -> WeldResult<()> {
+        if !self.enabled(stage) {
+            return Ok(());
+        }
+        let text = text();
+        match self.destination {
+            Destination::Stderr => {
+                let _ = writeln!(io::stderr(), "=== After {} ===\n{}", stage, text);
+            }
+            Destination::Directory(ref dir) => {
+                let path = dir.join(format!("{:02}-{}.txt", self.count, stage));
+                let res = fs::create_dir_all(dir)
+                    .and_then(|_| File::create(&path))
+                    .and_then(|mut f| f.write_all(text.as_bytes()));
+                if let Err(e) = res {
+                    return weld_err!(Config, "Could not write {}: {}", path.display(), e);
+                }
+            }
+            Destination::Callback(ref mut callback) => callback(stage, &text)
+        }
+        self.count += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn stage_callbacks() {
+    let mut conf = WeldConf::new();
+    conf.set(DUMP_STAGES_KEY, "parse,llvm");
+    let conf = CompileConf::parse(&conf).unwrap();
+    let dumps = Rc::new(RefCell::new(Vec::new()));
+    let sink = dumps.clone();
+    let mut log = StageLog::with_callback(&conf, Box::new(move |stage: &str, text: &str| {
+        sink.borrow_mut().push(format!("{}: {}", stage, text));
+    }));
+    assert!(log.enabled("llvm"));
+    assert!(!log.enabled("types"));
+    log.dump("parse", || "a".to_string()).unwrap();
+    log.dump("types", || panic!("types should not be printed")).unwrap();
+    log.dump("llvm", || "b".to_string()).unwrap();
+    assert_eq!(*dumps.borrow(), vec!["parse: a", "llvm: b"]);
+}
+
+#[test]
+fn log_variable() {
+    // WELD_LOG is only used if the configuration does not list stages
+    let stages = |conf: &WeldConf, env_value: &str| {
+        let conf = CompileConf::parse(conf).unwrap();
+        stages_or_env(&conf, Some(env_value.to_string()))
+    };
+    let mut conf = WeldConf::new();
+    assert_eq!(stages(&conf, "macros,llvm"), vec!["macros", "llvm"]);
+    assert_eq!(stages_or_env(&CompileConf::parse(&conf).unwrap(), None).len(), 0);
+    // An invalid value turns dumping off rather than failing
+    assert_eq!(stages(&conf, "macros,bogus").len(), 0);
+    conf.set(DUMP_STAGES_KEY, "types");
+    assert_eq!(stages(&conf, "macros"), vec!["types"]);
+    conf.set(DUMP_STAGES_KEY, "");
+    assert_eq!(stages(&conf, "macros").len(), 0);
+}
+
+#[test]
+fn stage_files() {
+    let dir = env::temp_dir().join(format!("weld-stage-log-{}", ::std::process::id()));
+    let mut conf = WeldConf::new();
+    conf.set(DUMP_STAGES_KEY, "all");
+    conf.set(DUMP_DIR_KEY, dir.to_string_lossy().into_owned());
+    let mut log = StageLog::new(&CompileConf::parse(&conf).unwrap());
+    log.dump("macros", || "x".to_string()).unwrap();
+    log.dump("inline-apply", || "y".to_string()).unwrap();
+    let mut text = String::new();
+    File::open(dir.join("01-inline-apply.txt")).unwrap().read_to_string(&mut text).unwrap();
+    assert_eq!(text, "y");
+    assert!(dir.join("00-macros.txt").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compilation_stages() {
+    let mut conf = WeldConf::new();
+    conf.set(DUMP_STAGES_KEY, "all");
+    let conf = CompileConf::parse(&conf).unwrap();
+    let dumps = Rc::new(RefCell::new(Vec::new()));
+    let sink = dumps.clone();
+    let mut log = StageLog::with_callback(&conf, Box::new(move |stage: &str, text: &str| {
+        sink.borrow_mut().push((stage.to_string(), text.to_string()));
+    }));
+    let program = parse_program("macro inc(x) = x + 1; |a:i32| inc(a)").unwrap();
+    generate_code_with_log(&program, &conf, &mut log).unwrap();
+    let dumps = dumps.borrow();
+    let stages: Vec<&str> = dumps.iter().map(|d| d.0.as_str()).collect();
+    assert_eq!(stages, vec!["parse", "macros", "inline-apply", "types", "llvm"]);
+    assert_eq!(dumps[0].1, "macro inc(x) = (x+1);\n|a|(inc)(a)");
+    assert_eq!(dumps[3].1, "|a:i32|(a:i32+1)");
+    assert!(dumps[4].1.contains("define i32 @run.raw"));
+}
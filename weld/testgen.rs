@@ -0,0 +1,410 @@
+//! Random generation of well-typed Weld programs and inputs, for fuzzing and differential testing.
+//!
+//! Programs are built directly as typed expressions: to generate an expression of some type,
+//! we pick a construct that can produce that type and recursively generate its children, using
+//! the variables in scope where possible. Generated programs avoid behavior whose result depends
+//! on the backend, such as integer division by zero or float division (which can produce NaNs),
+//! so that different ways of running the same program should agree exactly.
+
+use super::ast::*;
+use super::ast::BinOpKind::*;
+use super::ast::BuilderKind::*;
+use super::ast::ExprKind::*;
+use super::ast::ScalarKind::*;
+use super::ast::Type::*;
+use super::pretty_print::*;
+
+#[cfg(test)] use super::llvm::LlvmGenerator;
+#[cfg(test)] use super::parser::*;
+#[cfg(test)] use super::type_inference::*;
+
+/// A small, fast pseudo-random number generator (xorshift64*), so that generated programs are
+/// reproducible from a seed without depending on an external crate.
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u64
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        // Scramble the seed (with a splitmix64 step) so that nearby seeds give unrelated
+        // sequences; the state must also never be zero.
+        let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        Random { state: (z ^ (z >> 31)) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Return a random number in [0, n).
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % (n as u64)) as usize
+    }
+
+    /// Return true with probability `percent` / 100.
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    /// Pick a random element of a non-empty slice.
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// Settings for the size of generated programs and which language features they use.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenConf {
+    /// Maximum depth of the generated expression tree.
+    pub max_depth: usize,
+    /// Maximum number of parameters of the generated function.
+    pub max_params: usize,
+    pub lets: bool,
+    pub ifs: bool,
+    pub floats: bool,
+    /// Modulo, logical and bitwise operators, which the LLVM backend does not support yet.
+    pub extended_ops: bool,
+    pub structs: bool,
+    /// Vector values, and For loops that map over them with appenders.
+    pub vectors: bool,
+}
+
+impl GenConf {
+    /// Settings that use every supported feature.
+    pub fn new() -> GenConf {
+        GenConf {
+            max_depth: 5,
+            max_params: 3,
+            lets: true,
+            ifs: true,
+            floats: true,
+            extended_ops: true,
+            structs: true,
+            vectors: true,
+        }
+    }
+
+    /// Settings limited to scalar programs that the LLVM backend can compile.
+    pub fn scalar() -> GenConf {
+        GenConf { extended_ops: false, structs: false, vectors: false, ..GenConf::new() }
+    }
+}
+
+/// A generated program (a Lambda) and arguments to call it with, as literal expressions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestCase {
+    pub program: TypedExpr,
+    pub args: Vec<TypedExpr>,
+}
+
+impl TestCase {
+    /// The program's source code. Only the parameter types are written out; the others are
+    /// left to type inference.
+    pub fn program_source(&self) -> String {
+        match self.program.kind {
+            Lambda(ref params, ref body) => {
+                let params: Vec<String> = params.iter()
+                    .map(|p| format!("{}:{}", p.name, print_type(&p.ty)))
+                    .collect();
+                format!("|{}|{}", params.join(","), print_expr(body))
+            }
+            _ => print_expr(&self.program)
+        }
+    }
+
+    /// Source code for an expression that applies the program to its arguments.
+    pub fn apply_source(&self) -> String {
+        let args: Vec<String> = self.args.iter().map(print_expr).collect();
+        format!("({})({})", self.program_source(), args.join(","))
+    }
+}
+
+/// Generates random test cases from a seed.
+pub struct ProgramGenerator {
+    rng: Random,
+    conf: GenConf,
+    next_var: usize,
+}
+
+impl ProgramGenerator {
+    pub fn new(seed: u64, conf: GenConf) -> ProgramGenerator {
+        ProgramGenerator { rng: Random::new(seed), conf: conf, next_var: 0 }
+    }
+
+    /// Generate a new function with random parameters and body, plus random arguments for it.
+    pub fn test_case(&mut self) -> TestCase {
+        self.next_var = 0;
+        let num_params = self.rng.below(self.conf.max_params + 1);
+        let mut params = Vec::with_capacity(num_params);
+        for i in 0..num_params {
+            let ty = self.random_type(2);
+            params.push(TypedParameter { name: symbol(&format!("p{}", i)), ty: ty });
+        }
+        let args = params.iter().map(|p| self.literal(&p.ty)).collect();
+
+        let result_ty = self.random_type(2);
+        let mut env: Vec<(Symbol, Type)> = params.iter().map(|p| (p.name.clone(), p.ty.clone()))
+            .collect();
+        let max_depth = self.conf.max_depth;
+        let body = self.expr(&result_ty, max_depth, &mut env);
+        let fn_ty = Function(params.iter().map(|p| p.ty.clone()).collect(), Box::new(result_ty));
+        TestCase { program: typed(fn_ty, Lambda(params, Box::new(body))), args: args }
+    }
+
+    /// Pick a random type, nesting structs and vectors at most `depth` levels deep.
+    fn random_type(&mut self, depth: usize) -> Type {
+        let mut choices = vec![0, 1, 2];
+        if self.conf.floats {
+            choices.extend(&[3, 4]);
+        }
+        if depth > 0 && self.conf.structs {
+            choices.push(5);
+        }
+        if depth > 0 && self.conf.vectors {
+            choices.push(6);
+        }
+        match *self.rng.choose(&choices) {
+            0 => Scalar(Bool),
+            1 => Scalar(I32),
+            2 => Scalar(I64),
+            3 => Scalar(F32),
+            4 => Scalar(F64),
+            5 => {
+                let len = 1 + self.rng.below(3);
+                Struct((0..len).map(|_| self.random_type(depth - 1)).collect())
+            }
+            _ => Vector(Box::new(self.random_type(depth - 1))),
+        }
+    }
+
+    /// Pick a random scalar type that supports arithmetic.
+    fn numeric_type(&mut self) -> Type {
+        let kinds: &[ScalarKind] = if self.conf.floats {
+            &[I32, I64, F32, F64]
+        } else {
+            &[I32, I64]
+        };
+        Scalar(*self.rng.choose(kinds))
+    }
+
+    /// Generate a literal value of the given type.
+    fn literal(&mut self, ty: &Type) -> TypedExpr {
+        let kind = match *ty {
+            Scalar(Bool) => BoolLiteral(self.rng.chance(50)),
+            Scalar(I32) => I32Literal(self.rng.below(100) as i32),
+            Scalar(I64) => I64Literal(self.rng.below(100) as i64),
+            // Quarters are exactly representable, so literals print and parse back unchanged.
+            Scalar(F32) => F32Literal(self.rng.below(400) as f32 / 4.0),
+            Scalar(F64) => F64Literal(self.rng.below(400) as f64 / 4.0),
+            Struct(ref fields) => MakeStruct(fields.iter().map(|f| self.literal(f)).collect()),
+            Vector(ref elem) => {
+                // Empty vectors are avoided because their element type cannot be inferred.
+                let len = 1 + self.rng.below(4);
+                MakeVector((0..len).map(|_| self.literal(elem)).collect())
+            }
+            _ => panic!("Cannot generate a literal of type {}", print_type(ty))
+        };
+        typed(ty.clone(), kind)
+    }
+
+    /// Generate an expression of the given type using the variables in `env`.
+    fn expr(&mut self, ty: &Type, depth: usize, env: &mut Vec<(Symbol, Type)>) -> TypedExpr {
+        let vars: Vec<Symbol> = env.iter().filter(|v| v.1 == *ty).map(|v| v.0.clone()).collect();
+        if depth == 0 || self.rng.chance(20) {
+            if !vars.is_empty() && self.rng.chance(70) {
+                return typed(ty.clone(), Ident(self.rng.choose(&vars).clone()));
+            }
+            return self.literal(ty);
+        }
+
+        // Constructs that can produce any type
+        let mut choices = vec![0];
+        if self.conf.lets {
+            choices.push(1);
+        }
+        if self.conf.ifs {
+            choices.push(2);
+        }
+        if self.conf.structs {
+            choices.push(3);
+        }
+        // Constructs for specific types
+        match *ty {
+            Scalar(Bool) => choices.extend(&[10, 10]),
+            Scalar(_) => choices.extend(&[11, 11]),
+            Struct(_) => choices.push(12),
+            Vector(_) => choices.extend(&[13, 13]),
+            _ => ()
+        }
+        if *ty == Scalar(Bool) && self.conf.extended_ops {
+            choices.push(14);
+        }
+
+        let depth = depth - 1;
+        match *self.rng.choose(&choices) {
+            1 => {
+                let value_ty = self.random_type(1);
+                let value = self.expr(&value_ty, depth, env);
+                let name = self.fresh_symbol();
+                env.push((name.clone(), value_ty));
+                let body = self.expr(ty, depth, env);
+                env.pop();
+                typed(ty.clone(), Let(name, Box::new(value), Box::new(body)))
+            }
+            2 => {
+                let cond = self.expr(&Scalar(Bool), depth, env);
+                let on_true = self.expr(ty, depth, env);
+                let on_false = self.expr(ty, depth, env);
+                typed(ty.clone(), If(Box::new(cond), Box::new(on_true), Box::new(on_false)))
+            }
+            3 => {
+                // Build a struct with a field of the right type and take that field
+                let mut fields: Vec<Type> = (0..self.rng.below(3)).map(|_| self.random_type(0))
+                    .collect();
+                let index = self.rng.below(fields.len() + 1);
+                fields.insert(index, ty.clone());
+                let elems = fields.iter().map(|f| self.expr(f, depth, env)).collect();
+                let st = typed(Struct(fields), MakeStruct(elems));
+                typed(ty.clone(), GetField(Box::new(st), index as u32))
+            }
+            10 => {
+                let operand_ty = self.numeric_type();
+                let op = *self.rng.choose(&[Equal, NotEqual, LessThan, LessThanOrEqual,
+                    GreaterThan, GreaterThanOrEqual]);
+                let left = self.expr(&operand_ty, depth, env);
+                let right = self.expr(&operand_ty, depth, env);
+                typed(ty.clone(), BinOp(op, Box::new(left), Box::new(right)))
+            }
+            11 => self.arithmetic(ty, depth, env),
+            12 => {
+                let fields = match *ty {
+                    Struct(ref fields) => fields.clone(),
+                    _ => unreachable!()
+                };
+                let elems = fields.iter().map(|f| self.expr(f, depth, env)).collect();
+                typed(ty.clone(), MakeStruct(elems))
+            }
+            13 => self.map_loop(ty, depth, env),
+            14 => {
+                let op = *self.rng.choose(&[LogicalAnd, LogicalOr, BitwiseAnd, BitwiseOr, Xor]);
+                let left = self.expr(ty, depth, env);
+                let right = self.expr(ty, depth, env);
+                typed(ty.clone(), BinOp(op, Box::new(left), Box::new(right)))
+            }
+            _ => self.expr(ty, 0, env),
+        }
+    }
+
+    /// Generate an arithmetic expression of a non-boolean scalar type.
+    fn arithmetic(&mut self, ty: &Type, depth: usize, env: &mut Vec<(Symbol, Type)>) -> TypedExpr {
+        let is_int = *ty == Scalar(I32) || *ty == Scalar(I64);
+        let mut ops = vec![Add, Subtract, Multiply];
+        if is_int {
+            ops.push(Divide);
+            if self.conf.extended_ops {
+                ops.push(Modulo);
+            }
+        }
+        let op = *self.rng.choose(&ops);
+        let left = self.expr(ty, depth, env);
+        let right = if op == Divide || op == Modulo {
+            // Divide only by nonzero constants, since division by zero is undefined
+            let divisor = 1 + self.rng.below(9);
+            let kind = if *ty == Scalar(I32) {
+                I32Literal(divisor as i32)
+            } else {
+                I64Literal(divisor as i64)
+            };
+            typed(ty.clone(), kind)
+        } else {
+            self.expr(ty, depth, env)
+        };
+        typed(ty.clone(), BinOp(op, Box::new(left), Box::new(right)))
+    }
+
+    /// Generate a For loop that maps a function over another vector, with an appender.
+    fn map_loop(&mut self, ty: &Type, depth: usize, env: &mut Vec<(Symbol, Type)>) -> TypedExpr {
+        let elem_ty = match *ty { Vector(ref elem) => (**elem).clone(), _ => unreachable!() };
+        let input_elem_ty = self.random_type(0);
+        let data = self.expr(&Vector(Box::new(input_elem_ty.clone())), depth, env);
+
+        let bldr_ty = Builder(Appender(Box::new(elem_ty.clone())));
+        let b = self.fresh_symbol();
+        let x = self.fresh_symbol();
+        env.push((x.clone(), input_elem_ty.clone()));
+        let value = self.expr(&elem_ty, depth, env);
+        env.pop();
+        let merge = typed(bldr_ty.clone(), Merge(
+            Box::new(typed(bldr_ty.clone(), Ident(b.clone()))), Box::new(value)));
+        let params = vec![
+            TypedParameter { name: b, ty: bldr_ty.clone() },
+            TypedParameter { name: x, ty: input_elem_ty.clone() },
+        ];
+        let func_ty = Function(vec![bldr_ty.clone(), input_elem_ty], Box::new(bldr_ty.clone()));
+        let func = typed(func_ty, Lambda(params, Box::new(merge)));
+        let init = typed(bldr_ty.clone(), NewBuilder(None));
+        let for_expr = typed(bldr_ty, For(Box::new(data), Box::new(init), Box::new(func)));
+        typed(ty.clone(), Res(Box::new(for_expr)))
+    }
+
+    fn fresh_symbol(&mut self) -> Symbol {
+        self.next_var += 1;
+        symbol(&format!("v{}", self.next_var))
+    }
+}
+
+fn typed(ty: Type, kind: ExprKind<Type>) -> TypedExpr {
+    TypedExpr { ty: ty, kind: kind }
+}
+
+fn symbol(name: &str) -> Symbol {
+    Symbol::new(name, 0)
+}
+
+#[test]
+fn random_numbers() {
+    let mut a = Random::new(42);
+    let mut b = Random::new(42);
+    let mut c = Random::new(43);
+    let xs: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+    assert_eq!(xs, (0..10).map(|_| b.next_u64()).collect::<Vec<_>>());
+    assert!(xs != (0..10).map(|_| c.next_u64()).collect::<Vec<_>>());
+    assert!((0..1000).all(|_| a.below(7) < 7));
+}
+
+#[test]
+fn generated_programs_type_check() {
+    for seed in 0..200 {
+        let conf = if seed % 2 == 0 { GenConf::new() } else { GenConf::scalar() };
+        let case = ProgramGenerator::new(seed, conf).test_case();
+
+        // Printing and re-parsing the program should infer exactly the generated types
+        let mut expr = parse_expr(&case.program_source()).unwrap();
+        infer_types(&mut expr).unwrap();
+        assert_eq!(expr.to_typed().unwrap(), case.program, "seed {}", seed);
+
+        let mut apply = parse_expr(&case.apply_source()).unwrap();
+        infer_types(&mut apply).unwrap();
+        match case.program.ty {
+            Function(_, ref result) => assert_eq!(apply.to_typed().unwrap().ty, **result),
+            _ => panic!("Program is not a function")
+        }
+    }
+}
+
+#[test]
+fn scalar_programs_generate_code() {
+    for seed in 0..50 {
+        let case = ProgramGenerator::new(seed, GenConf::scalar()).test_case();
+        if let Lambda(ref params, ref body) = case.program.kind {
+            let mut gen = LlvmGenerator::new();
+            gen.add_function_on_pointers("run", params, body).unwrap();
+        }
+    }
+}
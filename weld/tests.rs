@@ -1,8 +1,21 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
 use super::ast::{Expr, ExprKind, Symbol};
+use super::ast::ScalarKind::{Decimal, F64, I32, I64};
+use super::ast::Type;
+use super::conf::{CompileConf, WeldConf};
+use super::error::{ErrorKind, WeldResult};
+use super::llvm::generate_code;
 use super::partial_types::PartialType::Unknown;
-use super::parser::parse_expr;
+use super::parser::{parse_expr, parse_program, MAX_NESTING_DEPTH};
 use super::pretty_print::*;
+use super::testgen::{GenConf, ProgramGenerator};
 use super::type_inference::*;
+use super::util::*;
+use super::validate;
 
 #[test]
 fn parse_and_print_literal_expressions() {
@@ -80,7 +93,7 @@ fn parse_and_print_typed_expressions() {
     assert_eq!(print_typed_expr(&e).as_str(), "a:?");
 
     let e = Expr {
-        kind: ExprKind::Ident(Symbol{name: "a".to_string(), id: 1}),
+        kind: ExprKind::Ident(Symbol::new("a", 1)),
         ty: Unknown
     };
     assert_eq!(print_typed_expr(&e).as_str(), "a#1:?");
@@ -119,3 +132,266 @@ fn parse_and_print_typed_expressions() {
     assert_eq!(print_typed_expr(&e).as_str(),
         "for([1],appender[i32],|b:appender[i32],x:i32|merge(b:appender[i32],x:i32))");
 }
+
+#[test]
+fn validate_programs() {
+    let program = parse_program("|x:i32, y:i64| y + 1L").unwrap();
+    assert_eq!(validate(&program).unwrap(), Type::Scalar(I64));
+
+    let program = parse_program("|v:vec[f64]| map(v, |x| x * 2.0)").unwrap();
+    assert_eq!(validate(&program).unwrap(), Type::Vector(Box::new(Type::Scalar(F64))));
+
+    let program = parse_program("let a = 1; {a, a + 1}").unwrap();
+    assert_eq!(validate(&program).unwrap(),
+        Type::Struct(vec![Type::Scalar(I32), Type::Scalar(I32)]));
+
+    let program = parse_program("|x:i32| x + 1.0").unwrap();
+    assert_eq!(validate(&program).unwrap_err().kind(), ErrorKind::Type);
+
+    let program = parse_program("macro m(a) = a; |x:i32| m(x, x)").unwrap();
+    assert_eq!(validate(&program).unwrap_err().kind(), ErrorKind::Macro);
+}
+
+#[test]
+fn builtin_names_are_not_reserved() {
+    // Lets, parameters and macros can use a builtin's name, and calls then refer to them
+    let programs = ["let dot = 1; dot", "|rand: i32| rand + 1", "macro gemm(x) = x; gemm(1)",
+        "let to_micros = (|t: i32| t); to_micros(1)", "(|extract_year: i32| extract_year)(1)"];
+    for code in programs.iter() {
+        let program = parse_program(code).unwrap();
+        assert_eq!(validate(&program).unwrap(), Type::Scalar(I32), "{}", code);
+    }
+
+    // Unbound names still call the builtin, including in macros that define a local of that name
+    let program = parse_program("|v: vec[f64]| dot(v, v)").unwrap();
+    assert_eq!(validate(&program).unwrap(), Type::Scalar(F64));
+    let program = parse_program("macro m(x) = let rand = x; rand; |v: vec[f64]| m(dot(v, v))")
+        .unwrap();
+    assert_eq!(validate(&program).unwrap(), Type::Scalar(F64));
+}
+
+#[test]
+fn type_names_are_not_reserved() {
+    // Names of types and builders are only keywords where a type or new builder is expected
+    let programs = [("|timestamp: i64| timestamp + 1L", Type::Scalar(I64)),
+        ("|x: timestamp| let timestamp = x; to_micros(timestamp)", Type::Scalar(I64)),
+        ("|timestamp: i64, decimal: i32| timestamp + 1L", Type::Scalar(I64)),
+        ("let decimal = 1; decimal + 1", Type::Scalar(I32)),
+        ("|x: decimal[5,2]| decimal[7,2](x)", Type::Scalar(Decimal(7, 2))),
+        ("let statsmerger = 1; statsmerger + 1", Type::Scalar(I32)),
+        ("|x: f64| result(merge(statsmerger[f64], x)).$0", Type::Scalar(I64)),
+        ("|x: i32| let histogram = x; histogram", Type::Scalar(I32)),
+        ("|x: f64| result(merge(histogram[f64](0.0, 1.0, 4L), x))",
+            Type::Vector(Box::new(Type::Scalar(I64)))),
+        ("let mutvec = 2; mutvec * mutvec", Type::Scalar(I32))];
+    for &(code, ref ty) in programs.iter() {
+        let program = parse_program(code).unwrap();
+        assert_eq!(&validate(&program).unwrap(), ty, "{}", code);
+    }
+}
+
+#[test]
+fn deeply_nested_programs() {
+    // Long chains of lets and binary operators, as machine-generated programs often contain
+    let depth = 10000;
+    let mut lets = "|x:i32| ".to_string();
+    let mut sum = "|x:i32| x".to_string();
+    for i in 0..depth {
+        lets.push_str(&format!("let a{} = x + {}; ", i, i));
+        sum.push_str(&format!(" + {}", i));
+    }
+    lets.push_str("a0");
+    let conf = CompileConf::parse(&WeldConf::new()).unwrap();
+    for code in &[lets, sum] {
+        let program = parse_program(code).unwrap();
+        assert_eq!(validate(&program).unwrap(), Type::Scalar(I32));
+        generate_code(&program, &conf).unwrap();
+    }
+
+    let mut code = "|x:i32| x".to_string();
+    for i in 0..MAX_EXPR_DEPTH {
+        code.push_str(&format!(" + {}", i));
+    }
+    let program = parse_program(&code).unwrap();
+    let err = generate_code(&program, &conf).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::Parse);
+    assert_eq!(err.to_string(), format!("Program is nested too deeply: its expression tree has \
+        depth {}, but at most {} is supported", MAX_EXPR_DEPTH + 2, MAX_EXPR_DEPTH));
+    assert_eq!(validate(&program).unwrap_err().kind(), ErrorKind::Parse);
+
+    // Explicit nesting is limited in the parser
+    let nested = |n| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+    parse_expr(&nested(MAX_NESTING_DEPTH)).unwrap();
+    let err = parse_expr(&nested(MAX_NESTING_DEPTH + 1)).unwrap_err();
+    assert_eq!(err.to_string(), format!("Expression is nested more than {} levels deep at 1:{}",
+        MAX_NESTING_DEPTH, MAX_NESTING_DEPTH + 2));
+}
+
+#[test]
+fn compile_stack_threads() {
+    // Only expressions too deep for the caller's stack get a thread of their own, and only once
+    let on_compile_thread = || Ok(::std::thread::current().name() == Some("weld-compile"));
+    assert!(!with_stack_for_depth(CALLER_STACK_DEPTH, &on_compile_thread).unwrap());
+    assert!(with_stack_for_depth(CALLER_STACK_DEPTH + 1, &on_compile_thread).unwrap());
+    assert!(with_compile_stack(|| {
+        let id = ::std::thread::current().id();
+        with_stack_for_depth(MAX_EXPR_DEPTH, || Ok(::std::thread::current().id() == id))
+    }).unwrap());
+}
+
+#[test]
+fn format_programs() {
+    let tests = vec![
+        ("|x:i32,y:i32|x+y*2", "|x: i32, y: i32| x + y * 2\n"),
+        ("(1+2)*3-(4-5)", "(1 + 2) * 3 - (4 - 5)\n"),
+        ("(a<b)==(c<d)", "a < b == c < d\n"),
+        ("(a==b)==c", "(a == b) == c\n"),
+        ("(a+b):i64", "(a + b): i64\n"),
+        ("let a:vec[i32]=[1,2];a.$0", "let a: vec[i32] = [1, 2];\na.$0\n"),
+        ("let f = (|x| x); (f)(1)", "let f = (|x| x);\nf(1)\n"),
+        ("(|x| x+1)(2)", "(|x| x + 1)(2)\n"),
+        ("|v:vec[i32]| let a = 1; let b = a; result(for(v, appender, |b,x| merge(b,x+a)))",
+            "|v: vec[i32]|\n  let a = 1;\n  let b = a;\n  \
+            result(for(v, appender, |b, x| merge(b, x + a)))\n"),
+        ("macro twice(x)=x*2;macro id(x)=x;||twice(id(1.5f))",
+            "macro twice(x) = x * 2;\nmacro id(x) = x;\n\n|| twice(id(1.5F))\n"),
+        ("mutvec[i64]({1L,true})", "mutvec[i64]({1L, true})\n"),
+        ("histogram(0,n,4L)", "histogram(0, n, 4L)\n"),
+        ("|d:decimal[10,2]|i64(d*d)", "|d: decimal[10,2]| i64(d * d)\n"),
+        ("if(a, |x| x, |x| let y = x; y)", "if(a, |x| x, |x| let y = x; y)\n"),
+    ];
+    for test in tests {
+        let program = parse_program(test.0).unwrap();
+        let formatted = format_program(&program);
+        assert_eq!(formatted, test.1);
+        // Formatting is idempotent and keeps the program the same
+        let reparsed = parse_program(&formatted).unwrap();
+        assert_eq!(reparsed, program);
+        assert_eq!(format_program(&reparsed), formatted);
+    }
+
+    // Generated programs survive a round trip too
+    for seed in 0..100 {
+        let case = ProgramGenerator::new(seed, GenConf::new()).test_case();
+        let program = parse_program(&case.program_source()).unwrap();
+        assert_eq!(parse_program(&format_program(&program)).unwrap(), program);
+    }
+}
+
+#[test]
+fn dot_graphs() {
+    let mut e = parse_expr("let a = 5; a + 1").unwrap();
+    infer_types(&mut e).unwrap();
+    assert_eq!(to_dot(&e), "\
+digraph weld {
+  ordering=out;
+  node [shape=box, fontname=\"monospace\"];
+  n0 [label=\"Let a\\ni32\"];
+  n1 [label=\"5\\ni32\"];
+  n0 -> n1;
+  n2 [label=\"BinOp +\\ni32\"];
+  n3 [label=\"Ident a\\ni32\"];
+  n2 -> n3;
+  n4 [label=\"1\\ni32\"];
+  n2 -> n4;
+  n0 -> n2;
+}
+");
+
+    let e = parse_expr("for([1],appender[?],|b,x|merge(b,x))").unwrap();
+    let dot = to_dot_highlighted(&e);
+    assert!(dot.contains("n0 [label=\"For\\n?\", style=filled, fillcolor=orange];"));
+    assert!(dot.contains(
+        "[label=\"NewBuilder\\nappender[?]\", style=filled, fillcolor=lightblue];"));
+    assert!(dot.contains("[label=\"Lambda |b:?,x:?|\\n?\"];"));
+    assert!(!to_dot(&e).contains("fillcolor"));
+}
+
+/// Directory holding the codegen snapshot corpus: each `name.weld` program is compiled and its
+/// generated LLVM body compared against `name.ll`.
+const GOLDEN_DIR: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/weld/resources/golden");
+
+/// Set this environment variable to rewrite the `.ll` snapshots instead of checking them.
+const UPDATE_GOLDEN_VAR: &'static str = "WELD_UPDATE_GOLDEN";
+
+/// Generate LLVM code for a program, leaving out the fixed prelude that every module shares.
+fn golden_code(code: &str) -> WeldResult<String> {
+    let program = try!(parse_program(code));
+    let conf = try!(CompileConf::parse(&WeldConf::new()));
+    let llvm_code = try!(generate_code(&program, &conf)).llvm_code;
+    Ok(llvm_code.replacen(include_str!("resources/prelude.ll"), "", 1))
+}
+
+/// Show the lines that differ between two texts, using a longest common subsequence so that
+/// an inserted line does not make everything after it look changed.
+fn diff_lines(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    // lcs[i][j] is the LCS length of old[i..] and new[j..]
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut res = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            res.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        } else {
+            res.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        }
+    }
+    res
+}
+
+#[test]
+fn codegen_snapshots() {
+    let update = env::var(UPDATE_GOLDEN_VAR).is_ok();
+    let mut paths: Vec<_> = fs::read_dir(GOLDEN_DIR).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "weld"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let mut failures = String::new();
+    for path in &paths {
+        let mut code = String::new();
+        File::open(path).unwrap().read_to_string(&mut code).unwrap();
+        let actual = golden_code(&code)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e.format_with_source(&code)));
+        let golden_path = path.with_extension("ll");
+        if update {
+            File::create(&golden_path).unwrap().write_all(actual.as_bytes()).unwrap();
+            continue;
+        }
+        let mut expected = String::new();
+        if let Ok(mut file) = File::open(&golden_path) {
+            file.read_to_string(&mut expected).unwrap();
+        }
+        if expected != actual {
+            let name = golden_path.file_name().unwrap().to_string_lossy().into_owned();
+            failures.push_str(&format!("--- {}\n{}", name, diff_lines(&expected, &actual)));
+        }
+    }
+    assert!(failures.is_empty(), "Generated code does not match snapshots in {} (rerun with {}=1 \
+        to update them):\n{}", Path::new(GOLDEN_DIR).display(), UPDATE_GOLDEN_VAR, failures);
+}
+
+#[test]
+fn snapshot_diffs() {
+    assert_eq!(diff_lines("a\nb\nc\n", "a\nb\nc\n"), "");
+    assert_eq!(diff_lines("a\nb\nc\n", "a\nx\nb\nc\n"), "+x\n");
+    assert_eq!(diff_lines("a\nb\nc\n", "a\nd\n"), "-b\n-c\n+d\n");
+}
@@ -0,0 +1,128 @@
+//! Calendar arithmetic on timestamps for the interpreters.
+//!
+//! Timestamps are i64 counts of microseconds since 1970-01-01 00:00:00 UTC, and dates are in the
+//! proleptic Gregorian calendar. Days are split into years, months and days with Howard
+//! Hinnant's `civil_from_days` and `days_from_civil` algorithms, which need no tables or loops.
+//! Generated code calls the `@timestamp.*` functions in the prelude, which implement the same
+//! algorithms in LLVM IR and should be kept in sync with this module.
+
+use super::ast::TimeUnit;
+use super::error::*;
+
+pub const MICROS_PER_HOUR: i64 = 3_600_000_000;
+pub const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+/// Divide, rounding towards negative infinity, for a positive divisor.
+fn floor_div(a: i64, b: i64) -> i64 {
+    a.div_euclid(b)
+}
+
+/// The remainder of `floor_div`, which is never negative.
+fn floor_mod(a: i64, b: i64) -> i64 {
+    a.rem_euclid(b)
+}
+
+/// The (year, month, day) of a number of days since the epoch.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = floor_div(z, 146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The number of days since the epoch of a (year, month, day).
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = floor_div(year, 400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The year, month (from 1), day of the month (from 1) or hour (from 0) of a timestamp.
+pub fn extract(unit: TimeUnit, micros: i64) -> i32 {
+    let (year, month, day) = civil_from_days(floor_div(micros, MICROS_PER_DAY));
+    let value = match unit {
+        TimeUnit::Year => year,
+        TimeUnit::Month => month,
+        TimeUnit::Day => day,
+        TimeUnit::Hour => floor_mod(micros, MICROS_PER_DAY) / MICROS_PER_HOUR,
+    };
+    value as i32
+}
+
+/// Round a timestamp down to the start of its year, month, day or hour. Fails if that is before
+/// the earliest timestamp, which only happens for timestamps near i64::MIN.
+pub fn trunc(unit: TimeUnit, micros: i64) -> WeldResult<i64> {
+    let (year, month, _) = civil_from_days(floor_div(micros, MICROS_PER_DAY));
+    let start = match unit {
+        TimeUnit::Year => days_from_civil(year, 1, 1).checked_mul(MICROS_PER_DAY),
+        TimeUnit::Month => days_from_civil(year, month, 1).checked_mul(MICROS_PER_DAY),
+        TimeUnit::Day => micros.checked_sub(floor_mod(micros, MICROS_PER_DAY)),
+        TimeUnit::Hour => micros.checked_sub(floor_mod(micros, MICROS_PER_HOUR)),
+    };
+    match start {
+        Some(start) => Ok(start),
+        None => weld_err!(Runtime, "Cannot truncate timestamp {} to its {:?}: the result is out \
+            of range", micros, unit)
+    }
+}
+
+#[test]
+fn calendar() {
+    assert_eq!(civil_from_days(0), (1970, 1, 1));
+    assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    assert_eq!(civil_from_days(11016), (2000, 2, 29));
+    assert_eq!(civil_from_days(-719468), (0, 3, 1));
+    for days in -800000..800000 {
+        let (y, m, d) = civil_from_days(days);
+        assert_eq!(days_from_civil(y, m, d), days);
+    }
+
+    // 2017-03-14 15:09:26.535897 UTC and a time before the epoch, 1969-07-20 20:17:40 UTC
+    let t = 1489504166535897;
+    let units = [TimeUnit::Year, TimeUnit::Month, TimeUnit::Day, TimeUnit::Hour];
+    let fields: Vec<i32> = units.iter().map(|u| extract(*u, t)).collect();
+    assert_eq!(fields, vec![2017, 3, 14, 15]);
+    let t = -14182940000000;
+    let fields: Vec<i32> = units.iter().map(|u| extract(*u, t)).collect();
+    assert_eq!(fields, vec![1969, 7, 20, 20]);
+    assert_eq!(trunc(TimeUnit::Year, t).unwrap(), -31536000000000);
+    assert_eq!(trunc(TimeUnit::Month, t).unwrap(), -15897600000000);
+    assert_eq!(trunc(TimeUnit::Day, t).unwrap(), -14256000000000);
+    assert_eq!(trunc(TimeUnit::Hour, t).unwrap(), -14184000000000);
+}
+
+#[test]
+fn extreme_timestamps() {
+    // i64::MIN is -290308-12-21 19:59:05.224192 UTC and i64::MAX is 294247-01-10 04:00:54.775807
+    let units = [TimeUnit::Year, TimeUnit::Month, TimeUnit::Day, TimeUnit::Hour];
+    let fields: Vec<i32> = units.iter().map(|u| extract(*u, i64::min_value())).collect();
+    assert_eq!(fields, vec![-290308, 12, 21, 19]);
+    let fields: Vec<i32> = units.iter().map(|u| extract(*u, i64::max_value())).collect();
+    assert_eq!(fields, vec![294247, 1, 10, 4]);
+
+    // The start of the earliest timestamp's year, month, day and hour cannot be represented
+    let t = i64::min_value();
+    for unit in units.iter() {
+        assert_eq!(trunc(*unit, t).unwrap_err().kind(), ErrorKind::Runtime);
+    }
+    let t = i64::min_value() + MICROS_PER_DAY;
+    assert_eq!(trunc(TimeUnit::Month, t).unwrap_err().kind(), ErrorKind::Runtime);
+    assert_eq!(trunc(TimeUnit::Day, t).unwrap(), t - 71945224192);
+    assert_eq!(trunc(TimeUnit::Hour, t).unwrap(), t - 3545224192);
+
+    let t = i64::max_value();
+    assert_eq!(trunc(TimeUnit::Year, t).unwrap(), days_from_civil(294247, 1, 1) * MICROS_PER_DAY);
+    assert_eq!(trunc(TimeUnit::Month, t).unwrap(), days_from_civil(294247, 1, 1) * MICROS_PER_DAY);
+    assert_eq!(trunc(TimeUnit::Day, t).unwrap(), t - 14454775807);
+    assert_eq!(trunc(TimeUnit::Hour, t).unwrap(), t - 54775807);
+}
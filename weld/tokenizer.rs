@@ -5,23 +5,29 @@
 //! individual tokens (+, -, $, if, |, etc). The regular expresions for various token types are
 //! matched greedily in an order that ensures the "largest" one wins first; for example, the
 //! string '1e-5' is parsed as a f64 literal, not as ('1e', '-', '5').
+//!
+//! Tokens own no heap data: identifiers are interned (so each distinct name is allocated once
+//! per process, however often it appears) and literals are parsed into their values, which makes
+//! `Token` a plain `Copy` value. Tokenizing fails if a new identifier would take the interned
+//! names past `intern::MAX_INTERNED_BYTES`.
 
 use std::fmt;
 use std::str::FromStr;
 use std::vec::Vec;
 
-use regex::Regex;
+use regex::{FindMatches, Regex};
 
 use super::error::*;
+use super::intern::Name;
 
-#[derive(Clone,Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub enum Token {
     TI32Literal(i32),
     TI64Literal(i64),
     TF32Literal(f32),
     TF64Literal(f64),
     TBoolLiteral(bool),
-    TIdent(String),
+    TIdent(Name),
     TIf,
     TFor,
     TMerge,
@@ -66,123 +72,165 @@ pub enum Token {
     TEndOfInput
 }
 
-/// Break up a string into tokens.
-pub fn tokenize(input: &str) -> WeldResult<Vec<Token>> {
-    lazy_static! {
-        // Regular expression for splitting up tokens.
-        static ref TOKEN_RE: Regex = Regex::new(concat!(
-            r"[0-9]+\.[0-9]+([eE]-?[0-9]+)?[fF]?|[0-9]+[eE]-?[0-9]+[fF]?|",
-            r"[A-Za-z0-9$_]+|==|!=|>=|<=|&&|\|\||[-+/*%,=()[\]{}|&\.:;?&\|^<>]|\S+"
-        )).unwrap();
+lazy_static! {
+    // Regular expression for splitting up tokens.
+    static ref TOKEN_RE: Regex = Regex::new(concat!(
+        r"[0-9]+\.[0-9]+([eE]-?[0-9]+)?[fF]?|[0-9]+[eE]-?[0-9]+[fF]?|",
+        r"[A-Za-z0-9$_]+|==|!=|>=|<=|&&|\|\||[-+/*%,=()\[\]{}|&\.:;?&\|^<>]|\S+"
+    )).unwrap();
+
+    // Regular expressions for various types of tokens.
+    static ref KEYWORD_RE: Regex = Regex::new(concat!(
+        "^(if|for|merge|result|let|true|false|macro|i32|i64|f32|f64|bool|",
+        "vec|appender)$"
+    )).unwrap();
 
-        // Regular expressions for various types of tokens.
-        static ref KEYWORD_RE: Regex = Regex::new(
-            "if|for|merge|result|let|true|false|macro|i32|i64|f32|f64|bool|vec|appender").unwrap();
+    static ref IDENT_RE: Regex = Regex::new(r"^[A-Za-z$_][A-Za-z0-9$_]*$").unwrap();
 
-        static ref IDENT_RE: Regex = Regex::new(r"^[A-Za-z$_][A-Za-z0-9$_]*$").unwrap();
+    static ref I32_BASE_10_RE: Regex = Regex::new(r"^[0-9]+$").unwrap();
+    static ref I32_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+$").unwrap();
+    static ref I32_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+$").unwrap();
+
+    static ref I64_BASE_10_RE: Regex = Regex::new(r"^[0-9]+[lL]$").unwrap();
+    static ref I64_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+[lL]$").unwrap();
+    static ref I64_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+[lL]$").unwrap();
+
+    static ref F32_RE: Regex = Regex::new(
+        r"[0-9]+\.[0-9]+([eE]-?[0-9]+)?[fF]|[0-9]+([eE]-?[0-9]+)?[fF]").unwrap();
+
+    static ref F64_RE: Regex = Regex::new(
+        r"[0-9]+\.[0-9]+([eE]-?[0-9]+)?|[0-9]+[eE]-?[0-9]+").unwrap();
+}
 
-        static ref I32_BASE_10_RE: Regex = Regex::new(r"^[0-9]+$").unwrap();
-        static ref I32_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+$").unwrap();
-        static ref I32_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+$").unwrap();
+/// Break up a string into tokens.
+pub fn tokenize(input: &str) -> WeldResult<Vec<Token>> {
+    Tokenizer::new(input).map(|res| res.map(|(token, _)| token)).collect()
+}
 
-        static ref I64_BASE_10_RE: Regex = Regex::new(r"^[0-9]+[lL]$").unwrap();
-        static ref I64_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+[lL]$").unwrap();
-        static ref I64_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+[lL]$").unwrap();
+/// Break up a string into tokens, each paired with the span of input it came from. Errors are
+/// tagged with the span of the offending text.
+pub fn tokenize_with_spans(input: &str) -> WeldResult<Vec<(Token, Span)>> {
+    Tokenizer::new(input).collect()
+}
 
-        static ref F32_RE: Regex = Regex::new(
-            r"[0-9]+\.[0-9]+([eE]-?[0-9]+)?[fF]|[0-9]+([eE]-?[0-9]+)?[fF]").unwrap();
+/// An iterator over the tokens in a string and their spans, which produces tokens as they are
+/// requested rather than materializing them all up front. Like `tokenize`, the last token is
+/// TEndOfInput. If a token is invalid, the iterator returns an error and then ends.
+pub struct Tokenizer<'a> {
+    input: &'a str,
+    matches: FindMatches<'static, 'a>,
+    done: bool,
+}
 
-        static ref F64_RE: Regex = Regex::new(
-            r"[0-9]+\.[0-9]+([eE]-?[0-9]+)?|[0-9]+[eE]-?[0-9]+").unwrap();
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: &'a str) -> Tokenizer<'a> {
+        Tokenizer { input: input, matches: TOKEN_RE.find_iter(input), done: false }
     }
+}
 
-    use self::Token::*;
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = WeldResult<(Token, Span)>;
 
-    let mut tokens: Vec<Token> = Vec::new();
-
-    for cap in TOKEN_RE.captures_iter(input) {
-        let text = cap.at(0).unwrap();
-        if KEYWORD_RE.is_match(text) {
-            tokens.push(match text {
-                "if" => TIf,
-                "let" => TLet,
-                "for" => TFor,
-                "merge" => TMerge,
-                "result" => TResult,
-                "macro" => TMacro,
-                "i32" => TI32,
-                "i64" => TI64,
-                "f32" => TF32,
-                "f64" => TF64,
-                "bool" => TBool,
-                "vec" => TVec,
-                "appender" => TAppender,
-                "true" => TBoolLiteral(true),
-                "false" => TBoolLiteral(false),
-                _ => return weld_err!("Invalid input token: {}", text)
-            });
-        } else if IDENT_RE.is_match(text) {
-            tokens.push(TIdent(text.to_string()));
-        } else if I32_BASE_10_RE.is_match(text) {
-            tokens.push(try!(parse_i32_literal(text, 10)))
-        } else if I32_BASE_2_RE.is_match(text) {
-            tokens.push(try!(parse_i32_literal(text, 2)))
-        } else if I32_BASE_16_RE.is_match(text) {
-            tokens.push(try!(parse_i32_literal(text, 16)))
-        } else if I64_BASE_10_RE.is_match(text) {
-            tokens.push(try!(parse_i64_literal(text, 10)))
-        } else if I64_BASE_2_RE.is_match(text) {
-            tokens.push(try!(parse_i64_literal(text, 2)))
-        } else if I64_BASE_16_RE.is_match(text) {
-            tokens.push(try!(parse_i64_literal(text, 16)))
-        } else if F32_RE.is_match(text) {
-            match f32::from_str(&text[..text.len()-1]) {
-                Ok(value) => tokens.push(Token::TF32Literal(value)),
-                Err(_) => return weld_err!("Invalid f32 literal: {}", text)
+    fn next(&mut self) -> Option<WeldResult<(Token, Span)>> {
+        if self.done {
+            return None;
+        }
+        match self.matches.next() {
+            Some((start, end)) => {
+                let span = Span { start: start, end: end };
+                let res = parse_token(&self.input[start..end]).map_err(|e| e.with_span(span));
+                self.done = res.is_err();
+                Some(res.map(|token| (token, span)))
             }
-        } else if F64_RE.is_match(text) {
-            match f64::from_str(text) {
-                Ok(value) => tokens.push(Token::TF64Literal(value)),
-                Err(_) => return weld_err!("Invalid f32 literal: {}", text)
+            None => {
+                self.done = true;
+                let end = self.input.len();
+                Some(Ok((Token::TEndOfInput, Span { start: end, end: end })))
             }
-        } else {
-            tokens.push(match text {
-                "+" => TPlus,
-                "-" => TMinus,
-                "*" => TTimes,
-                "/" => TDivide,
-                "%" => TModulo,
-                "(" => TOpenParen,
-                ")" => TCloseParen,
-                "[" => TOpenBracket,
-                "]" => TCloseBracket,
-                "{" => TOpenBrace,
-                "}" => TCloseBrace,
-                "|" => TBar,
-                "," => TComma,
-                "=" => TEqual,
-                "." => TDot,
-                ":" => TColon,
-                ";" => TSemicolon,
-                "?" => TQuestion,
-                "==" => TEqualEqual,
-                "!=" => TNotEqual,
-                "<" => TLessThan,
-                ">" => TGreaterThan,
-                "<=" => TLessThanOrEqual,
-                ">=" => TGreaterThanOrEqual,
-                "&&" => TLogicalAnd,
-                "||" => TLogicalOr,
-                "&" => TBitwiseAnd,
-                "^" => TXor,
-                _ => return weld_err!("Invalid input token: {}", text)
-            });
         }
     }
+}
 
-    tokens.push(TEndOfInput);
+/// Convert the text of a single token to a Token.
+fn parse_token(text: &str) -> WeldResult<Token> {
+    use self::Token::*;
 
-    return Ok(tokens);
+    if KEYWORD_RE.is_match(text) {
+        Ok(match text {
+            "if" => TIf,
+            "let" => TLet,
+            "for" => TFor,
+            "merge" => TMerge,
+            "result" => TResult,
+            "macro" => TMacro,
+            "i32" => TI32,
+            "i64" => TI64,
+            "f32" => TF32,
+            "f64" => TF64,
+            "bool" => TBool,
+            "vec" => TVec,
+            "appender" => TAppender,
+            "true" => TBoolLiteral(true),
+            "false" => TBoolLiteral(false),
+            _ => return weld_err!("Invalid input token: {}", text)
+        })
+    } else if IDENT_RE.is_match(text) {
+        Ok(TIdent(try!(Name::intern(text))))
+    } else if I32_BASE_10_RE.is_match(text) {
+        parse_i32_literal(text, 10)
+    } else if I32_BASE_2_RE.is_match(text) {
+        parse_i32_literal(text, 2)
+    } else if I32_BASE_16_RE.is_match(text) {
+        parse_i32_literal(text, 16)
+    } else if I64_BASE_10_RE.is_match(text) {
+        parse_i64_literal(text, 10)
+    } else if I64_BASE_2_RE.is_match(text) {
+        parse_i64_literal(text, 2)
+    } else if I64_BASE_16_RE.is_match(text) {
+        parse_i64_literal(text, 16)
+    } else if F32_RE.is_match(text) {
+        match f32::from_str(&text[..text.len()-1]) {
+            Ok(value) => Ok(TF32Literal(value)),
+            Err(_) => weld_err!("Invalid f32 literal: {}", text)
+        }
+    } else if F64_RE.is_match(text) {
+        match f64::from_str(text) {
+            Ok(value) => Ok(TF64Literal(value)),
+            Err(_) => weld_err!("Invalid f64 literal: {}", text)
+        }
+    } else {
+        Ok(match text {
+            "+" => TPlus,
+            "-" => TMinus,
+            "*" => TTimes,
+            "/" => TDivide,
+            "%" => TModulo,
+            "(" => TOpenParen,
+            ")" => TCloseParen,
+            "[" => TOpenBracket,
+            "]" => TCloseBracket,
+            "{" => TOpenBrace,
+            "}" => TCloseBrace,
+            "|" => TBar,
+            "," => TComma,
+            "=" => TEqual,
+            "." => TDot,
+            ":" => TColon,
+            ";" => TSemicolon,
+            "?" => TQuestion,
+            "==" => TEqualEqual,
+            "!=" => TNotEqual,
+            "<" => TLessThan,
+            ">" => TGreaterThan,
+            "<=" => TLessThanOrEqual,
+            ">=" => TGreaterThanOrEqual,
+            "&&" => TLogicalAnd,
+            "||" => TLogicalOr,
+            "&" => TBitwiseAnd,
+            "^" => TXor,
+            _ => return weld_err!("Invalid input token: {}", text)
+        })
+    }
 }
 
 impl fmt::Display for Token {
@@ -288,4 +336,40 @@ fn basic_tokenize() {
 
     assert_eq!(tokenize("1e-5f").unwrap(), vec![TF32Literal(1e-5f32), TEndOfInput]);
     assert_eq!(tokenize("1e-5").unwrap(), vec![TF64Literal(1e-5), TEndOfInput]);
+
+    // Identifiers that contain keywords
+    assert_eq!(tokenize("format iffy").unwrap(),
+        vec![TIdent("format".into()), TIdent("iffy".into()), TEndOfInput]);
+
+    // Builtin names are ordinary identifiers; the parser decides which calls are builtins
+    assert_eq!(tokenize("dot(a, dots)").unwrap(), vec![TIdent("dot".into()), TOpenParen,
+        TIdent("a".into()), TComma, TIdent("dots".into()), TCloseParen, TEndOfInput]);
+
+    // Repeated identifiers share one interned name
+    match (tokenize("abc + abc").unwrap()[0], tokenize("abc").unwrap()[0]) {
+        (TIdent(a), TIdent(b)) => assert_eq!(a.as_str().as_ptr(), b.as_str().as_ptr()),
+        _ => panic!("expected identifiers")
+    }
+}
+
+#[test]
+fn token_spans() {
+    use self::Token::*;
+
+    assert_eq!(tokenize_with_spans("a +\n 12").unwrap(), vec![
+        (TIdent("a".into()), Span { start: 0, end: 1 }),
+        (TPlus, Span { start: 2, end: 3 }),
+        (TI32Literal(12), Span { start: 5, end: 7 }),
+        (TEndOfInput, Span { start: 7, end: 7 }),
+    ]);
+
+    // The iterator produces tokens lazily and stops after the first error
+    let mut tokens = Tokenizer::new("x # y");
+    assert_eq!(tokens.next().unwrap().unwrap(), (TIdent("x".into()), Span { start: 0, end: 1 }));
+    let err = tokens.next().unwrap().unwrap_err();
+    assert_eq!(err.span(), Some(Span { start: 2, end: 3 }));
+    assert!(tokens.next().is_none());
+
+    assert_eq!(Tokenizer::new("").collect::<WeldResult<Vec<_>>>().unwrap(),
+        vec![(TEndOfInput, Span { start: 0, end: 0 })]);
 }
@@ -4,6 +4,14 @@ use super::ast::Expr;
 use super::ast::ExprKind::*;
 use super::error::*;
 
+/// Run the transform with the given name, as listed in `conf::TRANSFORM_NAMES`.
+pub fn apply_pass<T:Clone>(name: &str, expr: &mut Expr<T>) -> WeldResult<()> {
+    match name {
+        "inline-apply" => inline_apply(expr),
+        _ => weld_err!(Config, "Unknown optimization pass: {}", name)
+    }
+}
+
 /// Inlines Apply nodes whose argument is a Lambda expression. These often arise during macro
 /// expansion but it's simpler to inline them before doing type inference.
 /// Unlike many of the other transformations, we make this one independent of types so that
@@ -14,13 +22,32 @@ use super::error::*;
 ///   it to a temporary as would happen with function application.
 /// - Does not complete inlining if some of the functions take functions as arguments (in that
 ///   case, the expressions after inlining may lead to more inlining).
+/// - Leaves Apply nodes alone if an argument draws random numbers, since inlining could draw
+///   them a different number of times or in a different order.
 pub fn inline_apply<T:Clone>(expr: &mut Expr<T>) -> WeldResult<()> {
+    inline_apply_counted(expr).map(|_| ())
+}
+
+/// Same as `inline_apply`, but returns the number of Apply nodes that were inlined.
+pub fn inline_apply_counted<T:Clone>(expr: &mut Expr<T>) -> WeldResult<usize> {
+    Ok(inline_applies(expr).0)
+}
+
+/// Inline the Apply nodes in an expression, returning how many were inlined and whether the
+/// expression draws random numbers. Working out the latter on the way up, rather than checking
+/// each Apply's arguments separately, keeps deeply nested Apply nodes linear.
+fn inline_applies<T:Clone>(expr: &mut Expr<T>) -> (usize, bool) {
+    let mut count = 0;
+    let mut random = Vec::new();
     for child in expr.children_mut() {
-        try!(inline_apply(child));
+        let (child_count, child_random) = inline_applies(child);
+        count += child_count;
+        random.push(child_random);
     }
     let mut new_expr = None;
     if let Apply(ref func, ref args) = expr.kind {
-        if let Lambda(ref params, ref body) = func.kind {
+        // The function comes first among the children, followed by the arguments
+        if let (Lambda(ref params, ref body), false) = (&func.kind, random[1..].contains(&true)) {
             let mut new = *body.clone();
             for (param, arg) in params.iter().zip(args) {
                 new.substitute(&param.name, &arg);
@@ -28,8 +55,18 @@ pub fn inline_apply<T:Clone>(expr: &mut Expr<T>) -> WeldResult<()> {
             new_expr = Some(new);
         }
     }
+    let random = random.contains(&true) || builtin_is_random(expr);
     if let Some(new) = new_expr {
         *expr = new;
+        count += 1;
+    }
+    (count, random)
+}
+
+/// Whether an expression is itself a call to a builtin that draws random numbers.
+pub fn builtin_is_random<T:Clone>(expr: &Expr<T>) -> bool {
+    match expr.kind {
+        Builtin(kind, _) => kind.is_random(),
+        _ => false
     }
-    Ok(())
 }
@@ -1,22 +1,31 @@
 use std::collections::HashMap;
 
+use super::ast::{BinOpKind, BuiltinKind, MAX_DECIMAL64_PRECISION};
 use super::ast::ExprKind::*;
 use super::ast::ScalarKind::*;
 use super::ast::Symbol;
 use super::partial_types::PartialExpr;
 use super::partial_types::PartialType;
 use super::partial_types::PartialType::*;
+use super::partial_types::PartialBuilderKind;
 use super::partial_types::PartialBuilderKind::*;
 use super::error::*;
+use super::pretty_print::print_type;
 
 #[cfg(test)] use super::ast::BinOpKind::*;
 #[cfg(test)] use super::parser::*;
 #[cfg(test)] use super::partial_types::expr_box;
+#[cfg(test)] use super::pretty_print::print_typed_expr;
 
 type TypeMap = HashMap<Symbol, PartialType>;
 
 /// Infer the missing types of all expressions a tree, modifying it in place to set them.
+/// Errors are tagged as type errors unless they indicate an internal problem.
 pub fn infer_types(expr: &mut PartialExpr) -> WeldResult<()> {
+    infer_types_impl(expr).map_err(|e| e.with_kind(ErrorKind::Type))
+}
+
+fn infer_types_impl(expr: &mut PartialExpr) -> WeldResult<()> {
     // Note: we should also make sure that the types already set in expr are consistent; this will
     // be done by the first call to infer_up.
     loop {
@@ -106,6 +115,7 @@ fn infer_locally(expr: &mut PartialExpr, env: &mut TypeMap) -> WeldResult<bool>
             try!(push_type(&mut elem_type, &right.ty, "BinOp"));
             if !op.is_comparison() {
                 try!(push_type(&mut elem_type, &expr.ty, "BinOp"));
+                try!(check_arithmetic(op, &elem_type));
             }
             let mut changed = false;
             changed |= try!(push_type(&mut left.ty, &elem_type, "BinOp"));
@@ -155,7 +165,7 @@ fn infer_locally(expr: &mut PartialExpr, env: &mut TypeMap) -> WeldResult<bool>
                     changed |= try!(sync_types(elem_ty, &mut elem_expr.ty, "MakeStruct"));
                 }
             } else {
-                return weld_err!("Internal error: type of MakeStruct was not Struct");
+                return weld_err!(Internal, "Internal error: type of MakeStruct was not Struct");
             }
 
             Ok(changed)
@@ -171,7 +181,7 @@ fn infer_locally(expr: &mut PartialExpr, env: &mut TypeMap) -> WeldResult<bool>
             } else if param.ty == Unknown {
                 Ok(false)
             } else {
-                weld_err!("Internal error: GetField called on {:?}", param.ty)
+                weld_err!("GetField called on non-struct type {:?}", param.ty)
             }
         }
 
@@ -187,7 +197,7 @@ fn infer_locally(expr: &mut PartialExpr, env: &mut TypeMap) -> WeldResult<bool>
                     changed |= try!(sync_types(param_ty, &mut param_expr.ty, "Lambda parameter"));
                 }
             } else {
-                return weld_err!("Internal error: type of Lambda was not Function");
+                return weld_err!(Internal, "Internal error: type of Lambda was not Function");
             }
 
             Ok(changed)
@@ -197,11 +207,12 @@ fn infer_locally(expr: &mut PartialExpr, env: &mut TypeMap) -> WeldResult<bool>
             let mut changed = false;
             match builder.ty {
                 Builder(ref mut b) => {
-                    let mty = b.merge_type_mut();
-                    changed |= try!(sync_types(mty, &mut value.ty, "Merge"));
+                    let mut mty = b.merge_type();
+                    changed |= try!(sync_types(&mut mty, &mut value.ty, "Merge"));
+                    changed |= try!(push_merge_type(b, &mty, "Merge"));
                 }
                 Unknown => (),
-                _ => return weld_err!("Internal error: Merge called on non-builder")
+                _ => return weld_err!("Merge called on non-builder")
             }
             changed |= try!(sync_types(
                 &mut expr.ty, &mut builder.ty, "Merge"));
@@ -216,11 +227,13 @@ fn infer_locally(expr: &mut PartialExpr, env: &mut TypeMap) -> WeldResult<bool>
                     changed |= try!(push_type(&mut expr.ty, &rty, "Res"));
                 }
                 Unknown => (),
-                _ => return weld_err!("Internal error: Result called on non-builder")
+                _ => return weld_err!("Result called on non-builder")
             }
             Ok(changed)
         }
 
+        Builtin(kind, ref mut args) => infer_builtin(kind, args, &mut expr.ty),
+
         For(ref mut data, ref mut builder, ref mut func) => {
             let mut changed = false;
 
@@ -251,9 +264,43 @@ fn infer_locally(expr: &mut PartialExpr, env: &mut TypeMap) -> WeldResult<bool>
             Ok(changed)
         }
 
-        NewBuilder => {
-            match expr.ty {
-                Unknown | Builder(_) => Ok(false),
+        NewBuilder(ref mut arg) => {
+            match (&mut expr.ty, arg) {
+                (&mut Builder(MutVec(ref mut elem)), &mut Some(ref mut arg)) => {
+                    // The mutvec's element type must match the vector it takes ownership of.
+                    let mut vec_type = Vector(elem.clone());
+                    let mut changed = try!(sync_types(&mut vec_type, &mut arg.ty, "NewBuilder"));
+                    if let Vector(ref vec_elem) = vec_type {
+                        changed |= try!(push_type(elem, vec_elem, "NewBuilder"));
+                    }
+                    Ok(changed)
+                }
+                (&mut Builder(MutVec(_)), &mut None) =>
+                    weld_err!("mutvec requires an initial vector"),
+                (&mut Builder(StatsMerger(ref elem)), &mut None) => match **elem {
+                    Unknown | Scalar(I32) | Scalar(I64) | Scalar(F32) | Scalar(F64) |
+                        Scalar(Decimal(_, _)) => Ok(false),
+                    _ => weld_err!("statsmerger requires a numeric element type")
+                },
+                (&mut Builder(Histogram(ref mut elem)), &mut Some(ref mut arg)) => {
+                    // The arguments are {lo, hi, nbins}, with bounds of the element type.
+                    let mut args_type = Struct(vec![*elem.clone(), *elem.clone(), Scalar(I64)]);
+                    let mut changed = try!(sync_types(&mut args_type, &mut arg.ty, "histogram"));
+                    if let Struct(ref fields) = args_type {
+                        changed |= try!(push_type(elem, &fields[0], "histogram"));
+                        changed |= try!(push_type(elem, &fields[1], "histogram"));
+                    }
+                    match **elem {
+                        Unknown | Scalar(I32) | Scalar(I64) | Scalar(F32) | Scalar(F64) =>
+                            Ok(changed),
+                        _ => weld_err!("histogram requires a numeric element type")
+                    }
+                }
+                (&mut Builder(Histogram(_)), &mut None) =>
+                    weld_err!("histogram requires bounds and a number of bins"),
+                (&mut Builder(_), &mut Some(_)) =>
+                    weld_err!("Builder does not take an argument"),
+                (&mut Unknown, _) | (&mut Builder(_), &mut None) => Ok(false),
                 _ => weld_err!("Wrong type ascribed to NewBuilder")
             }
         }
@@ -283,7 +330,7 @@ fn infer_locally(expr: &mut PartialExpr, env: &mut TypeMap) -> WeldResult<bool>
                     }
                 }
 
-                _ => return weld_err!("Internal error: Apply was not called on a function")
+                _ => return weld_err!("Apply was not called on a function")
             }
 
             Ok(changed)
@@ -307,7 +354,107 @@ fn push_complete_type(dest: &mut PartialType, src: PartialType, context: &str)
             }
         }
 
-        _ => weld_err!("Internal error: push_complete_type not implemented for {:?}", src)
+        _ => weld_err!(Internal, "Internal error: push_complete_type not implemented for {:?}", src)
+    }
+}
+
+/// Infer the types of a builtin's arguments and result from each other.
+fn infer_builtin(kind: BuiltinKind, args: &mut Vec<PartialExpr>, ty: &mut PartialType)
+        -> WeldResult<bool> {
+    let num_args = match kind {
+        BuiltinKind::Dot => 2,
+        BuiltinKind::Gemm => 5,
+        BuiltinKind::Rand => 0,
+        BuiltinKind::RandSeeded => 1,
+        BuiltinKind::ToTimestamp | BuiltinKind::ToMicros => 1,
+        BuiltinKind::Extract(_) | BuiltinKind::Trunc(_) => 1,
+        BuiltinKind::AddInterval | BuiltinKind::IntervalBetween => 2,
+        BuiltinKind::Cast(_) => 1,
+    };
+    if args.len() != num_args {
+        return weld_err!("{} takes {} arguments but got {}", kind, num_args, args.len());
+    }
+
+    let mut changed = false;
+    match kind {
+        // Two vectors of the same float type, plus the dimensions for gemm
+        BuiltinKind::Dot | BuiltinKind::Gemm => {
+            let mut vec_type = match kind {
+                BuiltinKind::Dot => Vector(Box::new(ty.clone())),
+                _ => ty.clone(),
+            };
+            try!(push_type(&mut vec_type, &Vector(Box::new(Unknown)), "Builtin"));
+            for arg in args[0..2].iter_mut() {
+                changed |= try!(sync_types(&mut vec_type, &mut arg.ty, "Builtin"));
+            }
+            for arg in args[2..].iter_mut() {
+                changed |= try!(push_complete_type(&mut arg.ty, Scalar(I64), "Builtin"));
+            }
+            if let Vector(ref elem) = vec_type {
+                match **elem {
+                    Unknown | Scalar(F32) | Scalar(F64) => (),
+                    _ => return weld_err!("{} requires vectors of f32 or f64", kind)
+                }
+                changed |= match kind {
+                    BuiltinKind::Dot => try!(push_type(ty, elem, "Builtin")),
+                    _ => try!(push_type(ty, &vec_type, "Builtin")),
+                };
+            }
+        }
+
+        // An optional i64 seed
+        BuiltinKind::Rand | BuiltinKind::RandSeeded => {
+            changed |= try!(push_complete_type(ty, Scalar(F64), "Builtin"));
+            for arg in args.iter_mut() {
+                changed |= try!(push_complete_type(&mut arg.ty, Scalar(I64), "Builtin"));
+            }
+        }
+
+        // A conversion between numeric types
+        BuiltinKind::Cast(target) => {
+            changed |= try!(push_complete_type(ty, Scalar(target), "Builtin"));
+            match args[0].ty {
+                Unknown | Scalar(I32) | Scalar(I64) | Scalar(F32) | Scalar(F64) |
+                    Scalar(Decimal(_, _)) => (),
+                ref other => return weld_err!("Cannot cast {} to {}", print_type(other), target)
+            }
+        }
+
+        // Conversions to and from timestamps, and calendar functions on them
+        _ => {
+            let (arg_kinds, result_kind) = match kind {
+                BuiltinKind::ToTimestamp => (vec![I64], Timestamp),
+                BuiltinKind::ToMicros => (vec![Timestamp], I64),
+                BuiltinKind::Extract(_) => (vec![Timestamp], I32),
+                BuiltinKind::Trunc(_) => (vec![Timestamp], Timestamp),
+                BuiltinKind::AddInterval => (vec![Timestamp, I64], Timestamp),
+                _ => (vec![Timestamp, Timestamp], I64),
+            };
+            changed |= try!(push_complete_type(ty, Scalar(result_kind), "Builtin"));
+            for (arg, arg_kind) in args.iter_mut().zip(arg_kinds) {
+                changed |= try!(push_complete_type(&mut arg.ty, Scalar(arg_kind), "Builtin"));
+            }
+        }
+    }
+    Ok(changed)
+}
+
+/// Check that an arithmetic or logical operator can be applied to values of the given type.
+/// Other invalid combinations, such as bitwise operators on floats, are reported by codegen.
+fn check_arithmetic(op: BinOpKind, ty: &PartialType) -> WeldResult<()> {
+    match *ty {
+        Scalar(Timestamp) => weld_err!("Timestamps only support comparisons; use add_interval \
+            and interval_between for arithmetic"),
+        Scalar(Decimal(precision, _)) => match op {
+            BinOpKind::Add | BinOpKind::Subtract => Ok(()),
+            BinOpKind::Multiply | BinOpKind::Divide if precision <= MAX_DECIMAL64_PRECISION =>
+                Ok(()),
+            BinOpKind::Multiply | BinOpKind::Divide => weld_err!("Multiplying and dividing \
+                decimals with more than {} digits is not supported yet",
+                MAX_DECIMAL64_PRECISION),
+            _ => weld_err!("Operator {} is not supported on decimals", op)
+        },
+        _ => Ok(())
     }
 }
 
@@ -368,10 +515,44 @@ fn push_type(dest: &mut PartialType, src: &PartialType, context: &str) -> WeldRe
             _ => weld_err!("Mismatched types in {}", context)
         },
 
+        Builder(MutVec(ref mut dest_elem)) => match *src {
+            Builder(MutVec(ref src_elem)) =>
+                push_type(dest_elem.as_mut(), src_elem.as_ref(), context),
+            _ => weld_err!("Mismatched types in {}", context)
+        },
+
+        Builder(StatsMerger(ref mut dest_elem)) => match *src {
+            Builder(StatsMerger(ref src_elem)) =>
+                push_type(dest_elem.as_mut(), src_elem.as_ref(), context),
+            _ => weld_err!("Mismatched types in {}", context)
+        },
+
+        Builder(Histogram(ref mut dest_elem)) => match *src {
+            Builder(Histogram(ref src_elem)) =>
+                push_type(dest_elem.as_mut(), src_elem.as_ref(), context),
+            _ => weld_err!("Mismatched types in {}", context)
+        },
+
         _ => weld_err!("Internal error: push_type not implemented for {:?}", dest)
     }
 }
 
+/// Force the merge type of a builder to be at least as specific as `src`, which should have the
+/// shape returned by `merge_type`. Return a Result indicating whether the builder has changed.
+fn push_merge_type(bldr: &mut PartialBuilderKind, src: &PartialType, context: &str)
+        -> WeldResult<bool> {
+    match *bldr {
+        Appender(ref mut elem) => push_type(elem, src, context),
+        Merger(ref mut elem, _) => push_type(elem, src, context),
+        MutVec(ref mut elem) => match *src {
+            Struct(ref fields) if fields.len() == 2 => push_type(elem, &fields[1], context),
+            _ => weld_err!("Mismatched types in {}", context)
+        },
+        StatsMerger(ref mut elem) => push_type(elem, src, context),
+        Histogram(ref mut elem) => push_type(elem, src, context),
+    }
+}
+
 /// Force two types to be equal, calling `push_type` in each direction. Return true if any type
 /// has changed in this process or an error if the types cannot be made to match.
 fn sync_types(t1: &mut PartialType, t2: &mut PartialType, error: &str) -> WeldResult<bool> {
@@ -415,6 +596,12 @@ fn infer_types_simple() {
     let mut e = *fprod.clone();
     assert!(infer_types(&mut e).is_ok());
     assert_eq!(e.ty, Scalar(F32));
+
+    for op in &[LessThan, LessThanOrEqual, GreaterThan, GreaterThanOrEqual] {
+        let mut e = *expr_box(BinOp(*op, int_lit.clone(), int_lit.clone()));
+        assert!(infer_types(&mut e).is_ok());
+        assert_eq!(e.ty, Scalar(Bool));
+    }
 }
 
 #[test]
@@ -450,5 +637,100 @@ fn infer_types_let() {
     assert!(infer_types(&mut e).is_err());
 
     let mut e = parse_expr("let a = 1; a:bool").unwrap();
+    assert_eq!(infer_types(&mut e).unwrap_err().kind(), ErrorKind::Type);
+}
+
+#[test]
+fn infer_types_mutvec() {
+    let mut e = parse_expr("result(for([1,2],mutvec([0,0]),|b,x|merge(b,{0L,x})))").unwrap();
+    assert!(infer_types(&mut e).is_ok());
+    assert_eq!(e.ty, Vector(Box::new(Scalar(I32))));
+
+    let mut e = parse_expr("mutvec[f32]([1,2])").unwrap();
+    assert!(infer_types(&mut e).is_err());
+
+    // Merges must be {index, value} pairs with an i64 index.
+    let mut e = parse_expr("for([1,2],mutvec([0,0]),|b,x|merge(b,{0,x}))").unwrap();
+    assert!(infer_types(&mut e).is_err());
+}
+
+#[test]
+fn infer_types_statsmerger() {
+    let mut e = parse_expr("result(for([1.0,2.0],statsmerger,|b,x|merge(b,x)))").unwrap();
+    assert!(infer_types(&mut e).is_ok());
+    assert_eq!(e.ty, Struct(vec![Scalar(I64), Scalar(F64), Scalar(F64), Scalar(F64), Scalar(F64)]));
+
+    let mut e = parse_expr("for([true],statsmerger,|b,x|merge(b,x))").unwrap();
+    assert!(infer_types(&mut e).is_err());
+
+    let mut e = parse_expr("for([1,2],statsmerger[i64],|b,x|merge(b,x))").unwrap();
+    assert!(infer_types(&mut e).is_err());
+}
+
+#[test]
+fn infer_types_histogram() {
+    let mut e = parse_expr("result(for([1,5],histogram(0,10,5L),|b,x|merge(b,x)))").unwrap();
+    assert!(infer_types(&mut e).is_ok());
+    assert_eq!(e.ty, Vector(Box::new(Scalar(I64))));
+
+    // The bounds must have the element type, and the number of bins must be an i64
+    let mut e = parse_expr("for([1,5],histogram(0.0,10.0,5L),|b,x|merge(b,x))").unwrap();
+    assert!(infer_types(&mut e).is_err());
+    let mut e = parse_expr("histogram[f32](0.0f,1.0f,5)").unwrap();
+    assert!(infer_types(&mut e).is_err());
+    let mut e = parse_expr("histogram(0.0,1.0)").unwrap();
+    assert!(infer_types(&mut e).is_err());
+}
+
+#[test]
+fn infer_types_builtins() {
+    let mut e = parse_expr("|a:vec[f32]| dot(a, a)").unwrap();
+    assert!(infer_types(&mut e).is_ok());
+    assert_eq!(print_typed_expr(&e), "|a:vec[f32]|dot(a:vec[f32],a:vec[f32])");
+
+    // The empty vector's type comes from the other argument
+    let mut e = parse_expr("|a:vec[f64]| gemm(a, [], 1L, 1L, 1L)").unwrap();
+    assert!(infer_types(&mut e).is_ok());
+    assert_eq!(e.ty, Function(vec![Vector(Box::new(Scalar(F64)))],
+        Box::new(Vector(Box::new(Scalar(F64))))));
+
+    let mut e = parse_expr("dot([1, 2], [3, 4])").unwrap();
+    assert!(infer_types(&mut e).is_err());
+    let mut e = parse_expr("|a:vec[f64]| gemm(a, a, 1, 1, 1)").unwrap();
+    assert!(infer_types(&mut e).is_err());
+    let mut e = parse_expr("|a:vec[f64]| dot(a)").unwrap();
+    assert!(infer_types(&mut e).is_err());
+
+    let mut e = parse_expr("rand() + rand_seeded(1L)").unwrap();
+    assert!(infer_types(&mut e).is_ok());
+    assert_eq!(e.ty, Scalar(F64));
+    let mut e = parse_expr("rand_seeded(1)").unwrap();
+    assert!(infer_types(&mut e).is_err());
+
+    let mut e = parse_expr("|t:timestamp| {extract_year(t), interval_between(t, trunc_hour(t))}")
+        .unwrap();
+    assert!(infer_types(&mut e).is_ok());
+    assert_eq!(e.ty, Function(vec![Scalar(Timestamp)],
+        Box::new(Struct(vec![Scalar(I32), Scalar(I64)]))));
+    let mut e = parse_expr("|t:timestamp| t < add_interval(t, 1L)").unwrap();
+    assert!(infer_types(&mut e).is_ok());
+    let mut e = parse_expr("|t:timestamp| t + t").unwrap();
+    assert!(infer_types(&mut e).is_err());
+    let mut e = parse_expr("to_timestamp(1)").unwrap();
+    assert!(infer_types(&mut e).is_err());
+
+    let mut e = parse_expr("|x:i32| let d = decimal[10,2](x); f64(d * d - decimal[10,2](0.5))")
+        .unwrap();
+    assert!(infer_types(&mut e).is_ok());
+    assert_eq!(e.ty, Function(vec![Scalar(I32)], Box::new(Scalar(F64))));
+    let mut e = parse_expr("|d:decimal[10,2]| d + decimal[10,3](1)").unwrap();
+    assert!(infer_types(&mut e).is_err());
+    let mut e = parse_expr("|d:decimal[10,2]| d % d").unwrap();
+    assert!(infer_types(&mut e).is_err());
+    let mut e = parse_expr("|d:decimal[20,2]| d - d").unwrap();
+    assert!(infer_types(&mut e).is_ok());
+    let mut e = parse_expr("|d:decimal[20,2]| d * d").unwrap();
+    assert!(infer_types(&mut e).is_err());
+    let mut e = parse_expr("i64(true)").unwrap();
     assert!(infer_types(&mut e).is_err());
 }
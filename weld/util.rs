@@ -1,23 +1,28 @@
+use std::cell::Cell;
 use std::cmp::max;
 use std::collections::HashMap;
+use std::panic;
+use std::thread;
 
 use super::ast::*;
 use super::ast::ExprKind::*;
+use super::error::*;
+use super::intern::Name;
 
 /// Utility struct that can track and generate unique IDs and symbols for use in an expression.
 /// Each SymbolGenerator tracks the maximum ID used for every symbol name, and can be used to
 /// create new symbols with the same name but a unique ID.
 pub struct SymbolGenerator {
-    id_map: HashMap<String, i32>
+    id_map: HashMap<Name, i32>
 }
 
 impl SymbolGenerator {
     /// Initialize a SymbolGenerator from all the symbols defined in an expression.
     pub fn from_expression<T:Clone>(expr: &Expr<T>) -> SymbolGenerator {
-        let mut id_map: HashMap<String, i32> = HashMap::new();
+        let mut id_map: HashMap<Name, i32> = HashMap::new();
 
-        let update_id = |id_map: &mut HashMap<String, i32>, symbol: &Symbol| {
-            let id = id_map.entry(symbol.name.clone()).or_insert(0);
+        let update_id = |id_map: &mut HashMap<Name, i32>, symbol: &Symbol| {
+            let id = id_map.entry(symbol.name).or_insert(0);
             *id = max(*id, symbol.id);
         };
 
@@ -37,10 +42,10 @@ impl SymbolGenerator {
         SymbolGenerator { id_map: id_map }
     }
 
-    pub fn new_symbol(&mut self, name: &String) -> Symbol {
-        let id = self.id_map.entry(name.clone()).or_insert(-1);
+    pub fn new_symbol(&mut self, name: Name) -> Symbol {
+        let id = self.id_map.entry(name).or_insert(-1);
         *id += 1;
-        Symbol { name: name.clone(), id: *id }
+        Symbol { name: name, id: *id }
     }
 }
 
@@ -62,4 +67,71 @@ impl IdGenerator {
         self.next_id += 1;
         res
     }
-}
\ No newline at end of file
+}
+
+/// The deepest expression tree that the compiler accepts. Most passes over expressions are
+/// recursive, so this bounds how much stack they need; see `with_compile_stack`.
+pub const MAX_EXPR_DEPTH: usize = 20000;
+
+/// Stack size for the threads that compile programs, which is enough for expressions up to
+/// `MAX_EXPR_DEPTH` deep even in debug builds. Only the pages that get used are allocated.
+pub const COMPILE_STACK_SIZE: usize = 512 << 20;
+
+/// The deepest expression tree that is compiled on the caller's own stack. A level of nesting
+/// can take over 10 KiB of stack in debug builds, so this leaves room to spare in the 2 MiB that
+/// Rust gives new threads, while most programs still never need a thread of their own.
+pub const CALLER_STACK_DEPTH: usize = 50;
+
+thread_local! {
+    /// Whether the current thread was started by `with_compile_stack`.
+    static ON_COMPILE_STACK: Cell<bool> = Cell::new(false);
+}
+
+/// Check that an expression is no deeper than `MAX_EXPR_DEPTH`, and return its depth.
+pub fn check_depth<T:Clone>(expr: &Expr<T>) -> WeldResult<usize> {
+    let depth = expr.depth();
+    if depth > MAX_EXPR_DEPTH {
+        return weld_err!(Parse, "Program is nested too deeply: its expression tree has depth {}, \
+            but at most {} is supported", depth, MAX_EXPR_DEPTH);
+    }
+    Ok(depth)
+}
+
+/// Run `func` on the current thread if recursing over expressions `depth` deep fits on its
+/// stack, and with `with_compile_stack` otherwise.
+pub fn with_stack_for_depth<R, F>(depth: usize, func: F) -> WeldResult<R>
+        where R: Send, F: FnOnce() -> WeldResult<R> + Send {
+    if depth <= CALLER_STACK_DEPTH {
+        func()
+    } else {
+        with_compile_stack(func)
+    }
+}
+
+/// Run `func` on a new thread with a `COMPILE_STACK_SIZE` stack and return its result, so that
+/// recursive passes over deeply nested programs do not overflow the caller's stack. If the
+/// current thread already has such a stack, `func` runs on it directly.
+pub fn with_compile_stack<R, F>(func: F) -> WeldResult<R>
+        where R: Send, F: FnOnce() -> WeldResult<R> + Send {
+    if ON_COMPILE_STACK.with(|on_stack| on_stack.get()) {
+        return func();
+    }
+    thread::scope(|scope| {
+        let spawned = thread::Builder::new()
+            .name("weld-compile".to_string())
+            .stack_size(COMPILE_STACK_SIZE)
+            .spawn_scoped(scope, || {
+                ON_COMPILE_STACK.with(|on_stack| on_stack.set(true));
+                func()
+            });
+        let handle = match spawned {
+            Ok(handle) => handle,
+            Err(e) => return weld_err!("Could not start a thread to compile a deeply nested \
+                program: {}", e)
+        };
+        match handle.join() {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload)
+        }
+    })
+}
@@ -0,0 +1,308 @@
+//! C API for embedding Weld in non-Rust systems. The declarations here are mirrored in
+//! `include/weld.h`; all handles are opaque pointers owned by the caller, who must release them
+//! with the matching `_free` function.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+use super::conf::*;
+use super::error::*;
+use super::llvm;
+use super::llvm::WeldModule;
+
+extern "C" {
+    fn free(ptr: *mut c_void);
+}
+
+/// A Weld value, wrapping a pointer to data laid out as the corresponding Weld type.
+pub struct WeldValue {
+    data: *const c_void,
+    /// Whether `data` was allocated by Weld (and should be freed along with the value).
+    owned: bool,
+}
+
+/// An error slot that API calls fill in; a code of 0 means success.
+pub struct WeldErrorInfo {
+    code: i32,
+    message: CString,
+    /// The ID of the run that failed, or -1.
+    run_id: i64,
+}
+
+impl WeldErrorInfo {
+    fn clear(&mut self) {
+        self.code = 0;
+        self.message = CString::new("Success").unwrap();
+        self.run_id = -1;
+    }
+
+    fn set(&mut self, err: &WeldError) {
+        self.code = error_code(err.kind());
+        self.run_id = err.run_id().unwrap_or(-1);
+        // Messages come from format strings and should not contain NULs, but strip them if so.
+        let message = err.to_string().replace("\0", "");
+        self.message = CString::new(message).unwrap();
+    }
+}
+
+/// Return the C error code for a kind of error; these are defined in `include/weld.h`.
+fn error_code(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::Parse => 1,
+        ErrorKind::Macro => 2,
+        ErrorKind::Type => 3,
+        ErrorKind::Codegen => 4,
+        ErrorKind::Runtime => 5,
+        ErrorKind::Config => 6,
+        ErrorKind::Internal => 7,
+    }
+}
+
+/// Create a new error slot.
+#[no_mangle]
+pub extern "C" fn weld_error_new() -> *mut WeldErrorInfo {
+    let mut err = WeldErrorInfo { code: 0, message: CString::default(), run_id: -1 };
+    err.clear();
+    Box::into_raw(Box::new(err))
+}
+
+/// Return the error code stored in an error slot (0 for success, otherwise one of the
+/// `WELD_*_ERROR` codes).
+#[no_mangle]
+pub unsafe extern "C" fn weld_error_code(err: *const WeldErrorInfo) -> i32 {
+    (*err).code
+}
+
+/// Return the message stored in an error slot. The string is owned by the slot.
+#[no_mangle]
+pub unsafe extern "C" fn weld_error_message(err: *const WeldErrorInfo) -> *const c_char {
+    (*err).message.as_ptr()
+}
+
+/// Return the ID of the run an error occurred in, or -1 if it did not come from running a module.
+#[no_mangle]
+pub unsafe extern "C" fn weld_error_run_id(err: *const WeldErrorInfo) -> i64 {
+    (*err).run_id
+}
+
+/// Free an error slot.
+#[no_mangle]
+pub unsafe extern "C" fn weld_error_free(err: *mut WeldErrorInfo) {
+    if !err.is_null() {
+        drop(Box::from_raw(err));
+    }
+}
+
+/// Create an empty configuration.
+#[no_mangle]
+pub extern "C" fn weld_conf_new() -> *mut WeldConf {
+    Box::into_raw(Box::new(WeldConf::new()))
+}
+
+/// Set a configuration key. Keys and values are copied.
+#[no_mangle]
+pub unsafe extern "C" fn weld_conf_set(conf: *mut WeldConf, key: *const c_char,
+        value: *const c_char) {
+    let key = CStr::from_ptr(key).to_string_lossy().into_owned();
+    let value = CStr::from_ptr(value).to_string_lossy().into_owned();
+    (*conf).set(key, value);
+}
+
+/// Return the value of a configuration key, or NULL if it is not set. The returned string is
+/// newly allocated and must be released with `weld_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn weld_conf_get(conf: *const WeldConf, key: *const c_char)
+        -> *mut c_char {
+    let key = CStr::from_ptr(key).to_string_lossy();
+    match (*conf).get(&key) {
+        Some(value) => CString::new(value.replace("\0", "")).unwrap().into_raw(),
+        None => ptr::null_mut()
+    }
+}
+
+/// Free a string returned by the Weld API.
+#[no_mangle]
+pub unsafe extern "C" fn weld_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Free a configuration.
+#[no_mangle]
+pub unsafe extern "C" fn weld_conf_free(conf: *mut WeldConf) {
+    if !conf.is_null() {
+        drop(Box::from_raw(conf));
+    }
+}
+
+/// Wrap caller-owned data in a value; freeing the value does not free the data.
+#[no_mangle]
+pub extern "C" fn weld_value_new(data: *const c_void) -> *mut WeldValue {
+    Box::into_raw(Box::new(WeldValue { data: data, owned: false }))
+}
+
+/// Return the data pointer of a value.
+#[no_mangle]
+pub unsafe extern "C" fn weld_value_data(value: *const WeldValue) -> *const c_void {
+    (*value).data
+}
+
+/// Free a value, along with its data if it was produced by `weld_module_run`.
+#[no_mangle]
+pub unsafe extern "C" fn weld_value_free(value: *mut WeldValue) {
+    if value.is_null() {
+        return;
+    }
+    let value = Box::from_raw(value);
+    if value.owned {
+        free(value.data as *mut c_void);
+    }
+}
+
+/// Return the configuration behind a handle, or `default` if the handle is NULL.
+unsafe fn conf_or(conf: *const WeldConf, default: &WeldConf) -> &WeldConf {
+    if conf.is_null() { default } else { &*conf }
+}
+
+/// Compile a Weld program whose body is a function, with the default configuration if `conf` is
+/// NULL. Returns NULL and fills `err` on failure.
+#[no_mangle]
+pub unsafe extern "C" fn weld_module_compile(
+    code: *const c_char,
+    conf: *const WeldConf,
+    err: *mut WeldErrorInfo
+) -> *mut WeldModule {
+    (*err).clear();
+    let default = WeldConf::new();
+    let conf = conf_or(conf, &default);
+    let result = CStr::from_ptr(code).to_str()
+        .map_err(|_| WeldError::with_message(ErrorKind::Parse,
+            "Program is not valid UTF-8".to_string()))
+        .and_then(|code| llvm::compile_source(code, conf));
+    match result {
+        Ok(module) => Box::into_raw(Box::new(module)),
+        Err(ref e) => {
+            (*err).set(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Run a module on an argument value (pointing to a struct of its parameters), with the default
+/// configuration if `conf` is NULL. The returned value owns the result data and must be freed
+/// with `weld_value_free`. Returns NULL and fills `err` if the configuration is invalid or asks
+/// for something runs do not support yet (see `RunConf::check_supported`).
+#[no_mangle]
+pub unsafe extern "C" fn weld_module_run(
+    module: *const WeldModule,
+    conf: *const WeldConf,
+    arg: *const WeldValue,
+    err: *mut WeldErrorInfo
+) -> *mut WeldValue {
+    (*err).clear();
+    let run_id = llvm::new_run_id();
+    let default = WeldConf::new();
+    let run_conf = RunConf::parse(conf_or(conf, &default));
+    if let Err(e) = run_conf.and_then(|run_conf| run_conf.check_supported()) {
+        (*err).set(&e.with_run_id(run_id));
+        return ptr::null_mut();
+    }
+    let result = (*module).run((*arg).data as i64);
+    Box::into_raw(Box::new(WeldValue { data: result as *const c_void, owned: true }))
+}
+
+/// Free a compiled module.
+#[no_mangle]
+pub unsafe extern "C" fn weld_module_free(module: *mut WeldModule) {
+    if !module.is_null() {
+        drop(Box::from_raw(module));
+    }
+}
+
+#[test]
+fn capi_values() {
+    let input: i32 = 42;
+    unsafe {
+        let value = weld_value_new(&input as *const i32 as *const c_void);
+        assert_eq!(*(weld_value_data(value) as *const i32), 42);
+        weld_value_free(value);
+    }
+}
+
+#[test]
+fn capi_compile_error() {
+    let code = CString::new("|x:i32| x +").unwrap();
+    unsafe {
+        let err = weld_error_new();
+        let conf = weld_conf_new();
+        assert_eq!(weld_error_code(err), 0);
+        let module = weld_module_compile(code.as_ptr(), conf, err);
+        assert!(module.is_null());
+        assert_eq!(weld_error_code(err), 1);
+        assert_eq!(weld_error_run_id(err), -1);
+        let message = CStr::from_ptr(weld_error_message(err)).to_str().unwrap();
+        assert!(message.contains("Expected expression"));
+
+        let code = CString::new("|x:i32| x + 1.0").unwrap();
+        let module = weld_module_compile(code.as_ptr(), conf, err);
+        assert!(module.is_null());
+        assert_eq!(weld_error_code(err), 3);
+
+        // A NULL configuration stands for the default one
+        let module = weld_module_compile(code.as_ptr(), ptr::null(), err);
+        assert!(module.is_null());
+        assert_eq!(weld_error_code(err), 3);
+        weld_conf_free(conf);
+        weld_error_free(err);
+    }
+}
+
+#[test]
+fn capi_run() {
+    if !llvm::jit_available() {
+        eprintln!("Skipping: LLVM rejects the generated code");
+        return;
+    }
+    let code = CString::new("|x:i32| x + 1").unwrap();
+    let input: i32 = 41;
+    unsafe {
+        let err = weld_error_new();
+        let module = weld_module_compile(code.as_ptr(), ptr::null(), err);
+        assert_eq!(weld_error_code(err), 0);
+        let arg = weld_value_new(&input as *const i32 as *const c_void);
+        let result = weld_module_run(module, ptr::null(), arg, err);
+        assert_eq!(weld_error_code(err), 0);
+        assert_eq!(*(weld_value_data(result) as *const i32), 42);
+        weld_value_free(result);
+
+        // Settings that runs cannot follow are rejected rather than ignored
+        let conf = weld_conf_new();
+        let key = CString::new(THREADS_KEY).unwrap();
+        let value = CString::new("4").unwrap();
+        weld_conf_set(conf, key.as_ptr(), value.as_ptr());
+        assert!(weld_module_run(module, conf, arg, err).is_null());
+        assert_eq!(weld_error_code(err), 6);
+        assert!(weld_error_run_id(err) >= 0);
+        weld_conf_free(conf);
+        weld_value_free(arg);
+        weld_module_free(module);
+        weld_error_free(err);
+    }
+}
+
+#[test]
+fn capi_conf() {
+    let key = CString::new(THREADS_KEY).unwrap();
+    let value = CString::new("4").unwrap();
+    unsafe {
+        let conf = weld_conf_new();
+        assert!(weld_conf_get(conf, key.as_ptr()).is_null());
+        weld_conf_set(conf, key.as_ptr(), value.as_ptr());
+        let result = weld_conf_get(conf, key.as_ptr());
+        assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "4");
+        weld_string_free(result);
+        weld_conf_free(conf);
+    }
+}
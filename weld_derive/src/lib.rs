@@ -0,0 +1,82 @@
+//! `#[derive(WeldAbi)]` for Rust structs passed to or returned from Weld modules.
+//!
+//! The derived implementation of `weld::abi::WeldAbi` builds the struct's Weld type from the
+//! types of its fields and reports the actual offset of each field, so that
+//! `WeldAbi::check_layout` can catch mismatches with the layout used by generated code.
+//! Structs must be `#[repr(C)]`, since Rust may otherwise reorder their fields.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use] extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use syn::{Data, DeriveInput, Error, Fields};
+
+#[proc_macro_derive(WeldAbi)]
+pub fn derive_weld_abi(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match weld_abi_impl(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Generate the `WeldAbi` impl for a struct, or an error if it cannot be mapped to Weld.
+fn weld_abi_impl(input: &DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "WeldAbi cannot be derived for generic types"));
+    }
+    if !is_repr_c(input) {
+        return Err(Error::new(Span::call_site(), "WeldAbi requires #[repr(C)]"));
+    }
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => return Err(Error::new(Span::call_site(), "WeldAbi can only be derived for structs")),
+    };
+
+    let types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+    let accessors: Vec<_> = match *fields {
+        Fields::Named(ref named) => named.named.iter()
+            .map(|f| { let ident = &f.ident; quote!(#ident) })
+            .collect(),
+        Fields::Unnamed(ref unnamed) => (0..unnamed.unnamed.len())
+            .map(|i| { let index = syn::Index::from(i); quote!(#index) })
+            .collect(),
+        Fields::Unit => vec![],
+    };
+
+    Ok(quote! {
+        unsafe impl ::weld::abi::WeldAbi for #name {
+            fn weld_type() -> ::weld::ast::Type {
+                ::weld::ast::Type::Struct(vec![
+                    #( <#types as ::weld::abi::WeldAbi>::weld_type() ),*
+                ])
+            }
+
+            #[allow(unused_variables)]
+            fn field_offsets() -> Vec<usize> {
+                // All WeldAbi types are plain data, for which the all-zero pattern is valid.
+                let value: #name = unsafe { ::std::mem::zeroed() };
+                let base = &value as *const #name as usize;
+                vec![ #( &value.#accessors as *const _ as usize - base ),* ]
+            }
+        }
+    })
+}
+
+/// Does the input carry a `#[repr(C)]` attribute (possibly alongside other representations)?
+fn is_repr_c(input: &DeriveInput) -> bool {
+    let mut repr_c = false;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("repr")) {
+        let _ = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("C") {
+                repr_c = true;
+            }
+            Ok(())
+        });
+    }
+    repr_c
+}
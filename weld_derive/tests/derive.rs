@@ -0,0 +1,39 @@
+extern crate weld;
+#[macro_use] extern crate weld_derive;
+
+use weld::abi::WeldAbi;
+
+#[repr(C)]
+#[derive(Clone, Copy, WeldAbi)]
+struct Point {
+    x: f64,
+    y: f64,
+    valid: bool,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, WeldAbi)]
+struct Args {
+    count: i32,
+    origin: Point,
+    scale: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, WeldAbi)]
+struct Pair(i32, i64);
+
+#[test]
+fn derived_types() {
+    assert_eq!(Point::weld_type_string(), "{f64,f64,bool}");
+    assert_eq!(Args::weld_type_string(), "{i32,{f64,f64,bool},i64}");
+    assert_eq!(Pair::weld_type_string(), "{i32,i64}");
+}
+
+#[test]
+fn derived_layouts() {
+    assert!(Point::check_layout().is_ok());
+    assert!(Args::check_layout().is_ok());
+    assert!(Pair::check_layout().is_ok());
+    assert_eq!(Args::field_offsets(), vec![0, 8, 32]);
+}